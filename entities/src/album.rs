@@ -9,6 +9,11 @@ pub struct Album {
     pub year: i32,
     pub song_count: i32,
     pub artist_id: Uuid,
+    pub art_source: String,
+    pub art_path: Option<String>,
+    // The MusicBrainz release, reissues of an album are different ones
+    pub musicbrainz_id: Option<String>,
+    pub musicbrainz_release_group_id: Option<String>,
 }
 
 #[derive(FromRow, PartialEq, Eq, Hash, Clone, Debug, Serialize)]
@@ -19,4 +24,9 @@ pub struct AlbumSqlxModel {
     pub song_count: i32,
     pub year: i32,
     pub artist_name: String,
+    pub art_source: String,
+    pub art_path: Option<String>,
+    // The MusicBrainz release, reissues of an album are different ones
+    pub musicbrainz_id: Option<String>,
+    pub musicbrainz_release_group_id: Option<String>,
 }
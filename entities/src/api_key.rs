@@ -0,0 +1,12 @@
+use sqlx::{types::chrono::NaiveDateTime, FromRow};
+use uuid::Uuid;
+
+#[derive(FromRow, Clone, Debug)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    // blake3 of the key, the key itself is only shown once when it's created
+    pub key_hash: String,
+    pub created: NaiveDateTime,
+}
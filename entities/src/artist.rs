@@ -9,10 +9,20 @@ pub struct Artist {
     pub id: Uuid,
     pub name: String,
     pub album_count: i32,
+    pub musicbrainz_id: Option<String>,
 }
 #[derive(FromRow, PartialEq, Eq, Hash, Clone, Debug, Serialize)]
 pub struct ArtistSqlxModel {
     pub album_count: i32,
     pub id: Uuid,
     pub name: String,
+    pub musicbrainz_id: Option<String>,
+}
+
+/// One of the artists of a song, in the order the tag lists them.
+#[derive(Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct SongArtist {
+    pub song_id: Uuid,
+    pub artist_id: Uuid,
+    pub name: String,
 }
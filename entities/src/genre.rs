@@ -0,0 +1,15 @@
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct Genre {
+    pub id: Uuid,
+    pub name: String,
+}
+
+/// A genre of a song, by name.
+#[derive(Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct SongGenre {
+    pub song_id: Uuid,
+    pub name: String,
+}
@@ -1,8 +1,13 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.11
 
 pub mod album;
+pub mod api_key;
 pub mod artist;
+pub mod genre;
+pub mod lyrics;
+pub mod music_folder;
 pub mod playlist;
 pub mod return_id;
+pub mod scan_error;
 pub mod song;
 pub mod user;
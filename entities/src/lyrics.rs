@@ -0,0 +1,29 @@
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Lyrics of a song in one language, the lines are in `lyrics_line`.
+#[derive(Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct Lyrics {
+    pub id: Uuid,
+    pub song_id: Uuid,
+    // ISO 639-2 code, `xxx` when unknown
+    pub lang: String,
+    pub synced: bool,
+    pub position: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct LyricsLine {
+    pub lyrics_id: Uuid,
+    // Milliseconds into the song, for synced lyrics
+    pub start: Option<i32>,
+    pub value: String,
+}
+
+/// Lyrics as read from a file, before they belong to a stored song.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SongLyrics {
+    pub lang: String,
+    pub synced: bool,
+    pub lines: Vec<LyricsLine>,
+}
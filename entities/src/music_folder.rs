@@ -0,0 +1,8 @@
+use sqlx::FromRow;
+
+#[derive(Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct MusicFolder {
+    pub id: i32,
+    pub name: String,
+    pub path: String,
+}
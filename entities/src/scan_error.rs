@@ -0,0 +1,14 @@
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct ScanError {
+    pub id: Uuid,
+    pub path: String,
+    // Where in the scan it went wrong: walk, probe, tag or insert
+    pub stage: String,
+    pub message: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
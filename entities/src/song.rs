@@ -2,13 +2,15 @@
 
 use serde::Serialize;
 use sqlx::prelude::FromRow;
+use sqlx::types::chrono::{DateTime, Utc};
 use uuid::Uuid;
 
-#[derive(Clone, Debug, PartialEq, Eq, FromRow, Hash)]
+#[derive(Clone, Debug, PartialEq, FromRow)]
 pub struct Song {
     pub id: Uuid,
     pub title: String,
     pub path: String,
+    // The first of its genres, `song_genre` has them all
     pub genre: String,
     pub suffix: String,
     pub content_type: String,
@@ -16,9 +18,31 @@ pub struct Song {
     pub duration: i32,
     pub album_id: Uuid,
     pub disc_number: i32,
+    pub art_source: String,
+    pub art_path: Option<String>,
+    // File size in bytes, modification time and blake3 hash of the contents when last parsed
+    pub size: i64,
+    pub mtime: Option<DateTime<Utc>>,
+    pub hash: Option<String>,
+    pub music_folder_id: Option<i32>,
+    // Who performs the song, the album's artist can be someone else, or Various Artists. The first
+    // of its artists when there are several, `song_artist` has them all
+    pub artist_id: Uuid,
+    // The MusicBrainz recording
+    pub musicbrainz_id: Option<String>,
+    // ReplayGain, in dB for the gains and as a fraction of full scale for the peaks
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+    // Average bitrate in kbps, sample rate in Hz, bits per sample (0 for lossy codecs) and channels
+    pub bit_rate: i32,
+    pub sample_rate: i32,
+    pub bit_depth: i32,
+    pub channels: i32,
 }
 
-#[derive(FromRow, PartialEq, Eq, Hash, Clone, Debug, Serialize)]
+#[derive(FromRow, PartialEq, Clone, Debug, Serialize)]
 pub struct SongSqlxModel {
     pub title: String,
     pub duration: i32,
@@ -34,4 +58,15 @@ pub struct SongSqlxModel {
     pub artist_name: String,
     pub year: i32,
     pub artist_id: Uuid,
+    pub art_source: String,
+    pub musicbrainz_id: Option<String>,
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+    pub size: i64,
+    pub bit_rate: i32,
+    pub sample_rate: i32,
+    pub bit_depth: i32,
+    pub channels: i32,
 }
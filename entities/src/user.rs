@@ -7,4 +7,17 @@ pub struct User {
     pub id: Uuid,
     pub username: String,
     pub password: String,
+    pub email: Option<String>,
+    pub admin_role: bool,
+    pub settings_role: bool,
+    pub download_role: bool,
+    pub upload_role: bool,
+    pub playlist_role: bool,
+    pub cover_art_role: bool,
+    pub comment_role: bool,
+    pub podcast_role: bool,
+    pub share_role: bool,
+    pub stream_role: bool,
+    // In kbps, 0 means no limit
+    pub max_bit_rate: i32,
 }
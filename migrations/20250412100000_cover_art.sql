@@ -0,0 +1,9 @@
+-- Where the artwork for a song or album comes from: 'embedded' (art_path is the audio file),
+-- 'folder' (art_path is an image next to the audio files) or 'none'
+alter table public.song
+    add column art_source varchar default 'none' not null,
+    add column art_path   varchar;
+
+alter table public.album
+    add column art_source varchar default 'none' not null,
+    add column art_path   varchar;
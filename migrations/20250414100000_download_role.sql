@@ -0,0 +1,2 @@
+alter table public."user"
+    add column download_role boolean default true not null;
@@ -0,0 +1,15 @@
+-- OpenSubsonic API keys, only the blake3 hash of the key is stored
+create table public.api_key
+(
+    id       uuid default gen_random_uuid() not null
+        primary key,
+    user_id  uuid                           not null
+        constraint fk_api_key_user
+            references public."user"
+            on delete cascade,
+    name     varchar                        not null,
+    key_hash varchar                        not null
+        constraint api_key_key_hash_unique
+            unique,
+    created  timestamp default now()        not null
+);
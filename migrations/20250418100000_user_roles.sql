@@ -0,0 +1,46 @@
+-- Subsonic user roles, new users get the same defaults as in Subsonic
+alter table public."user"
+    add column email         varchar,
+    add column admin_role    boolean default false not null,
+    add column settings_role boolean default true  not null,
+    add column upload_role   boolean default false not null,
+    add column playlist_role boolean default false not null,
+    add column cover_art_role boolean default false not null,
+    add column comment_role  boolean default false not null,
+    add column podcast_role  boolean default false not null,
+    add column share_role    boolean default false not null,
+    add column stream_role   boolean default true  not null,
+    add column max_bit_rate  integer default 0     not null;
+
+-- Users created by hand so far had access to everything, keep it that way
+update public."user"
+set admin_role     = true,
+    settings_role  = true,
+    download_role  = true,
+    upload_role    = true,
+    playlist_role  = true,
+    cover_art_role = true,
+    comment_role   = true,
+    podcast_role   = true,
+    share_role     = true,
+    stream_role    = true;
+
+-- Usernames weren't unique before and users were added by hand, so there may be two of a name.
+-- Which one to keep is for whoever made them to decide, stop and say which names they are
+do
+$$
+    declare
+        duplicates text;
+    begin
+        select string_agg(username, ', ' order by username)
+        into duplicates
+        from (select username from public."user" group by username having count(*) > 1) as d;
+        if duplicates is not null then
+            raise exception 'Usernames must be unique now, but these belong to several users: %. '
+                'Rename or delete all but one of each, then start again', duplicates;
+        end if;
+    end
+$$;
+
+alter table public."user"
+    add constraint user_username_unique unique (username);
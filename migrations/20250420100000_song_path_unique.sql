@@ -0,0 +1,22 @@
+-- A full rescan updates songs in place by path instead of inserting them again. The old scanner
+-- inserted them again on every scan, so first keep one row per path and point the playlist entries
+-- of the others at it
+create temporary table song_duplicate as
+select song.id, kept.id as kept_id
+from song
+         inner join (select distinct on (path) id, path from song order by path, id) as kept
+                    on kept.path = song.path and kept.id <> song.id;
+
+update playlist_items
+set song_id = song_duplicate.kept_id
+from song_duplicate
+where playlist_items.song_id = song_duplicate.id;
+
+delete
+from song using song_duplicate
+where song.id = song_duplicate.id;
+
+drop table song_duplicate;
+
+alter table public.song
+    add constraint song_path_unique unique (path);
@@ -0,0 +1,6 @@
+-- What the file looked like when it was last parsed, so rescans can skip unchanged files.
+-- Existing rows have no mtime yet and get parsed again once on the next scan.
+alter table public.song
+    add column size  bigint default 0 not null,
+    add column mtime timestamp with time zone,
+    add column hash  varchar;
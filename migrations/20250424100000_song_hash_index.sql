@@ -0,0 +1,2 @@
+-- Moved files are found by their hash
+create index song_hash on song (hash);
@@ -0,0 +1,19 @@
+-- Named music folders from the configuration, each song belongs to the one it was found in.
+-- Rows are kept in step with the configuration, songs of a folder that's gone lose their folder
+-- until the next scan removes them.
+create table public.music_folder
+(
+    id   serial  not null
+        primary key,
+    name varchar not null,
+    path varchar not null
+        constraint music_folder_path unique
+);
+
+alter table public.song
+    add column music_folder_id integer
+        constraint "fk-song-music_folder_id"
+            references public.music_folder
+            on delete set null;
+
+create index song_music_folder_id on song (music_folder_id);
@@ -0,0 +1,13 @@
+-- Problems the scanner ran into, one row per file and stage of the scan where it went wrong.
+-- Rows go away once the file scans fine, or when a scan of the whole library doesn't see them again.
+create table public.scan_error
+(
+    id         uuid                     default gen_random_uuid() not null
+        primary key,
+    path       varchar                                            not null,
+    stage      varchar                                            not null,
+    message    varchar                                            not null,
+    first_seen timestamp with time zone default now()             not null,
+    last_seen  timestamp with time zone default now()             not null,
+    constraint scan_error_path_stage unique (path, stage)
+);
@@ -0,0 +1,16 @@
+-- Songs get an artist of their own, the performer, next to the album artist. Existing songs start
+-- out with their album's artist until their tags are read again.
+alter table public.song
+    add column artist_id uuid
+        constraint "fk-song-artist_id"
+            references public.artist;
+
+update public.song
+set artist_id = album.artist_id
+from public.album
+where album.id = song.album_id;
+
+alter table public.song
+    alter column artist_id set not null;
+
+create index song_artist_id on song (artist_id);
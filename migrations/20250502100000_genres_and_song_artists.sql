@@ -0,0 +1,75 @@
+-- Songs can have several artists and genres, and albums the genres of their songs. `song.artist_id`
+-- and `song.genre` keep the first of each. Existing songs start out with the one artist and genre
+-- they have until their tags are read again.
+create table public.genre
+(
+    id   uuid default gen_random_uuid() not null
+        primary key,
+    name varchar                        not null
+        constraint genre_name unique
+);
+
+create table public.song_artist
+(
+    song_id   uuid    not null
+        constraint "fk-song_artist-song_id"
+            references public.song
+            on delete cascade,
+    artist_id uuid    not null
+        constraint "fk-song_artist-artist_id"
+            references public.artist
+            on delete cascade,
+    -- Order the artists are listed in on the tag
+    position  integer not null,
+    primary key (song_id, artist_id)
+);
+
+create index song_artist_artist_id on song_artist (artist_id);
+
+create table public.song_genre
+(
+    song_id  uuid    not null
+        constraint "fk-song_genre-song_id"
+            references public.song
+            on delete cascade,
+    genre_id uuid    not null
+        constraint "fk-song_genre-genre_id"
+            references public.genre
+            on delete cascade,
+    position integer not null,
+    primary key (song_id, genre_id)
+);
+
+create index song_genre_genre_id on song_genre (genre_id);
+
+create table public.album_genre
+(
+    album_id uuid not null
+        constraint "fk-album_genre-album_id"
+            references public.album
+            on delete cascade,
+    genre_id uuid not null
+        constraint "fk-album_genre-genre_id"
+            references public.genre
+            on delete cascade,
+    primary key (album_id, genre_id)
+);
+
+insert into song_artist (song_id, artist_id, position)
+select id, artist_id, 0
+from song;
+
+insert into genre (name)
+select distinct genre
+from song
+where genre <> '';
+
+insert into song_genre (song_id, genre_id, position)
+select song.id, genre.id, 0
+from song
+         inner join genre on genre.name = song.genre;
+
+insert into album_genre (album_id, genre_id)
+select distinct song.album_id, song_genre.genre_id
+from song
+         inner join song_genre on song_genre.song_id = song.id;
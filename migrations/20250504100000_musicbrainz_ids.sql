@@ -0,0 +1,15 @@
+-- MusicBrainz identifiers from the tags. Artists and albums that have one are told apart by it
+-- rather than by name.
+alter table public.artist
+    add column musicbrainz_id varchar;
+
+create unique index artist_musicbrainz_id on artist (musicbrainz_id);
+
+alter table public.album
+    add column musicbrainz_id               varchar,
+    add column musicbrainz_release_group_id varchar;
+
+create index album_musicbrainz_id on album (musicbrainz_id);
+
+alter table public.song
+    add column musicbrainz_id varchar;
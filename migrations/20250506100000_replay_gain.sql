@@ -0,0 +1,7 @@
+-- ReplayGain from the tags, gains in dB and peaks as a fraction of full scale. Null means the tag
+-- doesn't have it, or hasn't been read since.
+alter table public.song
+    add column track_gain double precision,
+    add column track_peak double precision,
+    add column album_gain double precision,
+    add column album_peak double precision;
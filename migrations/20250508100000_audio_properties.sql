@@ -0,0 +1,8 @@
+-- What the audio stream says about itself: average bitrate in kbps, sample rate in Hz, bits per
+-- sample (0 for lossy codecs) and channel count. Existing songs have zeros, and the content type
+-- their extension gave them, until they're read again.
+alter table public.song
+    add column bit_rate    integer not null default 0,
+    add column sample_rate integer not null default 0,
+    add column bit_depth   integer not null default 0,
+    add column channels    integer not null default 0;
@@ -0,0 +1,30 @@
+-- Lyrics from the tags and from `.lrc` and `.txt` files next to the songs, one set per language
+-- and kind. Lines of synced lyrics have the time they start at.
+create table public.lyrics
+(
+    id       uuid default gen_random_uuid() not null
+        primary key,
+    song_id  uuid                           not null
+        constraint "fk-lyrics-song_id"
+            references public.song
+            on delete cascade,
+    -- ISO 639-2 code, `xxx` when unknown
+    lang     varchar                        not null,
+    synced   boolean                        not null,
+    -- Order they were found in, embedded ones first
+    position integer                        not null,
+    constraint lyrics_song_id_position unique (song_id, position)
+);
+
+create table public.lyrics_line
+(
+    lyrics_id uuid    not null
+        constraint "fk-lyrics_line-lyrics_id"
+            references public.lyrics
+            on delete cascade,
+    position  integer not null,
+    -- Milliseconds into the song, null for lyrics that aren't synced
+    start     integer,
+    value     varchar not null,
+    primary key (lyrics_id, position)
+);
@@ -0,0 +1,6 @@
+-- Song hashes now cover only the audio, not the tags around it, so retagging a file that's also
+-- moved doesn't lose it. This is the one forced re-read of the library: the next scan hashes every
+-- song again, which also fills in the song artists, genres, MusicBrainz IDs, ReplayGain, audio
+-- properties and lyrics that the migrations before this one only made room for.
+update song
+set mtime = null;
@@ -0,0 +1,3 @@
+-- getCoverArt now needs the cover art role, new users keep seeing covers
+alter table public."user"
+    alter column cover_art_role set default true;
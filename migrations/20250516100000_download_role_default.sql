@@ -0,0 +1,3 @@
+-- Subsonic doesn't let new users download, as `new_user()` has it. Existing users keep theirs
+alter table public."user"
+    alter column download_role set default false;
@@ -1,20 +1,48 @@
-use entities::{album::Album, artist::Artist, song::Song, user::User};
+use entities::{
+    album::Album,
+    api_key::ApiKey,
+    artist::{Artist, SongArtist},
+    genre::{Genre, SongGenre},
+    lyrics::{Lyrics, LyricsLine, SongLyrics},
+    music_folder::MusicFolder,
+    playlist::Playlist,
+    scan_error::ScanError,
+    song::Song,
+    song::SongSqlxModel,
+    user::User,
+};
 use log::error;
-use sqlx::{Pool, Postgres, types::Uuid};
-pub struct SongPath {
-    path: String,
+use sqlx::{
+    PgConnection, Pool, Postgres,
+    types::Uuid,
+    types::chrono::{DateTime, Utc},
+};
+// Key of the advisory lock held while a scan runs, whichever process runs it
+const SCAN_LOCK_KEY: i64 = 0x736f_6e69_6363_6176;
+
+/// What a song's file looked like when it was last parsed.
+pub struct SongFileState {
+    pub path: String,
+    pub size: i64,
+    pub mtime: Option<DateTime<Utc>>,
 }
 
+/// Albums by name, only those with songs in `music_folder_id` when given.
 pub async fn get_albums(
     pool: &Pool<Postgres>,
     limit: i32,
     offset: i32,
+    music_folder_id: Option<i32>,
 ) -> Result<Vec<Album>, sqlx::Error> {
     sqlx::query_as!(
         Album,
-        "select * from album order by name limit $1 offset $2",
+        r#"select * from album
+        where $3::int is null
+            or exists (select 1 from song where song.album_id = album.id and song.music_folder_id = $3)
+        order by name limit $1 offset $2"#,
         i64::try_from(limit).unwrap(),
-        i64::try_from(offset).unwrap()
+        i64::try_from(offset).unwrap(),
+        music_folder_id
     )
     .fetch_all(pool)
     .await
@@ -22,11 +50,50 @@ pub async fn get_albums(
 pub async fn get_random_albums(
     pool: &Pool<Postgres>,
     limit: i32,
+    music_folder_id: Option<i32>,
 ) -> Result<Vec<Album>, sqlx::Error> {
     sqlx::query_as!(
         Album,
-        "select * from album order by RANDOM() limit $1",
-        i64::try_from(limit).unwrap()
+        r#"select * from album
+        where $2::int is null
+            or exists (select 1 from song where song.album_id = album.id and song.music_folder_id = $2)
+        order by RANDOM() limit $1"#,
+        i64::try_from(limit).unwrap(),
+        music_folder_id
+    )
+    .fetch_all(pool)
+    .await
+}
+/// Random songs, narrowed down by whichever of genre, year range and folder are given.
+pub async fn get_random_songs(
+    pool: &Pool<Postgres>,
+    limit: i32,
+    genre: Option<&str>,
+    from_year: Option<i32>,
+    to_year: Option<i32>,
+    music_folder_id: Option<i32>,
+) -> Result<Vec<SongSqlxModel>, sqlx::Error> {
+    sqlx::query_as!(
+        SongSqlxModel,
+        r#"select song.id, song.title,song.path,song.genre,song.suffix,song.content_type,song.track,
+        song.duration, song.album_id, song.disc_number, song.art_source,
+         album.name as album_name, artist.name as artist_name, album.year, artist.id as artist_id,
+         song.musicbrainz_id, song.track_gain, song.track_peak, song.album_gain, song.album_peak,
+         song.size, song.bit_rate, song.sample_rate, song.bit_depth, song.channels
+        from song inner join album on song.album_id = album.id
+                  inner join artist on song.artist_id = artist.id
+        where ($2::text is null or exists (
+                select 1 from song_genre inner join genre on genre.id = song_genre.genre_id
+                where song_genre.song_id = song.id and genre.name ilike $2))
+            and ($3::int is null or album.year >= $3)
+            and ($4::int is null or album.year <= $4)
+            and ($5::int is null or song.music_folder_id = $5)
+        order by RANDOM() limit $1"#,
+        i64::try_from(limit).unwrap(),
+        genre,
+        from_year,
+        to_year,
+        music_folder_id
     )
     .fetch_all(pool)
     .await
@@ -40,13 +107,184 @@ pub async fn get_artists_by_id(
         .await
 }
 
-pub async fn get_song_paths(pool: &Pool<Postgres>) -> Result<Vec<String>, sqlx::Error> {
-    let ret: Result<Vec<SongPath>, sqlx::Error> =
-        sqlx::query_as!(SongPath, "select path from song;")
-            .fetch_all(pool)
-            .await;
-    let res: Vec<String> = ret?.into_iter().map(|s| s.path).collect();
-    Ok(res)
+pub async fn get_song_file_states(
+    pool: &Pool<Postgres>,
+) -> Result<Vec<SongFileState>, sqlx::Error> {
+    sqlx::query_as!(SongFileState, "select path, size, mtime from song;")
+        .fetch_all(pool)
+        .await
+}
+
+pub struct SongHash {
+    pub id: Uuid,
+    pub path: String,
+    pub hash: String,
+}
+
+pub async fn get_songs_by_hashes(
+    conn: &mut PgConnection,
+    hashes: &[String],
+) -> Result<Vec<SongHash>, sqlx::Error> {
+    sqlx::query_as!(
+        SongHash,
+        r#"select id, path, hash as "hash!" from song where hash = ANY($1)"#,
+        hashes
+    )
+    .fetch_all(&mut *conn)
+    .await
+}
+
+pub async fn update_song_path(
+    conn: &mut PgConnection,
+    id: Uuid,
+    path: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("update song set path = $2 where id = $1", id, path)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_music_folders(pool: &Pool<Postgres>) -> Result<Vec<MusicFolder>, sqlx::Error> {
+    sqlx::query_as!(MusicFolder, "select * from music_folder order by id")
+        .fetch_all(pool)
+        .await
+}
+
+/// Makes the music folder rows match the configured ones, matched up by path so ids stay the
+/// same across restarts, and puts every song under the folder its path is in.
+pub async fn sync_music_folders(
+    pool: &Pool<Postgres>,
+    names: &[String],
+    paths: &[String],
+) -> Result<Vec<MusicFolder>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!("delete from music_folder where not (path = ANY($1))", paths)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query!(
+        r#"
+insert into music_folder (name, path)
+select * from UNNEST($1::text[], $2::text[])
+on conflict (path) do update set name = excluded.name
+        "#,
+        names,
+        paths
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+update song set music_folder_id = music_folder.id
+from music_folder
+where starts_with(song.path, music_folder.path || '/')
+    and song.music_folder_id is distinct from music_folder.id
+        "#
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    get_music_folders(pool).await
+}
+
+/// Records scan problems, bumping `last_seen` of the ones already known.
+pub async fn add_scan_errors(
+    pool: &Pool<Postgres>,
+    paths: &[String],
+    stages: &[String],
+    messages: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+insert into scan_error (path, stage, message)
+select * from UNNEST($1::text[], $2::text[], $3::text[])
+on conflict (path, stage) do update set message = excluded.message, last_seen = now()
+        "#,
+        paths,
+        stages,
+        messages
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Forgets the problems of files that scanned fine.
+pub async fn clear_scan_errors(
+    conn: &mut PgConnection,
+    paths: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("delete from scan_error where path = ANY($1)", paths)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Forgets the problems a scan of the whole library started at `since` didn't run into again.
+pub async fn clear_scan_errors_before(
+    pool: &Pool<Postgres>,
+    since: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    Ok(
+        sqlx::query!("delete from scan_error where last_seen < $1", since)
+            .execute(pool)
+            .await?
+            .rows_affected(),
+    )
+}
+
+/// Scan problems, the most recent first, only those of `stage` when given.
+pub async fn get_scan_errors(
+    pool: &Pool<Postgres>,
+    stage: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<ScanError>, sqlx::Error> {
+    sqlx::query_as!(
+        ScanError,
+        r#"select * from scan_error
+        where $1::text is null or stage = $1
+        order by last_seen desc, path
+        limit $2 offset $3"#,
+        stage,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn count_scan_errors(
+    pool: &Pool<Postgres>,
+    stage: Option<&str>,
+) -> Result<i64, sqlx::Error> {
+    let ret = sqlx::query!(
+        r#"select count(*) as "count!" from scan_error where $1::text is null or stage = $1"#,
+        stage
+    )
+    .fetch_one(pool)
+    .await;
+    Ok(ret?.count)
+}
+
+/// Takes the scan lock on a connection of its own, None if another session holds it. The lock
+/// goes away with the connection, which is closed when dropped instead of going back to the pool.
+pub async fn try_lock_scan(pool: &Pool<Postgres>) -> Result<Option<PgConnection>, sqlx::Error> {
+    let mut connection = pool.acquire().await?.detach();
+    let ret = sqlx::query!(
+        r#"select pg_try_advisory_lock($1) as "locked!""#,
+        SCAN_LOCK_KEY
+    )
+    .fetch_one(&mut connection)
+    .await?;
+    Ok(ret.locked.then_some(connection))
+}
+
+pub async fn count_songs(pool: &Pool<Postgres>) -> Result<i64, sqlx::Error> {
+    let ret = sqlx::query!(r#"select count(*) as "count!" from song"#)
+        .fetch_one(pool)
+        .await;
+    Ok(ret?.count)
 }
 
 pub async fn search_artists_paginated(
@@ -70,10 +308,13 @@ pub async fn search_artists_paginated(
     if !vec.is_empty() { Some(vec) } else { None }
 }
 
-pub async fn get_albums_by_artist_id(pool: &Pool<Postgres>, artist_id: Uuid) -> Option<Vec<Album>> {
+pub async fn get_albums_by_artist_id(
+    conn: &mut PgConnection,
+    artist_id: Uuid,
+) -> Option<Vec<Album>> {
     let ret: Result<Vec<Album>, sqlx::Error> =
         sqlx::query_as!(Album, "select * from album where artist_id = $1", artist_id)
-            .fetch_all(pool)
+            .fetch_all(&mut *conn)
             .await;
     if let Err(e) = ret {
         error!("There was an error querying the database: {}", e);
@@ -83,6 +324,22 @@ pub async fn get_albums_by_artist_id(pool: &Pool<Postgres>, artist_id: Uuid) ->
     if !vec.is_empty() { Some(vec) } else { None }
 }
 
+/// Albums the artist is the album artist of, or performs songs on.
+pub async fn get_albums_with_artist(
+    pool: &Pool<Postgres>,
+    artist_id: Uuid,
+) -> Result<Vec<Album>, sqlx::Error> {
+    sqlx::query_as!(
+        Album,
+        r#"select * from album
+        where artist_id = $1 or id in (select album_id from song where artist_id = $1)
+        order by year, name"#,
+        artist_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
 pub async fn get_songs_by_album_id(pool: &Pool<Postgres>, album_id: Uuid) -> Option<Vec<Song>> {
     let ret: Result<Vec<Song>, sqlx::Error> = sqlx::query_as!(
         Song,
@@ -106,10 +363,23 @@ pub async fn delete_song_by_id(pool: &Pool<Postgres>, song_id: Uuid) -> Result<(
     ret?;
     Ok(())
 }
-pub async fn get_all_artists(pool: &Pool<Postgres>) -> Result<Vec<Artist>, sqlx::Error> {
-    sqlx::query_as!(Artist, "select * from artist")
-        .fetch_all(pool)
-        .await
+/// Every artist, or only those with songs in `music_folder_id` when given.
+pub async fn get_all_artists(
+    pool: &Pool<Postgres>,
+    music_folder_id: Option<i32>,
+) -> Result<Vec<Artist>, sqlx::Error> {
+    sqlx::query_as!(
+        Artist,
+        r#"select * from artist
+        where $1::int is null or exists (
+            select 1 from album inner join song on song.album_id = album.id
+            where (album.artist_id = artist.id or song.artist_id = artist.id)
+                and song.music_folder_id = $1
+        )"#,
+        music_folder_id
+    )
+    .fetch_all(pool)
+    .await
 }
 pub async fn get_user_by_username(
     pool: &Pool<Postgres>,
@@ -124,6 +394,165 @@ pub async fn get_user_by_username(
     .await
 }
 
+pub async fn get_users(pool: &Pool<Postgres>) -> Result<Vec<User>, sqlx::Error> {
+    sqlx::query_as!(User, r#"select * from "user" order by username"#)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn add_user(pool: &Pool<Postgres>, user: &User) -> Result<Uuid, sqlx::Error> {
+    let ret = sqlx::query_as!(
+        ReturnId,
+        r#"insert into "user" (username, password, email, admin_role, settings_role, download_role,
+        upload_role, playlist_role, cover_art_role, comment_role, podcast_role, share_role,
+        stream_role, max_bit_rate)
+        values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14) returning id"#,
+        user.username,
+        user.password,
+        user.email,
+        user.admin_role,
+        user.settings_role,
+        user.download_role,
+        user.upload_role,
+        user.playlist_role,
+        user.cover_art_role,
+        user.comment_role,
+        user.podcast_role,
+        user.share_role,
+        user.stream_role,
+        user.max_bit_rate
+    )
+    .fetch_one(pool)
+    .await;
+    Ok(ret?.id)
+}
+
+/// Saves everything but the password, which goes through `update_user_password`.
+pub async fn update_user(conn: &mut PgConnection, user: &User) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"update "user" set email = $2, admin_role = $3, settings_role = $4, download_role = $5,
+        upload_role = $6, playlist_role = $7, cover_art_role = $8, comment_role = $9,
+        podcast_role = $10, share_role = $11, stream_role = $12, max_bit_rate = $13
+        where id = $1"#,
+        user.id,
+        user.email,
+        user.admin_role,
+        user.settings_role,
+        user.download_role,
+        user.upload_role,
+        user.playlist_role,
+        user.cover_art_role,
+        user.comment_role,
+        user.podcast_role,
+        user.share_role,
+        user.stream_role,
+        user.max_bit_rate
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn update_user_password(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    password: &String,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"update "user" set password = $2 where id = $1"#,
+        user_id,
+        password
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn update_user_passwords(
+    pool: &Pool<Postgres>,
+    user_ids: &[Uuid],
+    passwords: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"update "user" set password = new.password
+        from unnest($1::uuid[], $2::text[]) as new(id, password)
+        where "user".id = new.id"#,
+        user_ids,
+        passwords
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_user_by_id(pool: &Pool<Postgres>, user_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(r#"delete from "user" where id = $1"#, user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_user_by_api_key_hash(
+    pool: &Pool<Postgres>,
+    key_hash: &String,
+) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as!(
+        User,
+        r#"select "user".* from "user" inner join api_key on api_key.user_id = "user".id
+        where api_key.key_hash = $1"#,
+        key_hash
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn add_api_key(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    name: &String,
+    key_hash: &String,
+) -> Result<Uuid, sqlx::Error> {
+    let ret = sqlx::query_as!(
+        ReturnId,
+        "insert into api_key (user_id, name, key_hash) values ($1, $2, $3) returning id",
+        user_id,
+        name,
+        key_hash
+    )
+    .fetch_one(pool)
+    .await;
+    Ok(ret?.id)
+}
+
+pub async fn get_api_keys_by_user_id(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+) -> Result<Vec<ApiKey>, sqlx::Error> {
+    sqlx::query_as!(
+        ApiKey,
+        "select * from api_key where user_id = $1 order by created",
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Removes a user's key, returning whether there was one with that ID.
+pub async fn delete_api_key(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    key_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let ret = sqlx::query!(
+        "delete from api_key where id = $1 and user_id = $2",
+        key_id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(ret.rows_affected() > 0)
+}
+
 pub async fn delete_album_by_id(pool: &Pool<Postgres>, album_id: Uuid) -> Result<(), sqlx::Error> {
     let ret = sqlx::query!("delete from song where album_id = $1", album_id)
         .execute(pool)
@@ -145,6 +574,41 @@ pub async fn get_song_by_id(
         .await
 }
 
+pub async fn get_playlists(pool: &Pool<Postgres>) -> Result<Vec<Playlist>, sqlx::Error> {
+    sqlx::query_as!(Playlist, "select * from playlists order by name")
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn get_playlist_by_id(
+    pool: &Pool<Postgres>,
+    playlist_id: Uuid,
+) -> Result<Option<Playlist>, sqlx::Error> {
+    sqlx::query_as!(
+        Playlist,
+        "select * from playlists where id = $1",
+        playlist_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn get_songs_by_playlist_id(
+    pool: &Pool<Postgres>,
+    playlist_id: Uuid,
+) -> Result<Vec<Song>, sqlx::Error> {
+    sqlx::query_as!(
+        Song,
+        r#"select song.* from song
+        inner join playlist_items on playlist_items.song_id = song.id
+        where playlist_items.playlist_id = $1
+        order by playlist_items.item"#,
+        playlist_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
 pub async fn get_album_by_id(
     pool: &Pool<Postgres>,
     album_id: Uuid,
@@ -162,14 +626,6 @@ pub async fn get_artist_by_id(
         .fetch_optional(pool)
         .await
 }
-pub async fn get_artist_by_name(
-    pool: &Pool<Postgres>,
-    artist_name: &String,
-) -> Result<Option<Artist>, sqlx::Error> {
-    sqlx::query_as!(Artist, "select * from artist where name = $1", artist_name)
-        .fetch_optional(pool)
-        .await
-}
 pub async fn delete_artist_by_id(
     pool: &Pool<Postgres>,
     artist_id: Uuid,
@@ -185,7 +641,9 @@ pub async fn delete_artist_by_id(
 pub struct ReturnId {
     pub id: Uuid,
 }
-pub async fn prune_songs(pool: &Pool<Postgres>, paths: &Vec<String>) -> Result<(), sqlx::Error> {
+/// Deletes the songs at `paths` and whatever albums, artists and genres that leaves empty. Returns
+/// how many songs were deleted.
+pub async fn prune_songs(pool: &Pool<Postgres>, paths: &Vec<String>) -> Result<u64, sqlx::Error> {
     let mut ret = sqlx::query!(
         "delete from playlist_items where song_id in (select id from song where path = ANY($1))",
         paths
@@ -193,42 +651,445 @@ pub async fn prune_songs(pool: &Pool<Postgres>, paths: &Vec<String>) -> Result<(
     .execute(pool)
     .await;
     ret?;
-    ret = sqlx::query!("delete from song where path =ANY($1)", paths)
+    let deleted = sqlx::query!("delete from song where path =ANY($1)", paths)
         .execute(pool)
-        .await;
-    ret?;
+        .await?
+        .rows_affected();
     ret = sqlx::query!("delete from album where id not in (select distinct album_id from song)")
         .execute(pool)
         .await;
     ret?;
-    ret = sqlx::query!("delete from artist where id not in (select distinct artist_id from album)")
-        .execute(pool)
-        .await;
+    ret = sqlx::query!(
+        r#"delete from artist
+        where id not in (select distinct artist_id from album)
+            and id not in (select distinct artist_id from song)
+            and id not in (select distinct artist_id from song_artist)"#
+    )
+    .execute(pool)
+    .await;
     ret?;
+    prune_genres(pool).await?;
+    Ok(deleted)
+}
+
+/// Drops album genres none of the album's songs have anymore, and genres nothing has.
+pub async fn prune_genres(pool: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"delete from album_genre
+        where not exists (
+            select 1 from song inner join song_genre on song_genre.song_id = song.id
+            where song.album_id = album_genre.album_id and song_genre.genre_id = album_genre.genre_id)"#
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query!(
+        r#"delete from genre
+        where id not in (select distinct genre_id from song_genre)
+            and id not in (select distinct genre_id from album_genre)"#
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Replaces the artists of the songs at `paths`, `artist_ids` holds each song's in order.
+pub async fn set_song_artists(
+    conn: &mut PgConnection,
+    paths: &[String],
+    artist_ids: &[Vec<Uuid>],
+) -> Result<(), sqlx::Error> {
+    let mut link_path: Vec<String> = Vec::new();
+    let mut link_artist: Vec<Uuid> = Vec::new();
+    let mut position: Vec<i32> = Vec::new();
+    for (path, ids) in paths.iter().zip(artist_ids) {
+        for (i, id) in ids.iter().enumerate() {
+            link_path.push(path.to_owned());
+            link_artist.push(*id);
+            position.push(i as i32);
+        }
+    }
+    sqlx::query!(
+        "delete from song_artist using song where song.id = song_artist.song_id and song.path = ANY($1)",
+        paths
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"
+insert into song_artist (song_id, artist_id, position)
+select song.id, new.artist_id, new.position
+from UNNEST($1::text[], $2::uuid[], $3::int[]) as new (path, artist_id, position)
+    inner join song on song.path = new.path
+on conflict do nothing
+        "#,
+        &link_path[..],
+        &link_artist[..],
+        &position[..]
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Replaces the genres of the songs at `paths`, creating the genres that don't exist yet, and
+/// brings the genres of their albums up to date.
+pub async fn set_song_genres(
+    conn: &mut PgConnection,
+    paths: &[String],
+    genres: &[&[String]],
+) -> Result<(), sqlx::Error> {
+    let mut link_path: Vec<String> = Vec::new();
+    let mut link_genre: Vec<String> = Vec::new();
+    let mut position: Vec<i32> = Vec::new();
+    for (path, names) in paths.iter().zip(genres) {
+        for (i, name) in names.iter().enumerate() {
+            link_path.push(path.to_owned());
+            link_genre.push(name.to_owned());
+            position.push(i as i32);
+        }
+    }
+    sqlx::query!(
+        "insert into genre (name) select distinct UNNEST($1::text[]) on conflict (name) do nothing",
+        &link_genre[..]
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "delete from song_genre using song where song.id = song_genre.song_id and song.path = ANY($1)",
+        paths
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"
+insert into song_genre (song_id, genre_id, position)
+select song.id, genre.id, new.position
+from UNNEST($1::text[], $2::text[], $3::int[]) as new (path, name, position)
+    inner join song on song.path = new.path
+    inner join genre on genre.name = new.name
+on conflict do nothing
+        "#,
+        &link_path[..],
+        &link_genre[..],
+        &position[..]
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"
+insert into album_genre (album_id, genre_id)
+select distinct song.album_id, song_genre.genre_id
+from song inner join song_genre on song_genre.song_id = song.id
+where song.path = ANY($1)
+on conflict do nothing
+        "#,
+        paths
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// The artists of these songs, in tag order.
+pub async fn get_song_artists(
+    pool: &Pool<Postgres>,
+    song_ids: &[Uuid],
+) -> Result<Vec<SongArtist>, sqlx::Error> {
+    sqlx::query_as!(
+        SongArtist,
+        r#"select song_artist.song_id, artist.id as artist_id, artist.name
+        from song_artist inner join artist on artist.id = song_artist.artist_id
+        where song_artist.song_id = ANY($1)
+        order by song_artist.position"#,
+        song_ids
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// The genres of these songs, in tag order.
+pub async fn get_song_genres(
+    pool: &Pool<Postgres>,
+    song_ids: &[Uuid],
+) -> Result<Vec<SongGenre>, sqlx::Error> {
+    sqlx::query_as!(
+        SongGenre,
+        r#"select song_genre.song_id, genre.name
+        from song_genre inner join genre on genre.id = song_genre.genre_id
+        where song_genre.song_id = ANY($1)
+        order by song_genre.position"#,
+        song_ids
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_album_genres(
+    pool: &Pool<Postgres>,
+    album_id: Uuid,
+) -> Result<Vec<Genre>, sqlx::Error> {
+    sqlx::query_as!(
+        Genre,
+        r#"select genre.* from album_genre inner join genre on genre.id = album_genre.genre_id
+        where album_genre.album_id = $1
+        order by genre.name"#,
+        album_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Replaces the lyrics of the songs at `paths`, `lyrics` has them for each path in turn.
+pub async fn set_song_lyrics(
+    conn: &mut PgConnection,
+    paths: &[String],
+    lyrics: &[&[SongLyrics]],
+) -> Result<(), sqlx::Error> {
+    let mut lyrics_path: Vec<String> = Vec::new();
+    let mut lang: Vec<String> = Vec::new();
+    let mut synced: Vec<bool> = Vec::new();
+    let mut position: Vec<i32> = Vec::new();
+    let mut line_path: Vec<String> = Vec::new();
+    let mut line_lyrics: Vec<i32> = Vec::new();
+    let mut line_position: Vec<i32> = Vec::new();
+    let mut start: Vec<Option<i32>> = Vec::new();
+    let mut value: Vec<String> = Vec::new();
+    for (path, song_lyrics) in paths.iter().zip(lyrics) {
+        for (i, l) in song_lyrics.iter().enumerate() {
+            lyrics_path.push(path.to_owned());
+            lang.push(l.lang.to_owned());
+            synced.push(l.synced);
+            position.push(i as i32);
+            for (j, line) in l.lines.iter().enumerate() {
+                line_path.push(path.to_owned());
+                line_lyrics.push(i as i32);
+                line_position.push(j as i32);
+                start.push(line.start);
+                value.push(line.value.to_owned());
+            }
+        }
+    }
+    sqlx::query!(
+        "delete from lyrics using song where song.id = lyrics.song_id and song.path = ANY($1)",
+        paths
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"
+insert into lyrics (song_id, lang, synced, position)
+select song.id, new.lang, new.synced, new.position
+from UNNEST($1::text[], $2::text[], $3::bool[], $4::int[]) as new (path, lang, synced, position)
+    inner join song on song.path = new.path
+        "#,
+        &lyrics_path[..],
+        &lang[..],
+        &synced[..],
+        &position[..]
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"
+insert into lyrics_line (lyrics_id, position, start, value)
+select lyrics.id, new.position, new.start, new.value
+from UNNEST($1::text[], $2::int[], $3::int[], $4::int[], $5::text[])
+        as new (path, lyrics_position, position, start, value)
+    inner join song on song.path = new.path
+    inner join lyrics on lyrics.song_id = song.id and lyrics.position = new.lyrics_position
+        "#,
+        &line_path[..],
+        &line_lyrics[..],
+        &line_position[..],
+        &start[..] as &[Option<i32>],
+        &value[..]
+    )
+    .execute(&mut *conn)
+    .await?;
     Ok(())
 }
-pub async fn add_artist(pool: &Pool<Postgres>, artist: &Artist) -> Result<Uuid, sqlx::Error> {
+
+pub async fn get_lyrics_by_song_id(
+    pool: &Pool<Postgres>,
+    song_id: Uuid,
+) -> Result<Vec<Lyrics>, sqlx::Error> {
+    sqlx::query_as!(
+        Lyrics,
+        "select * from lyrics where song_id = $1 order by position",
+        song_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_lyrics_lines(
+    pool: &Pool<Postgres>,
+    lyrics_ids: &[Uuid],
+) -> Result<Vec<LyricsLine>, sqlx::Error> {
+    sqlx::query_as!(
+        LyricsLine,
+        r#"select lyrics_id, start, value from lyrics_line
+        where lyrics_id = ANY($1)
+        order by position"#,
+        lyrics_ids
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// The best match among the songs with lyrics for an artist and title, either may be left out.
+pub async fn find_song_with_lyrics(
+    pool: &Pool<Postgres>,
+    artist: Option<&str>,
+    title: Option<&str>,
+) -> Result<Option<Song>, sqlx::Error> {
+    sqlx::query_as!(
+        Song,
+        r#"select song.* from song
+        where exists (select 1 from lyrics where lyrics.song_id = song.id)
+            and ($1::text is null or exists (
+                select 1 from song_artist inner join artist on artist.id = song_artist.artist_id
+                where song_artist.song_id = song.id and artist.name ilike $1)
+                or exists (select 1 from artist where artist.id = song.artist_id and artist.name ilike $1))
+            and ($2::text is null or song.title ilike $2)
+        order by song.path
+        limit 1"#,
+        artist,
+        title
+    )
+    .fetch_optional(pool)
+    .await
+}
+pub async fn add_artist(conn: &mut PgConnection, artist: &Artist) -> Result<Uuid, sqlx::Error> {
     let ret = sqlx::query_as! {
         ReturnId,
-        "insert into artist (name, album_count) values ($1, $2) returning id",
+        "insert into artist (name, album_count, musicbrainz_id) values ($1, $2, $3) returning id",
         artist.name,
-        artist.album_count
+        artist.album_count,
+        artist.musicbrainz_id
     }
-    .fetch_one(pool)
+    .fetch_one(&mut *conn)
     .await;
     Ok(ret?.id)
 }
+/// The artist a tag names. With a MusicBrainz ID that's the artist with the same one, or one with
+/// the same name and no ID yet. Without, it's any artist with the same name, preferably one without
+/// an ID.
+pub async fn find_artist(
+    conn: &mut PgConnection,
+    name: &str,
+    musicbrainz_id: Option<&str>,
+) -> Result<Option<Artist>, sqlx::Error> {
+    sqlx::query_as!(
+        Artist,
+        r#"select * from artist
+        where case when $2::text is null then name = $1
+            else musicbrainz_id = $2 or (name = $1 and musicbrainz_id is null) end
+        order by musicbrainz_id is not distinct from $2 desc, musicbrainz_id is null desc
+        limit 1"#,
+        name,
+        musicbrainz_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+}
+
+/// Gives an artist found by name the MusicBrainz ID it didn't have.
+pub async fn set_artist_musicbrainz_id(
+    conn: &mut PgConnection,
+    artist_id: Uuid,
+    musicbrainz_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "update artist set musicbrainz_id = $2 where id = $1 and musicbrainz_id is null",
+        artist_id,
+        musicbrainz_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Finds the artists tags name, creating the ones that don't exist yet, along the lines of
+/// `find_artist`. Returns every artist with one of the names or IDs, callers pick theirs out.
+pub async fn get_or_add_artists(
+    conn: &mut PgConnection,
+    artists: &[Artist],
+) -> Result<Vec<Artist>, sqlx::Error> {
+    let names: Vec<String> = artists.iter().map(|a| a.name.to_owned()).collect();
+    let musicbrainz_ids: Vec<Option<String>> = artists
+        .iter()
+        .map(|a| a.musicbrainz_id.to_owned())
+        .collect();
+    sqlx::query!(
+        r#"
+update artist set musicbrainz_id = new.musicbrainz_id
+from UNNEST($1::text[], $2::text[]) as new (name, musicbrainz_id)
+where new.musicbrainz_id is not null
+    and artist.id = (select id from artist a where a.name = new.name and a.musicbrainz_id is null limit 1)
+    and not exists (select 1 from artist a where a.musicbrainz_id = new.musicbrainz_id)
+        "#,
+        &names[..],
+        &musicbrainz_ids[..] as &[Option<String>]
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"
+insert into artist (name, album_count, musicbrainz_id)
+select distinct new.name, 0, new.musicbrainz_id
+from UNNEST($1::text[], $2::text[]) as new (name, musicbrainz_id)
+where not exists (
+    select 1 from artist
+    where case when new.musicbrainz_id is null then artist.name = new.name
+        else artist.musicbrainz_id = new.musicbrainz_id end)
+        "#,
+        &names[..],
+        &musicbrainz_ids[..] as &[Option<String>]
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query_as!(
+        Artist,
+        "select * from artist where name = ANY($1) or musicbrainz_id = ANY($2)",
+        &names[..],
+        &musicbrainz_ids[..] as &[Option<String>]
+    )
+    .fetch_all(&mut *conn)
+    .await
+}
+
+/// Fills in the MusicBrainz IDs of an album that was found without them, keeps the ones it has
+/// when `album` has none.
+pub async fn update_album_musicbrainz_ids(
+    conn: &mut PgConnection,
+    album_id: Uuid,
+    album: &Album,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"update album set musicbrainz_id = coalesce($2, musicbrainz_id),
+            musicbrainz_release_group_id = coalesce($3, musicbrainz_release_group_id)
+        where id = $1"#,
+        album_id,
+        album.musicbrainz_id,
+        album.musicbrainz_release_group_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
 pub async fn add_album(
-    pool: &Pool<Postgres>,
+    conn: &mut PgConnection,
     artist_id: Option<Uuid>,
     album: &Album,
-    songs: &Vec<Song>,
+    songs: &[&Song],
 ) -> Result<(), sqlx::Error> {
     let ret = sqlx::query_as!(
         ReturnId,
         r#"
-        insert into album (name, year, song_count, artist_id)
-        values ($1, $2, $3, $4)
+        insert into album (name, year, song_count, artist_id, musicbrainz_id, musicbrainz_release_group_id)
+        values ($1, $2, $3, $4, $5, $6)
         returning id
    "#,
         album.name,
@@ -238,20 +1099,47 @@ pub async fn add_album(
             Some(id) => id,
             None => album.artist_id,
         },
+        album.musicbrainz_id,
+        album.musicbrainz_release_group_id,
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *conn)
     .await;
     let album_id = ret?.id;
-    let mut mut_songs = songs.to_owned();
-    for song in &mut mut_songs {
-        song.album_id = album_id;
-    }
-    let songs_ret = add_songs(pool, &mut_songs).await;
+    let songs_ret = add_songs(&mut *conn, album_id, songs).await;
     songs_ret?;
+    refresh_album_art(&mut *conn, album_id).await
+}
+
+/// Picks the album artwork out of its songs, preferring embedded pictures over folder images.
+pub async fn refresh_album_art(conn: &mut PgConnection, album_id: Uuid) -> Result<(), sqlx::Error> {
+    let ret = sqlx::query!(
+        r#"
+with chosen as (
+    select art_source, art_path from song
+    where album_id = $1 and art_source <> 'none'
+    order by art_source = 'embedded' desc, disc_number, track
+    limit 1
+)
+update album
+set art_source = coalesce((select art_source from chosen), 'none'),
+    art_path   = (select art_path from chosen)
+where id = $1
+        "#,
+        album_id
+    )
+    .execute(&mut *conn)
+    .await;
+    ret?;
     Ok(())
 }
 
-pub async fn add_songs(pool: &Pool<Postgres>, songs: &Vec<Song>) -> Result<(), sqlx::Error> {
+/// Inserts songs into an album, or updates them in place when a song with the same path is
+/// already there.
+pub async fn add_songs(
+    conn: &mut PgConnection,
+    album: Uuid,
+    songs: &[&Song],
+) -> Result<(), sqlx::Error> {
     let mut title: Vec<String> = Vec::new();
     let mut path: Vec<String> = Vec::new();
     let mut genre: Vec<String> = Vec::new();
@@ -261,6 +1149,22 @@ pub async fn add_songs(pool: &Pool<Postgres>, songs: &Vec<Song>) -> Result<(), s
     let mut duration: Vec<i32> = Vec::new();
     let mut album_id: Vec<Uuid> = Vec::new();
     let mut disc_number: Vec<i32> = Vec::new();
+    let mut art_source: Vec<String> = Vec::new();
+    let mut art_path: Vec<Option<String>> = Vec::new();
+    let mut size: Vec<i64> = Vec::new();
+    let mut mtime: Vec<Option<DateTime<Utc>>> = Vec::new();
+    let mut hash: Vec<Option<String>> = Vec::new();
+    let mut music_folder_id: Vec<Option<i32>> = Vec::new();
+    let mut artist_id: Vec<Uuid> = Vec::new();
+    let mut musicbrainz_id: Vec<Option<String>> = Vec::new();
+    let mut track_gain: Vec<Option<f64>> = Vec::new();
+    let mut track_peak: Vec<Option<f64>> = Vec::new();
+    let mut album_gain: Vec<Option<f64>> = Vec::new();
+    let mut album_peak: Vec<Option<f64>> = Vec::new();
+    let mut bit_rate: Vec<i32> = Vec::new();
+    let mut sample_rate: Vec<i32> = Vec::new();
+    let mut bit_depth: Vec<i32> = Vec::new();
+    let mut channels: Vec<i32> = Vec::new();
     for song in songs {
         title.push(song.title.to_owned());
         path.push(song.path.to_owned());
@@ -269,13 +1173,41 @@ pub async fn add_songs(pool: &Pool<Postgres>, songs: &Vec<Song>) -> Result<(), s
         content_type.push(song.content_type.to_owned());
         track.push(song.track);
         duration.push(song.duration);
-        album_id.push(song.album_id);
+        album_id.push(album);
         disc_number.push(song.disc_number);
+        art_source.push(song.art_source.to_owned());
+        art_path.push(song.art_path.to_owned());
+        size.push(song.size);
+        mtime.push(song.mtime);
+        hash.push(song.hash.to_owned());
+        music_folder_id.push(song.music_folder_id);
+        artist_id.push(song.artist_id);
+        musicbrainz_id.push(song.musicbrainz_id.to_owned());
+        track_gain.push(song.track_gain);
+        track_peak.push(song.track_peak);
+        album_gain.push(song.album_gain);
+        album_peak.push(song.album_peak);
+        bit_rate.push(song.bit_rate);
+        sample_rate.push(song.sample_rate);
+        bit_depth.push(song.bit_depth);
+        channels.push(song.channels);
     }
     let ret = sqlx::query!(
         r#"
-insert into song (title, path, genre, suffix, content_type, track, duration, album_id, disc_number) 
-select * FROM UNNEST($1::text[], $2::text[], $3::text[], $4::text[], $5::text[], $6::int[], $7::int[], $8::uuid[], $9::int[])            
+insert into song (title, path, genre, suffix, content_type, track, duration, album_id, disc_number, art_source, art_path, size, mtime, hash, music_folder_id, artist_id, musicbrainz_id, track_gain, track_peak, album_gain, album_peak, bit_rate, sample_rate, bit_depth, channels)
+select * FROM UNNEST($1::text[], $2::text[], $3::text[], $4::text[], $5::text[], $6::int[], $7::int[], $8::uuid[], $9::int[], $10::text[], $11::text[], $12::bigint[], $13::timestamptz[], $14::text[], $15::int[], $16::uuid[], $17::text[], $18::float8[], $19::float8[], $20::float8[], $21::float8[], $22::int[], $23::int[], $24::int[], $25::int[])
+on conflict (path) do update
+set title = excluded.title, genre = excluded.genre, suffix = excluded.suffix,
+    content_type = excluded.content_type, track = excluded.track, duration = excluded.duration,
+    album_id = excluded.album_id, disc_number = excluded.disc_number,
+    art_source = excluded.art_source, art_path = excluded.art_path,
+    size = excluded.size, mtime = excluded.mtime, hash = excluded.hash,
+    music_folder_id = excluded.music_folder_id, artist_id = excluded.artist_id,
+    musicbrainz_id = excluded.musicbrainz_id, track_gain = excluded.track_gain,
+    track_peak = excluded.track_peak, album_gain = excluded.album_gain,
+    album_peak = excluded.album_peak, bit_rate = excluded.bit_rate,
+    sample_rate = excluded.sample_rate, bit_depth = excluded.bit_depth,
+    channels = excluded.channels
         "#,
         &title[..],
         &path[..],
@@ -285,8 +1217,24 @@ select * FROM UNNEST($1::text[], $2::text[], $3::text[], $4::text[], $5::text[],
         &track[..],
         &duration[..],
         &album_id[..],
-        &disc_number[..]
-    ).execute(pool).await;
+        &disc_number[..],
+        &art_source[..],
+        &art_path[..] as &[Option<String>],
+        &size[..],
+        &mtime[..] as &[Option<DateTime<Utc>>],
+        &hash[..] as &[Option<String>],
+        &music_folder_id[..] as &[Option<i32>],
+        &artist_id[..],
+        &musicbrainz_id[..] as &[Option<String>],
+        &track_gain[..] as &[Option<f64>],
+        &track_peak[..] as &[Option<f64>],
+        &album_gain[..] as &[Option<f64>],
+        &album_peak[..] as &[Option<f64>],
+        &bit_rate[..],
+        &sample_rate[..],
+        &bit_depth[..],
+        &channels[..]
+    ).execute(&mut *conn).await;
     ret?;
     Ok(())
 }
@@ -0,0 +1,298 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+const FOOTER_LENGTH: u64 = 32;
+const ID3V1_LENGTH: u64 = 128;
+// Tags bigger than this are broken, or not worth reading
+const MAX_TAG_SIZE: u32 = 16 * 1024 * 1024;
+const ITEM_BINARY: u32 = 1;
+// Set in the footer's flags when a header precedes the items too
+const HAS_HEADER: u32 = 1 << 31;
+
+// Sample rates WavPack blocks refer to by index
+const WAVPACK_SAMPLE_RATES: [u32; 15] = [
+    6000, 8000, 9600, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000, 64000, 88200, 96000,
+    192000,
+];
+
+/// An APEv2 tag, the kind Monkey's Audio and WavPack files carry (and some MP3s).
+pub struct ApeTag {
+    items: Vec<ApeItem>,
+}
+
+struct ApeItem {
+    key: String,
+    binary: bool,
+    value: Vec<u8>,
+}
+
+impl ApeTag {
+    /// Reads the tag at the end of the file, None if there isn't one.
+    pub fn read_from_path(path: &str) -> Option<Self> {
+        Self::read(&mut File::open(path).ok()?)
+    }
+
+    /// Reads the tag at the end of `reader`, None if there isn't one.
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Option<Self> {
+        let length = reader.seek(SeekFrom::End(0)).ok()?;
+        // The tag comes last, unless there's an ID3v1 tag after it
+        let (tag_end, footer) = [length, length.saturating_sub(ID3V1_LENGTH)]
+            .into_iter()
+            .find_map(|end| Some((end, read_footer(reader, end)?)))?;
+        let size = u32_le(&footer[12..16]);
+        let item_count = u32_le(&footer[16..20]);
+        reader
+            .seek(SeekFrom::Start(tag_end - u64::from(size)))
+            .ok()?;
+        let mut data = vec![0u8; (u64::from(size) - FOOTER_LENGTH) as usize];
+        reader.read_exact(&mut data).ok()?;
+
+        let mut items = Vec::new();
+        let mut position = 0;
+        for _ in 0..item_count {
+            if position + 8 > data.len() {
+                break;
+            }
+            let value_length = u32_le(&data[position..position + 4]) as usize;
+            let flags = u32_le(&data[position + 4..position + 8]);
+            position += 8;
+            let key_length = data[position..].iter().position(|b| *b == 0)?;
+            let key = String::from_utf8_lossy(&data[position..position + key_length]).to_string();
+            position += key_length + 1;
+            let value = data.get(position..position + value_length)?.to_vec();
+            position += value_length;
+            items.push(ApeItem {
+                key,
+                binary: (flags >> 1) & 3 == ITEM_BINARY,
+                value,
+            });
+        }
+        Some(Self { items })
+    }
+
+    /// The first value of a text item, keys are case-insensitive. APEv2 separates multiple values
+    /// with a null byte.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let item = self
+            .items
+            .iter()
+            .find(|i| !i.binary && i.key.eq_ignore_ascii_case(key))?;
+        String::from_utf8_lossy(&item.value)
+            .split('\0')
+            .find(|v| !v.is_empty())
+            .map(|v| v.to_string())
+    }
+
+    /// Every value of a text item, APEv2 separates them with null bytes.
+    pub fn get_all(&self, key: &str) -> Vec<String> {
+        self.items
+            .iter()
+            .filter(|i| !i.binary && i.key.eq_ignore_ascii_case(key))
+            .flat_map(|i| {
+                String::from_utf8_lossy(&i.value)
+                    .split('\0')
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// The front cover, or any other picture if there's none.
+    pub fn cover(&self) -> Option<Vec<u8>> {
+        let pictures: Vec<&ApeItem> = self
+            .items
+            .iter()
+            .filter(|i| i.binary && i.key.to_lowercase().starts_with("cover art"))
+            .collect();
+        let item = pictures
+            .iter()
+            .find(|i| i.key.eq_ignore_ascii_case("cover art (front)"))
+            .or(pictures.first())?;
+        // The picture is preceded by its file name
+        let start = item.value.iter().position(|b| *b == 0)? + 1;
+        Some(item.value[start..].to_vec())
+    }
+}
+
+/// Where the tag ending at `end` starts, its header included, None if no tag ends there.
+pub fn tag_start<R: Read + Seek>(reader: &mut R, end: u64) -> Option<u64> {
+    let footer = read_footer(reader, end)?;
+    let size = u64::from(u32_le(&footer[12..16]));
+    let flags = u32_le(&footer[20..24]);
+    let start = end - size;
+    if flags & HAS_HEADER != 0 {
+        start.checked_sub(FOOTER_LENGTH)
+    } else {
+        Some(start)
+    }
+}
+
+/// The footer of a tag ending at `end`, if there's a sensible one. Its size counts the items and
+/// the footer, but not the header.
+fn read_footer<R: Read + Seek>(reader: &mut R, end: u64) -> Option<[u8; FOOTER_LENGTH as usize]> {
+    if end < FOOTER_LENGTH {
+        return None;
+    }
+    let mut footer = [0u8; FOOTER_LENGTH as usize];
+    reader.seek(SeekFrom::Start(end - FOOTER_LENGTH)).ok()?;
+    reader.read_exact(&mut footer).ok()?;
+    if &footer[0..8] != b"APETAGEX" {
+        return None;
+    }
+    let size = u32_le(&footer[12..16]);
+    if size > MAX_TAG_SIZE || u64::from(size) > end || u64::from(size) < FOOTER_LENGTH {
+        return None;
+    }
+    Some(footer)
+}
+
+/// The stream properties of a Monkey's Audio or WavPack file.
+pub struct StreamInfo {
+    pub seconds: f64,
+    pub sample_rate: u32,
+    pub bit_depth: u32,
+    pub channels: u32,
+}
+
+/// Reads the header of a Monkey's Audio or WavPack file, which symphonia can't open.
+pub fn stream_info(path: &str) -> Option<StreamInfo> {
+    let mut file = File::open(path).ok()?;
+    let mut header = [0u8; 96];
+    let read = file.read(&mut header).ok()?;
+    let header = &header[..read];
+    match header.get(0..4)? {
+        b"MAC " => monkeys_audio_stream_info(header),
+        b"wvpk" => wavpack_stream_info(header),
+        _ => None,
+    }
+}
+
+fn monkeys_audio_stream_info(header: &[u8]) -> Option<StreamInfo> {
+    let version = u16_le(header.get(4..6)?);
+    if version < 3980 {
+        // Older files have a different header, and are long gone from most libraries
+        return None;
+    }
+    // The descriptor says where the header starts
+    let start = u32_le(header.get(8..12)?) as usize;
+    let h = header.get(start..start + 24)?;
+    let blocks_per_frame = u32_le(&h[4..8]) as u64;
+    let final_frame_blocks = u32_le(&h[8..12]) as u64;
+    let total_frames = u32_le(&h[12..16]) as u64;
+    let bit_depth = u16_le(&h[16..18]) as u32;
+    let channels = u16_le(&h[18..20]) as u32;
+    let sample_rate = u32_le(&h[20..24]);
+    if total_frames == 0 || sample_rate == 0 {
+        return None;
+    }
+    let samples = (total_frames - 1) * blocks_per_frame + final_frame_blocks;
+    Some(StreamInfo {
+        seconds: samples as f64 / sample_rate as f64,
+        sample_rate,
+        bit_depth,
+        channels,
+    })
+}
+
+fn wavpack_stream_info(header: &[u8]) -> Option<StreamInfo> {
+    let total_samples = u32_le(header.get(12..16)?);
+    let flags = u32_le(header.get(24..28)?);
+    // All ones means the length is unknown
+    if total_samples == u32::MAX {
+        return None;
+    }
+    let sample_rate = *WAVPACK_SAMPLE_RATES.get(((flags >> 23) & 0xf) as usize)?;
+    // The first block only says mono or stereo, more channels are described further in
+    Some(StreamInfo {
+        seconds: total_samples as f64 / sample_rate as f64,
+        sample_rate,
+        bit_depth: ((flags & 0x3) + 1) * 8,
+        channels: if flags & 0x4 != 0 { 1 } else { 2 },
+    })
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn u16_le(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn item(key: &str, flags: u32, value: &[u8]) -> Vec<u8> {
+        let mut item = (value.len() as u32).to_le_bytes().to_vec();
+        item.extend(flags.to_le_bytes());
+        item.extend(key.as_bytes());
+        item.push(0);
+        item.extend(value);
+        item
+    }
+
+    fn tag(items: &[Vec<u8>]) -> Vec<u8> {
+        let items = items.concat();
+        let part = |flags: u32| {
+            let mut part = b"APETAGEX".to_vec();
+            part.extend(2000u32.to_le_bytes());
+            part.extend((items.len() as u32 + 32).to_le_bytes());
+            part.extend(3u32.to_le_bytes());
+            part.extend(flags.to_le_bytes());
+            part.extend([0u8; 8]);
+            part
+        };
+        [part(HAS_HEADER | 1 << 29), items.clone(), part(HAS_HEADER)].concat()
+    }
+
+    #[test]
+    fn items_are_read_before_an_id3v1_tag() {
+        let tag = tag(&[
+            item("Artist", 0, b"First\0Second"),
+            item("Cover Art (Front)", ITEM_BINARY << 1, b"cover.jpg\0JPEG"),
+            item("Title", 0, b"Song"),
+        ]);
+        let mut id3v1 = b"TAG".to_vec();
+        id3v1.extend([b' '; 125]);
+        let mut file = Cursor::new([&b"AUDIO"[..], &tag, &id3v1].concat());
+
+        let ape = ApeTag::read(&mut file).unwrap();
+        assert_eq!(ape.get("TITLE").as_deref(), Some("Song"));
+        assert_eq!(ape.get("artist").as_deref(), Some("First"));
+        assert_eq!(ape.get_all("Artist"), vec!["First", "Second"]);
+        assert_eq!(ape.get("Cover Art (Front)"), None);
+        assert_eq!(ape.cover().as_deref(), Some(&b"JPEG"[..]));
+
+        let end = file.get_ref().len() as u64 - ID3V1_LENGTH;
+        assert_eq!(tag_start(&mut file, end), Some(5));
+    }
+
+    #[test]
+    fn footers_with_impossible_sizes_are_ignored() {
+        let mut footer = b"APETAGEX".to_vec();
+        footer.extend(2000u32.to_le_bytes());
+        footer.extend(1000u32.to_le_bytes());
+        footer.extend([0u8; 16]);
+        let mut file = Cursor::new([&b"AUDIO"[..], &footer].concat());
+        assert!(ApeTag::read(&mut file).is_none());
+    }
+
+    #[test]
+    fn wavpack_headers_give_the_stream_properties() {
+        let mut header = b"wvpk".to_vec();
+        header.extend([0u8; 8]);
+        header.extend(441_000u32.to_le_bytes());
+        header.extend([0u8; 8]);
+        // 16 bit stereo at 44.1 kHz
+        header.extend((1u32 | 9 << 23).to_le_bytes());
+        let info = wavpack_stream_info(&header).unwrap();
+        assert_eq!(info.sample_rate, 44100);
+        assert_eq!(info.bit_depth, 16);
+        assert_eq!(info.channels, 2);
+        assert_eq!(info.seconds, 10.0);
+    }
+}
@@ -2,70 +2,217 @@ use axum::extract::{Query, Request, State};
 use axum::http::StatusCode;
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
+use entities::user::User;
 use log::{error, warn};
 use md5::{Digest, Md5};
 use serde::Deserialize;
 
+use crate::password_cipher::from_hex;
+use crate::responses::format::ResponseFormat;
+use crate::responses::responses::{ErrorResponse, SubsonicResponse};
 use crate::DatabaseState;
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Default)]
 pub struct Auth {
-    u: String,
-    t: String,
-    s: String,
-    v: String,
-    c: String,
-    f: String,
+    u: Option<String>,
+    p: Option<String>,
+    t: Option<String>,
+    s: Option<String>,
+    #[serde(rename = "apiKey")]
+    api_key: Option<String>,
 }
 
-impl Default for Auth {
-    fn default() -> Self {
-        Auth {
-            u: "".to_string(),
-            t: "".to_string(),
-            s: "".to_string(),
-            v: "".to_string(),
-            c: "".to_string(),
-            f: "".to_string(),
-        }
-    }
+fn auth_error(format: &ResponseFormat, code: i32, message: &str) -> Response {
+    let ret: SubsonicResponse<ErrorResponse> =
+        SubsonicResponse::from_error_code(code, message.to_string());
+    ret.render(format)
+}
+
+/// Hash used to store and look up API keys, so a database dump doesn't leak usable keys.
+pub fn hash_api_key(key: &str) -> String {
+    blake3::hash(key.as_bytes()).to_hex().to_string()
+}
+
+/// Decodes the `p` parameter, which is either the plain password or `enc:` followed by its hex.
+pub(crate) fn decode_password(p: &str) -> Option<String> {
+    let hex = match p.strip_prefix("enc:") {
+        Some(hex) => hex,
+        None => return Some(p.to_string()),
+    };
+    String::from_utf8(from_hex(hex)?).ok()
 }
 
 pub async fn auth_middleware(
     State(state): State<DatabaseState>,
     auth: Option<Query<Auth>>,
-    request: Request,
+    format: ResponseFormat,
+    mut request: Request,
     next: Next,
 ) -> Response {
     // do something with `request`...
-    let owned_auth = auth.unwrap_or_default().to_owned();
-    let user_result = queries::get_user_by_username(&state.pool, &owned_auth.u).await;
-    if let Err(err) = user_result {
-        error!("Error in database connection: {}", err);
-        return StatusCode::UNAUTHORIZED.into_response();
+    let owned_auth = auth.map(|a| a.0).unwrap_or_default();
+
+    let user = if let Some(api_key) = &owned_auth.api_key {
+        if owned_auth.u.is_some()
+            || owned_auth.p.is_some()
+            || owned_auth.t.is_some()
+            || owned_auth.s.is_some()
+        {
+            return auth_error(
+                &format,
+                43,
+                "Multiple conflicting authentication mechanisms provided",
+            );
+        }
+        let user_result =
+            queries::get_user_by_api_key_hash(&state.pool, &hash_api_key(api_key)).await;
+        if let Err(err) = user_result {
+            error!("Error in database connection: {}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        let user_option = user_result.unwrap();
+        if user_option.is_none() {
+            warn!("Invalid API key");
+            return auth_error(&format, 44, "Invalid API key");
+        }
+        user_option.unwrap()
+    } else {
+        if owned_auth.p.is_some() && owned_auth.t.is_some() {
+            return auth_error(
+                &format,
+                43,
+                "Multiple conflicting authentication mechanisms provided",
+            );
+        }
+        let username = match &owned_auth.u {
+            Some(u) => u,
+            None => return auth_error(&format, 10, r#"required parameter "u" is missing"#),
+        };
+        if owned_auth.p.is_none() && owned_auth.t.is_none() {
+            return auth_error(&format, 10, r#"required parameter "t" is missing"#);
+        }
+        if owned_auth.t.is_some() && owned_auth.s.is_none() {
+            return auth_error(&format, 10, r#"required parameter "s" is missing"#);
+        }
+        if owned_auth.p.is_some() && !state.config.allow_password_auth {
+            return auth_error(
+                &format,
+                42,
+                "Provided authentication mechanism not supported",
+            );
+        }
+
+        let user_result = queries::get_user_by_username(&state.pool, username).await;
+        if let Err(err) = user_result {
+            error!("Error in database connection: {}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        let user_option = user_result.unwrap();
+        if user_option.is_none() {
+            warn!("User doesn't exist: {}", username);
+            return auth_error(&format, 40, "Wrong username or password");
+        }
+        let user = user_option.unwrap();
+        // The only place stored passwords are decrypted
+        let password = match state.cipher.decrypt(&user.password) {
+            Ok(p) => p,
+            Err(err) => {
+                error!("Error decrypting password of {}: {}", username, err);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        match check_password(&password, &owned_auth) {
+            Ok(()) => user,
+            Err((code, message)) => {
+                warn!("Failed login for user {}: {}", username, message);
+                return auth_error(&format, code, message);
+            }
+        }
+    };
+
+    // Carry on my wayward son
+    // Handlers that need to check permissions can pick the user up as an `Extension<User>`
+    request.extensions_mut().insert(user);
+    next.run(request).await
+}
+
+fn check_password(stored_password: &str, auth: &Auth) -> Result<(), (i32, &'static str)> {
+    if let Some(p) = &auth.p {
+        return match decode_password(p) {
+            Some(password) if password == stored_password => Ok(()),
+            _ => Err((40, "Wrong username or password")),
+        };
     }
-    let user_option = user_result.unwrap();
-    if user_option.is_none() {
-        warn!("User doesn't exist: {}", &owned_auth.u);
-        return StatusCode::UNAUTHORIZED.into_response();
+    if stored_password.is_empty() {
+        // Without a stored password there's nothing to salt and hash
+        return Err((41, "Token authentication not supported for this user"));
     }
-    let user = user_option.unwrap();
 
     // create a Md5 hasher instance
     let mut hasher = Md5::new();
 
     // process input message
-    hasher.update(user.password + &*owned_auth.s);
+    hasher.update(stored_password.to_owned() + auth.s.as_deref().unwrap_or(""));
 
     // acquire hash digest in the form of GenericArray,
     // which in this case is equivalent to [u8; 16]
     let result = hasher.finalize();
-    if !owned_auth.t.eq(&format!("{:x}", result)) {
-        warn!("Wrong password for user {}", &owned_auth.u);
-        return StatusCode::UNAUTHORIZED.into_response();
+    if !auth
+        .t
+        .as_deref()
+        .unwrap_or("")
+        .eq_ignore_ascii_case(&format!("{:x}", result))
+    {
+        return Err((40, "Wrong username or password"));
     }
+    Ok(())
+}
 
-    // Carry on my wayward son
+/// What a user needs to be allowed on a route, checked by `role_middleware`.
+#[derive(Clone, Copy, Debug)]
+pub enum Role {
+    Admin,
+    Stream,
+    Download,
+    Playlist,
+    CoverArt,
+}
 
-    next.run(request).await
+impl Role {
+    fn granted(self, user: &User) -> bool {
+        match self {
+            Role::Admin => user.admin_role,
+            Role::Stream => user.stream_role,
+            Role::Download => user.download_role,
+            Role::Playlist => user.playlist_role,
+            Role::CoverArt => user.cover_art_role,
+        }
+    }
+
+    fn denied(self) -> &'static str {
+        match self {
+            Role::Admin => "user is not authorized for the given operation",
+            Role::Stream => "user is not authorized to stream",
+            Role::Download => "user is not authorized to download",
+            Role::Playlist => "user is not authorized to manage playlists",
+            Role::CoverArt => "user is not authorized to get cover art",
+        }
+    }
+}
+
+/// Goes after `auth_middleware` on routes only users with `role` may use.
+pub async fn role_middleware(
+    State(role): State<Role>,
+    format: ResponseFormat,
+    request: Request,
+    next: Next,
+) -> Response {
+    match request.extensions().get::<User>() {
+        Some(user) if role.granted(user) => next.run(request).await,
+        Some(user) => {
+            warn!("User {} lacks the {:?} role", user.username, role);
+            auth_error(&format, 50, role.denied())
+        }
+        None => auth_error(&format, 10, r#"required parameter "u" is missing"#),
+    }
 }
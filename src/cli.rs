@@ -0,0 +1,261 @@
+use std::fs;
+use std::io::{BufRead, IsTerminal, Write};
+
+use entities::user::User;
+use log::info;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::auth_middleware::{decode_password, hash_api_key};
+use crate::password_cipher::{self, PasswordCipher};
+use crate::scan::ScanStage;
+use crate::users::new_user;
+use crate::Config;
+
+/// Takes the password from the command line, or reads it from stdin so it doesn't end up in the
+/// shell history (`echo secret | soniccave user add bob` works too).
+fn read_password(password: Option<String>) -> Result<String, String> {
+    let password = match password {
+        Some(p) => p,
+        None => {
+            let stdin = std::io::stdin();
+            if stdin.is_terminal() {
+                eprint!("Password: ");
+                let _ = std::io::stderr().flush();
+            }
+            let mut line = String::new();
+            stdin
+                .lock()
+                .read_line(&mut line)
+                .map_err(|e| format!("Error reading password: {}", e))?;
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    match decode_password(&password) {
+        Some(p) if !p.is_empty() => Ok(p),
+        _ => Err("The password can't be empty".to_string()),
+    }
+}
+
+pub async fn user_add(
+    pool: &Pool<Postgres>,
+    config: &Config,
+    username: String,
+    password: Option<String>,
+    admin: bool,
+) -> Result<(), String> {
+    if queries::get_user_by_username(pool, &username)
+        .await
+        .map_err(|e| e.to_string())?
+        .is_some()
+    {
+        return Err(format!("User {} already exists", username));
+    }
+    let cipher = PasswordCipher::from_config(config)?;
+    let mut user = new_user(username, cipher.encrypt(&read_password(password)?)?);
+    if admin {
+        user.admin_role = true;
+        user.download_role = true;
+        user.upload_role = true;
+        user.playlist_role = true;
+        user.cover_art_role = true;
+        user.comment_role = true;
+        user.podcast_role = true;
+        user.share_role = true;
+    }
+    queries::add_user(pool, &user)
+        .await
+        .map_err(|e| e.to_string())?;
+    info!("User {} created", user.username);
+    Ok(())
+}
+
+pub async fn user_passwd(
+    pool: &Pool<Postgres>,
+    config: &Config,
+    username: String,
+    password: Option<String>,
+) -> Result<(), String> {
+    let user = queries::get_user_by_username(pool, &username)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or(format!("User {} doesn't exist", username))?;
+    let cipher = PasswordCipher::from_config(config)?;
+    let password = cipher.encrypt(&read_password(password)?)?;
+    let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    queries::update_user_password(&mut conn, user.id, &password)
+        .await
+        .map_err(|e| e.to_string())?;
+    info!("Password of {} changed", username);
+    Ok(())
+}
+
+pub async fn user_list(pool: &Pool<Postgres>) -> Result<(), String> {
+    let users = queries::get_users(pool).await.map_err(|e| e.to_string())?;
+    println!("{:<24} {:<6} EMAIL", "USERNAME", "ADMIN");
+    for user in users {
+        println!(
+            "{:<24} {:<6} {}",
+            user.username,
+            user.admin_role,
+            user.email.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+pub async fn user_delete(pool: &Pool<Postgres>, username: String) -> Result<(), String> {
+    let user = queries::get_user_by_username(pool, &username)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or(format!("User {} doesn't exist", username))?;
+    queries::delete_user_by_id(pool, user.id)
+        .await
+        .map_err(|e| e.to_string())?;
+    info!("User {} deleted", username);
+    Ok(())
+}
+
+async fn find_user(pool: &Pool<Postgres>, username: &String) -> Result<User, String> {
+    queries::get_user_by_username(pool, username)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or(format!("User {} doesn't exist", username))
+}
+
+pub async fn api_key_add(
+    pool: &Pool<Postgres>,
+    username: String,
+    name: String,
+) -> Result<(), String> {
+    let user = find_user(pool, &username).await?;
+    // Only the hash is stored, so this is the one chance to see the key
+    let key = password_cipher::generate_secret();
+    let id = queries::add_api_key(pool, user.id, &name, &hash_api_key(&key))
+        .await
+        .map_err(|e| e.to_string())?;
+    info!("API key {} ({}) created for {}", name, id, username);
+    println!("{}", key);
+    Ok(())
+}
+
+pub async fn api_key_list(pool: &Pool<Postgres>, username: String) -> Result<(), String> {
+    let user = find_user(pool, &username).await?;
+    let keys = queries::get_api_keys_by_user_id(pool, user.id)
+        .await
+        .map_err(|e| e.to_string())?;
+    println!("{:<36} {:<19} NAME", "ID", "CREATED");
+    for key in keys {
+        println!(
+            "{:<36} {:<19} {}",
+            key.id,
+            key.created.format("%Y-%m-%d %H:%M:%S"),
+            key.name
+        );
+    }
+    Ok(())
+}
+
+pub async fn api_key_revoke(
+    pool: &Pool<Postgres>,
+    username: String,
+    id: Uuid,
+) -> Result<(), String> {
+    let user = find_user(pool, &username).await?;
+    if !queries::delete_api_key(pool, user.id, id)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        return Err(format!("{} has no API key {}", username, id));
+    }
+    info!("API key {} of {} revoked", id, username);
+    Ok(())
+}
+
+pub async fn playlist_list(pool: &Pool<Postgres>) -> Result<(), String> {
+    let playlists = queries::get_playlists(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    println!("{:<36} {:<6} {:<19} NAME", "ID", "SONGS", "CREATED");
+    for playlist in playlists {
+        let songs = queries::get_songs_by_playlist_id(pool, playlist.id)
+            .await
+            .map_err(|e| e.to_string())?;
+        println!(
+            "{:<36} {:<6} {:<19} {}",
+            playlist.id,
+            songs.len(),
+            playlist.created.format("%Y-%m-%d %H:%M:%S"),
+            playlist.name
+        );
+    }
+    Ok(())
+}
+
+pub async fn scan_errors(pool: &Pool<Postgres>, stage: Option<String>) -> Result<(), String> {
+    if let Some(stage) = &stage {
+        if ScanStage::from_name(stage).is_none() {
+            return Err(format!(
+                "Unknown stage {stage}, expected walk, probe, tag or insert"
+            ));
+        }
+    }
+    let errors = queries::get_scan_errors(pool, stage.as_deref(), i64::MAX, 0)
+        .await
+        .map_err(|e| e.to_string())?;
+    println!("{:<19} {:<6} {:<40} MESSAGE", "LAST SEEN", "STAGE", "PATH");
+    for error in &errors {
+        println!(
+            "{:<19} {:<6} {:<40} {}",
+            error.last_seen.format("%Y-%m-%d %H:%M:%S"),
+            error.stage,
+            error.path,
+            error.message
+        );
+    }
+    info!("{} scan errors", errors.len());
+    Ok(())
+}
+
+pub async fn key_rotate(
+    pool: &Pool<Postgres>,
+    config: &Config,
+    new_key_file: Option<String>,
+) -> Result<(), String> {
+    let old = PasswordCipher::from_config(config)?;
+    let secret = password_cipher::generate_secret();
+    let new = PasswordCipher::from_secret(&secret);
+    match new_key_file {
+        Some(path) => {
+            password_cipher::write_key_file(&path, &secret)?;
+            let count = password_cipher::reencrypt(pool, &old, &new).await?;
+            info!("Re-encrypted the passwords of {} users", count);
+            info!(
+                "Set password_key_file to {} (and remove password_key) in the configuration",
+                path
+            );
+        }
+        None => {
+            if config.password_key.is_some() {
+                return Err(
+                    "The key is set in the configuration, pass --new-key-file to rotate it"
+                        .to_string(),
+                );
+            }
+            // The new key only replaces the old one once every password is encrypted with it
+            let path = &config.password_key_file;
+            let new_path = format!("{}.new", path);
+            password_cipher::write_key_file(&new_path, &secret)?;
+            let count = password_cipher::reencrypt(pool, &old, &new).await?;
+            fs::rename(&new_path, path).map_err(|e| {
+                format!(
+                    "Passwords are encrypted with the key in {}, but it couldn't replace {}: {}",
+                    new_path, path, e
+                )
+            })?;
+            info!("Re-encrypted the passwords of {} users", count);
+        }
+    }
+    info!("Restart the server so it picks up the new key");
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::imageops::FilterType;
+use image::ImageOutputFormat;
+use log::error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, StandardVisualKey, Visual};
+use symphonia::core::probe::Hint;
+use uuid::Uuid;
+
+use crate::ape::ApeTag;
+use crate::explorer::{self, TagType};
+
+pub const ART_EMBEDDED: &str = "embedded";
+pub const ART_FOLDER: &str = "folder";
+pub const ART_NONE: &str = "none";
+
+// Ordered by preference, matched case-insensitively
+const FOLDER_ART_NAMES: [&str; 4] = ["cover", "folder", "front", "album"];
+const FOLDER_ART_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+// The sizes thumbnails are made in, so clients asking for any size can't fill the cache
+const THUMBNAIL_SIZES: [u32; 6] = [64, 128, 256, 512, 1024, 2048];
+
+/// The `coverArt` id clients should use for an item, if it has any artwork at all.
+pub fn cover_art_id(id: Uuid, art_source: &str) -> Option<String> {
+    if art_source == ART_NONE {
+        None
+    } else {
+        Some(id.to_string())
+    }
+}
+
+/// Looks for the usual `cover.jpg`/`folder.png` style images inside an album directory.
+pub fn find_folder_art(dir: &Path) -> Option<String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(err) => {
+            error!("Error reading directory {}: {}", dir.display(), err);
+            return None;
+        }
+    };
+    let mut candidates: Vec<(usize, String)> = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        let extension = path
+            .extension()
+            .and_then(|s| s.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        if !FOLDER_ART_EXTENSIONS.contains(&extension.as_str()) {
+            continue;
+        }
+        if let Some(rank) = FOLDER_ART_NAMES.iter().position(|n| *n == stem) {
+            if let Ok(p) = path.into_os_string().into_string() {
+                candidates.push((rank, p));
+            }
+        }
+    }
+    candidates.sort();
+    candidates.into_iter().next().map(|c| c.1)
+}
+
+/// Reads the front cover (or the first picture if there's no front cover) from the file's tags.
+pub fn read_embedded_art(path: &str) -> Option<Vec<u8>> {
+    match explorer::probe(path) {
+        Ok(Some(TagType::Ogg)) | Ok(Some(TagType::Mp4)) => return read_symphonia_art(path),
+        Ok(Some(TagType::Ape)) => return ApeTag::read_from_path(path)?.cover(),
+        _ => {}
+    }
+    if let Ok(tag) = metaflac::Tag::read_from_path(path) {
+        let pictures: Vec<&metaflac::block::Picture> = tag.pictures().collect();
+        let picture = pictures
+            .iter()
+            .find(|p| p.picture_type == metaflac::block::PictureType::CoverFront)
+            .or(pictures.first());
+        if let Some(p) = picture {
+            return Some(p.data.to_owned());
+        }
+    }
+    if let Ok(tag) = id3::Tag::read_from_path(path) {
+        let pictures: Vec<&id3::frame::Picture> = tag.pictures().collect();
+        let picture = pictures
+            .iter()
+            .find(|p| p.picture_type == id3::frame::PictureType::CoverFront)
+            .or(pictures.first());
+        if let Some(p) = picture {
+            return Some(p.data.to_owned());
+        }
+    }
+    None
+}
+
+/// Pictures in Ogg and MP4 files, which symphonia picks up while probing.
+fn read_symphonia_art(path: &str) -> Option<Vec<u8>> {
+    let src = fs::File::open(path).ok()?;
+    let media_source_stream = MediaSourceStream::new(Box::new(src), Default::default());
+    let mut probed = symphonia::default::get_probe()
+        .format(
+            &Hint::new(),
+            media_source_stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .ok()?;
+    let visuals: Vec<Visual> = probed
+        .format
+        .metadata()
+        .current()
+        .map(|r| r.visuals().to_vec())
+        .unwrap_or_default();
+    let visual = visuals
+        .iter()
+        .find(|v| v.usage == Some(StandardVisualKey::FrontCover))
+        .or(visuals.first())?;
+    Some(visual.data.to_vec())
+}
+
+/// Loads the artwork bytes for a song or album given its recorded source.
+pub fn read_art(source: &str, path: &str) -> Option<Vec<u8>> {
+    match source {
+        ART_EMBEDDED => read_embedded_art(path),
+        ART_FOLDER => match fs::read(path) {
+            Ok(bytes) => Some(bytes),
+            Err(err) => {
+                error!("Error reading cover art {}: {}", path, err);
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+pub fn mime_type(bytes: &[u8]) -> &'static str {
+    match image::guess_format(bytes) {
+        Ok(format) => format.to_mime_type(),
+        Err(_) => "application/octet-stream",
+    }
+}
+
+/// The thumbnail size that serves a request for `size`: the smallest one at least that big, or the
+/// largest there is.
+pub fn thumbnail_size(size: u32) -> u32 {
+    THUMBNAIL_SIZES
+        .into_iter()
+        .find(|s| *s >= size)
+        .unwrap_or(THUMBNAIL_SIZES[THUMBNAIL_SIZES.len() - 1])
+}
+
+/// Where the thumbnail of the artwork at `path` goes. The name changes along with the file's
+/// modification time, so replaced artwork or a retagged song never gets an outdated thumbnail.
+pub fn thumbnail_path(cache_path: &str, path: &str, modified: SystemTime, size: u32) -> PathBuf {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(path.as_bytes());
+    let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    hasher.update(&since_epoch.as_nanos().to_le_bytes());
+    Path::new(cache_path).join("covers").join(format!(
+        "{}-{}.jpg",
+        hasher.finalize().to_hex(),
+        size
+    ))
+}
+
+/// Scales the artwork down to fit in a `size`x`size` box and stores it in the thumbnail cache, if
+/// there's a place for it there.
+pub fn resize(bytes: &[u8], size: u32, cached: Option<&Path>) -> Result<Vec<u8>, String> {
+    let img = image::load_from_memory(bytes).map_err(|e| e.to_string())?;
+    let thumbnail = if img.width() > size || img.height() > size {
+        img.resize(size, size, FilterType::Lanczos3)
+    } else {
+        img
+    };
+    let mut out = Cursor::new(Vec::new());
+    thumbnail
+        .to_rgb8()
+        .write_to(&mut out, ImageOutputFormat::Jpeg(90))
+        .map_err(|e| e.to_string())?;
+    let out = out.into_inner();
+    let Some(cached) = cached else {
+        return Ok(out);
+    };
+    if let Some(parent) = cached.parent() {
+        if let Err(err) = fs::create_dir_all(parent) {
+            error!(
+                "Error creating thumbnail cache {}: {}",
+                parent.display(),
+                err
+            );
+            return Ok(out);
+        }
+    }
+    if let Err(err) = fs::write(cached, &out) {
+        error!("Error writing thumbnail {}: {}", cached.display(), err);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requested_sizes_are_rounded_up_to_a_thumbnail_size() {
+        assert_eq!(thumbnail_size(1), 64);
+        assert_eq!(thumbnail_size(300), 512);
+        assert_eq!(thumbnail_size(512), 512);
+        assert_eq!(thumbnail_size(100_000), 2048);
+    }
+}
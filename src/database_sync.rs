@@ -1,25 +1,26 @@
 use std::collections::HashMap;
+use std::path::Path;
 
 use entities::album::Album;
 use entities::artist::Artist;
 use entities::song::Song;
 
-use sqlx::{Pool, Postgres};
+use log::info;
+use queries::SongHash;
+use sqlx::PgConnection;
 use uuid::Uuid;
 
 pub async fn handle_album(
-    conn: &Pool<Postgres>,
+    conn: &mut PgConnection,
     artist_id: Uuid,
     album_id_opt: Option<Uuid>,
     album: &Album,
-    songs: &Vec<Song>,
+    songs: &[&Song],
 ) -> Result<(), sqlx::Error> {
     if let Some(album_id) = album_id_opt {
-        let mut cloned = songs.clone();
-        for s in &mut cloned {
-            s.album_id = album_id;
-        }
-        let ret = queries::add_songs(conn, &cloned).await;
+        queries::add_songs(conn, album_id, songs).await?;
+        queries::update_album_musicbrainz_ids(conn, album_id, album).await?;
+        let ret = queries::refresh_album_art(conn, album_id).await;
         ret?
     } else {
         let ret = queries::add_album(conn, Some(artist_id), album, songs).await;
@@ -28,28 +29,94 @@ pub async fn handle_album(
     Ok(())
 }
 
-pub async fn sync_database(
-    hashmap_to_add: HashMap<Artist, HashMap<Album, Vec<Song>>>,
-    vec_to_delete: &Vec<String>,
-    conn: &mut Pool<Postgres>,
+/// The artist of `artists` a tagged artist is, by the same rules as `queries::find_artist`.
+pub fn find_artist<'a>(artists: &'a [Artist], artist: &Artist) -> Option<&'a Artist> {
+    let unidentified = || {
+        artists
+            .iter()
+            .find(|a| a.name == artist.name && a.musicbrainz_id.is_none())
+    };
+    match &artist.musicbrainz_id {
+        Some(id) => artists
+            .iter()
+            .find(|a| a.musicbrainz_id.as_ref() == Some(id))
+            .or_else(unidentified),
+        None => unidentified().or_else(|| artists.iter().find(|a| a.name == artist.name)),
+    }
+}
+
+/// The album of `albums` a tagged album goes in. Albums with a MusicBrainz ID go in the one with
+/// the same ID, or one with the same name and no ID yet, so reissues stay apart. Albums without go
+/// in one with the same name, preferably without an ID.
+fn find_album<'a>(albums: &'a [Album], album: &Album) -> Option<&'a Album> {
+    let unidentified = || {
+        albums
+            .iter()
+            .find(|a| a.name == album.name && a.musicbrainz_id.is_none())
+    };
+    match &album.musicbrainz_id {
+        Some(id) => albums
+            .iter()
+            .find(|a| a.musicbrainz_id.as_ref() == Some(id))
+            .or_else(unidentified),
+        None => unidentified().or_else(|| albums.iter().find(|a| a.name == album.name)),
+    }
+}
+
+/// Points songs whose file is gone at the new file with the same hash, so a song that was moved or
+/// renamed keeps its ID, and with it its playlist entries. `songs` are the ones not in the database
+/// yet. Returns how many were moved.
+pub async fn relink_moved(conn: &mut PgConnection, songs: &[&Song]) -> Result<usize, sqlx::Error> {
+    let hashes: Vec<String> = songs.iter().filter_map(|s| s.hash.to_owned()).collect();
+    if hashes.is_empty() {
+        return Ok(0);
+    }
+    // A file that still exists was copied, not moved, the copy is a new song
+    let mut gone: HashMap<String, Vec<SongHash>> = HashMap::new();
+    for song in queries::get_songs_by_hashes(conn, &hashes).await? {
+        if !Path::new(&song.path).exists() {
+            gone.entry(song.hash.to_owned()).or_default().push(song);
+        }
+    }
+    let mut moved = 0;
+    for song in songs {
+        let old = match song.hash.as_ref().and_then(|h| gone.get_mut(h)?.pop()) {
+            Some(old) => old,
+            None => continue,
+        };
+        info!("{} moved to {}", old.path, song.path);
+        queries::update_song_path(conn, old.id, &song.path).await?;
+        moved += 1;
+    }
+    Ok(moved)
+}
+
+/// Adds or updates songs, creating their artists and albums as needed. Safe to call once per
+/// batch: artists and albums written by earlier batches are found by name.
+pub async fn add_to_database(
+    hashmap_to_add: HashMap<&Artist, HashMap<&Album, Vec<&Song>>>,
+    conn: &mut PgConnection,
 ) -> Result<(), sqlx::Error> {
-    let disk_artists: Vec<&Artist> = hashmap_to_add.keys().to_owned().collect();
+    let disk_artists: Vec<&Artist> = hashmap_to_add.keys().copied().collect();
     for disk_artist in disk_artists {
-        let db_artist = queries::get_artist_by_name(conn, &disk_artist.name).await?;
+        let db_artist = queries::find_artist(
+            conn,
+            &disk_artist.name,
+            disk_artist.musicbrainz_id.as_deref(),
+        )
+        .await?;
         match db_artist {
             Some(artist) => {
                 // Existing artist
+                if let (None, Some(id)) = (&artist.musicbrainz_id, &disk_artist.musicbrainz_id) {
+                    queries::set_artist_musicbrainz_id(conn, artist.id, id).await?;
+                }
                 let albums = hashmap_to_add.get(disk_artist).unwrap();
                 let db_albums = queries::get_albums_by_artist_id(conn, artist.id)
                     .await
-                    .unwrap_or(Vec::new())
-                    .into_iter();
+                    .unwrap_or(Vec::new());
                 for (album, songs) in albums {
-                    let existing_album_opt = db_albums.clone().find(|s| s.name == album.name);
-                    let album_id = match existing_album_opt {
-                        Some(a) => Some(a.id),
-                        None => None,
-                    };
+                    let album_id = find_album(&db_albums, album).map(|a| a.id);
                     handle_album(conn, artist.id, album_id, album, songs).await?;
                 }
             }
@@ -64,9 +131,5 @@ pub async fn sync_database(
             }
         }
     }
-    let ret = queries::prune_songs(conn, vec_to_delete).await;
-    match ret {
-        Ok(_) => Ok(()),
-        Err(e) => Err(e),
-    }
+    Ok(())
 }
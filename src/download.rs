@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use entities::song::Song;
+use log::{error, info};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::responses::format::ResponseFormat;
+use crate::responses::responses::{ErrorResponse, SubsonicResponse};
+use crate::stream::serve_file;
+use crate::zip_stream::{zip_body, ZipEntry};
+use crate::DatabaseState;
+
+#[derive(Deserialize)]
+pub struct DownloadQuery {
+    id: Uuid,
+}
+
+/// Sends the original file for a song, or a zip for an album, artist or playlist.
+pub async fn download(
+    State(state): State<DatabaseState>,
+    format: ResponseFormat,
+    request_headers: HeaderMap,
+    query_option: Option<Query<DownloadQuery>>,
+) -> Response {
+    if query_option.is_none() {
+        let ret: SubsonicResponse<ErrorResponse> = SubsonicResponse::from_error_code(
+            10,
+            r#"required parameter "id" is missing"#.to_string(),
+        );
+        return ret.render(&format);
+    }
+    let id = query_option.unwrap().id;
+
+    match download_contents(&state, id).await {
+        Ok(Some(Download::File(song))) => {
+            info!("Downloading song {} with id {}", song.title, song.id);
+            let file_name = Path::new(&song.path)
+                .file_name()
+                .and_then(|f| f.to_str())
+                .unwrap_or("download")
+                .to_string();
+            match serve_file(&song.path, &song.content_type, &request_headers).await {
+                Ok(mut response) => {
+                    if let Ok(value) = HeaderValue::from_str(&content_disposition(&file_name)) {
+                        response
+                            .headers_mut()
+                            .insert(header::CONTENT_DISPOSITION, value);
+                    }
+                    response
+                }
+                Err(err) => err.into_response(),
+            }
+        }
+        Ok(Some(Download::Archive(name, entries))) => {
+            info!("Downloading {} ({} files) as a zip", name, entries.len());
+            let disposition = content_disposition(&format!("{}.zip", sanitize(&name)));
+            (
+                [
+                    (header::CONTENT_TYPE, "application/zip".to_string()),
+                    (header::CONTENT_DISPOSITION, disposition),
+                ],
+                zip_body(entries),
+            )
+                .into_response()
+        }
+        Ok(None) => {
+            let ret: SubsonicResponse<ErrorResponse> = SubsonicResponse::from_error_code(
+                70,
+                r#"resource with provided id does not exist"#.to_string(),
+            );
+            ret.render(&format)
+        }
+        Err(err) => {
+            error!("Error fetching download contents: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+enum Download {
+    File(Box<Song>),
+    Archive(String, Vec<ZipEntry>),
+}
+
+/// Figures out what the id points to, trying songs, albums, artists and playlists in that order.
+async fn download_contents(
+    state: &DatabaseState,
+    id: Uuid,
+) -> Result<Option<Download>, sqlx::Error> {
+    if let Some(song) = queries::get_song_by_id(&state.pool, id).await? {
+        return Ok(Some(Download::File(Box::new(song))));
+    }
+    if let Some(album) = queries::get_album_by_id(&state.pool, id).await? {
+        let artist_name = queries::get_artist_by_id(&state.pool, album.artist_id)
+            .await?
+            .map(|a| a.name)
+            .unwrap_or_default();
+        let songs = queries::get_songs_by_album_id(&state.pool, album.id)
+            .await
+            .unwrap_or_default();
+        let entries = album_entries("", &songs);
+        return Ok(Some(Download::Archive(
+            format!("{} - {}", artist_name, album.name),
+            entries,
+        )));
+    }
+    if let Some(artist) = queries::get_artist_by_id(&state.pool, id).await? {
+        let albums = queries::get_albums_by_artist_id(&mut *state.pool.acquire().await?, artist.id)
+            .await
+            .unwrap_or_default();
+        let mut entries = Vec::new();
+        let mut used_folders = HashSet::new();
+        for album in albums {
+            let songs = queries::get_songs_by_album_id(&state.pool, album.id)
+                .await
+                .unwrap_or_default();
+            let folder = unique_name(&mut used_folders, sanitize(&album.name), "");
+            entries.append(&mut album_entries(&format!("{}/", folder), &songs));
+        }
+        return Ok(Some(Download::Archive(artist.name, entries)));
+    }
+    if let Some(playlist) = queries::get_playlist_by_id(&state.pool, id).await? {
+        let songs = queries::get_songs_by_playlist_id(&state.pool, playlist.id).await?;
+        let digits = songs.len().to_string().len().max(2);
+        let mut used = HashSet::new();
+        let entries = songs
+            .iter()
+            .enumerate()
+            .map(|(i, song)| {
+                let stem = format!(
+                    "{:0width$} - {}",
+                    i + 1,
+                    sanitize(&song.title),
+                    width = digits
+                );
+                ZipEntry {
+                    name: unique_name(&mut used, stem, &extension(&song.path)),
+                    path: song.path.to_owned(),
+                }
+            })
+            .collect();
+        return Ok(Some(Download::Archive(playlist.name, entries)));
+    }
+    Ok(None)
+}
+
+/// Names album tracks `NN - Title.ext`, inside `Disc N/` folders when the album has several discs.
+fn album_entries(prefix: &str, songs: &[Song]) -> Vec<ZipEntry> {
+    let multi_disc = songs.iter().any(|s| s.disc_number != songs[0].disc_number);
+    let mut used = HashSet::new();
+    songs
+        .iter()
+        .map(|song| {
+            let disc = if multi_disc {
+                format!("Disc {}/", song.disc_number)
+            } else {
+                "".to_string()
+            };
+            let stem = format!(
+                "{}{}{:02} - {}",
+                prefix,
+                disc,
+                song.track,
+                sanitize(&song.title)
+            );
+            ZipEntry {
+                name: unique_name(&mut used, stem, &extension(&song.path)),
+                path: song.path.to_owned(),
+            }
+        })
+        .collect()
+}
+
+fn extension(path: &str) -> String {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some(e) => format!(".{}", e),
+        None => "".to_string(),
+    }
+}
+
+fn unique_name(used: &mut HashSet<String>, stem: String, extension: &str) -> String {
+    let mut name = format!("{}{}", stem, extension);
+    let mut counter = 2;
+    while used.contains(&name) {
+        name = format!("{} ({}){}", stem, counter, extension);
+        counter += 1;
+    }
+    used.insert(name.to_owned());
+    name
+}
+
+/// Keeps names safe to extract on any filesystem.
+fn sanitize(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let trimmed = cleaned.trim().trim_matches('.').trim();
+    if trimmed.is_empty() {
+        "Unknown".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+fn content_disposition(file_name: &str) -> String {
+    let ascii: String = file_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let encoded: String = file_name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        ascii, encoded
+    )
+}
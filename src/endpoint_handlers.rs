@@ -2,9 +2,8 @@ use std::collections::HashMap;
 
 use axum::extract::Query;
 use axum::extract::State;
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
-use axum::Json;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
 use chrono::DateTime;
 use chrono::Local;
 use entities::playlist::Playlist;
@@ -16,19 +15,33 @@ use serde::{Deserialize, Serialize};
 
 use uuid::Uuid;
 
-use crate::responses::album_response::AlbumResponse;
+use crate::cover_art;
+use crate::responses::album_response::{AlbumResponse, SongLinks};
+use crate::responses::format::ResponseFormat;
+use crate::responses::responses::MusicFoldersResponse;
 use crate::responses::responses::PlaylistResponse;
 use crate::responses::responses::PlaylistsResponse;
+use crate::responses::responses::RandomSongsResponse;
 use crate::responses::responses::SearchResponse;
 use sqlx::postgres::PgQueryResult;
-use sqlx::FromRow;
+use sqlx::{Pool, Postgres};
 
 use crate::responses::responses::{
-    ArtistIndex, ArtistItem, ArtistsEndpointResponse, ArtistsEndpointResponseIndex, ErrorResponse,
-    SubsonicResponse,
+    ArtistIndex, ArtistItem, ArtistsEndpointResponse, ArtistsEndpointResponseIndex, EmptyResponse,
+    ErrorResponse, OpenSubsonicExtensionsResponse, SubsonicResponse,
 };
 use crate::DatabaseState;
 
+// OpenSubsonic extensions this server implements, with their supported versions
+const OPEN_SUBSONIC_EXTENSIONS: [(&str, &[i32]); 3] = [
+    ("apiKeyAuthentication", &[1]),
+    ("songLyrics", &[1]),
+    ("transcodeOffset", &[1]),
+];
+
+// The most songs getRandomSongs returns, as in the Subsonic spec
+const MAX_RANDOM_SONGS: i32 = 500;
+
 #[derive(Deserialize)]
 pub struct GetAlbumsQuery {
     r#type: String,
@@ -36,6 +49,28 @@ pub struct GetAlbumsQuery {
     size: Option<i32>,
     #[serde(default)]
     offset: Option<i32>,
+    #[serde(rename = "musicFolderId", default)]
+    music_folder_id: Option<i32>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct MusicFolderQuery {
+    #[serde(rename = "musicFolderId", default)]
+    music_folder_id: Option<i32>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct RandomSongsQuery {
+    #[serde(default)]
+    size: Option<i32>,
+    #[serde(default)]
+    genre: Option<String>,
+    #[serde(rename = "fromYear", default)]
+    from_year: Option<i32>,
+    #[serde(rename = "toYear", default)]
+    to_year: Option<i32>,
+    #[serde(rename = "musicFolderId", default)]
+    music_folder_id: Option<i32>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -43,6 +78,13 @@ pub struct IdQuery {
     id: Uuid,
 }
 
+#[derive(Deserialize)]
+pub struct CoverArtQuery {
+    id: Uuid,
+    #[serde(default)]
+    size: Option<u32>,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct CountQuery {
     count: Option<i64>,
@@ -63,6 +105,8 @@ pub struct SearchQuery {
     song_count: Option<i32>,
     #[serde(rename = "songOffset")]
     song_offset: Option<i32>,
+    #[serde(rename = "musicFolderId")]
+    music_folder_id: Option<i32>,
 }
 
 #[derive(Deserialize, Clone, Serialize)]
@@ -73,14 +117,90 @@ pub struct CreatePlaylistQuery {
     #[serde(rename = "songId", default)]
     song_id: Option<Vec<Uuid>>,
 }
-#[derive(FromRow, Serialize)]
-struct IdName {
-    id: Uuid,
-    name: String,
+
+pub async fn ping(format: ResponseFormat) -> impl IntoResponse {
+    SubsonicResponse::<EmptyResponse>::ok().render(&format)
+}
+
+pub async fn get_open_subsonic_extensions(format: ResponseFormat) -> impl IntoResponse {
+    SubsonicResponse::<OpenSubsonicExtensionsResponse>::from_extensions(&OPEN_SUBSONIC_EXTENSIONS)
+        .render(&format)
+}
+
+pub async fn get_music_folders(
+    State(state): State<DatabaseState>,
+    format: ResponseFormat,
+) -> impl IntoResponse {
+    match queries::get_music_folders(&state.pool).await {
+        Ok(folders) => {
+            SubsonicResponse::<MusicFoldersResponse>::from_music_folders(folders).render(&format)
+        }
+        Err(err) => {
+            error!("Error fetching music folders: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// The error response for a `musicFolderId` that doesn't match any folder, None if it's fine.
+async fn unknown_music_folder(
+    state: &DatabaseState,
+    music_folder_id: Option<i32>,
+    format: &ResponseFormat,
+) -> Option<Response> {
+    let id = music_folder_id?;
+    match queries::get_music_folders(&state.pool).await {
+        Ok(folders) if folders.iter().any(|f| f.id == id) => None,
+        Ok(_) => {
+            let ret: SubsonicResponse<ErrorResponse> =
+                SubsonicResponse::from_error_code(70, r#"music folder not found"#.to_string());
+            Some(ret.render(format))
+        }
+        Err(err) => {
+            error!("Error fetching music folders: {}", err);
+            Some(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+pub async fn get_random_songs(
+    State(state): State<DatabaseState>,
+    format: ResponseFormat,
+    query_option: Option<Query<RandomSongsQuery>>,
+) -> impl IntoResponse {
+    let query = query_option.map(|q| q.0).unwrap_or_default();
+    if let Some(response) = unknown_music_folder(&state, query.music_folder_id, &format).await {
+        return response;
+    }
+    let songs_result = queries::get_random_songs(
+        &state.pool,
+        query.size.unwrap_or(10).clamp(0, MAX_RANDOM_SONGS),
+        query.genre.as_deref(),
+        query.from_year,
+        query.to_year,
+        query.music_folder_id,
+    )
+    .await;
+    match songs_result {
+        Ok(songs) => {
+            let links = song_links(&state.pool, songs.iter().map(|s| s.id).collect()).await;
+            SubsonicResponse::<RandomSongsResponse>::from_random_songs(
+                songs,
+                &links,
+                &state.config.transcoding,
+            )
+            .render(&format)
+        }
+        Err(err) => {
+            error!("Error fetching random songs: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
 }
 
 pub async fn search(
     State(state): State<DatabaseState>,
+    format: ResponseFormat,
     query_option: Option<Query<SearchQuery>>,
 ) -> impl IntoResponse {
     if query_option.is_none() {
@@ -88,22 +208,30 @@ pub async fn search(
             10,
             r#"required parameter "query" is missing"#.to_string(),
         );
-        return Json(ret).into_response();
+        return ret.render(&format);
     }
     let query = query_option.unwrap().clone();
+    if let Some(response) = unknown_music_folder(&state, query.music_folder_id, &format).await {
+        return response;
+    }
 
     let artist_rows = sqlx::query_as!(
         entities::artist::ArtistSqlxModel,
         r#"SELECT
 	*
 FROM "artist"
-WHERE SIMILARITY(name,$1) > 0.4 or name ilike '%' || $1 || '%'
+WHERE (SIMILARITY(name,$1) > 0.4 or name ilike '%' || $1 || '%')
+    and ($4::int is null or exists (
+        select 1 from album inner join song on song.album_id = album.id
+        where (album.artist_id = artist.id or song.artist_id = artist.id)
+            and song.music_folder_id = $4))
 order by SIMILARITY(name,$1) desc
         LIMIT $2
         OFFSET $3;"#,
         query.query,
         query.artist_count.unwrap_or(10) as i64,
-        query.artist_offset.unwrap_or(0) as i64
+        query.artist_offset.unwrap_or(0) as i64,
+        query.music_folder_id
     )
     .fetch_all(&state.pool)
     .await
@@ -112,14 +240,17 @@ order by SIMILARITY(name,$1) desc
         entities::album::AlbumSqlxModel,
         r#"select album.*, artist.name as artist_name
         from album inner join artist on album.artist_id = artist.id
-        where SIMILARITY(album.name,$1) > 0.4 or album.name ilike '%' || $1 || '%'
-        or SIMILARITY(artist.name,$1) > 0.4 or artist.name ilike '%' || $1 || '%'
+        where (SIMILARITY(album.name,$1) > 0.4 or album.name ilike '%' || $1 || '%'
+        or SIMILARITY(artist.name,$1) > 0.4 or artist.name ilike '%' || $1 || '%')
+        and ($4::int is null or exists (
+            select 1 from song where song.album_id = album.id and song.music_folder_id = $4))
         order by SIMILARITY(album.name,$1) + SIMILARITY(artist.name,$1) * 0.3 desc
         LIMIT $2
         OFFSET $3;"#,
         query.query,
         query.album_count.unwrap_or(10) as i64,
-        query.album_offset.unwrap_or(0) as i64
+        query.album_offset.unwrap_or(0) as i64,
+        query.music_folder_id
     )
     .fetch_all(&state.pool)
     .await
@@ -127,26 +258,50 @@ order by SIMILARITY(name,$1) desc
     let song_rows = sqlx::query_as!(
         entities::song::SongSqlxModel,
         r#"select song.id, song.title,song.path,song.genre,song.suffix,song.content_type,song.track,
-        song.duration, song.album_id, song.disc_number,
-         album.name as album_name, artist.name as artist_name, album.year, artist.id as artist_id
+        song.duration, song.album_id, song.disc_number, song.art_source,
+         album.name as album_name, artist.name as artist_name, album.year, artist.id as artist_id,
+         song.musicbrainz_id, song.track_gain, song.track_peak, song.album_gain, song.album_peak,
+         song.size, song.bit_rate, song.sample_rate, song.bit_depth, song.channels
         from song inner join album on song.album_id = album.id
-                  inner join artist on album.artist_id = artist.id
-        where SIMILARITY(song.title,$1) > 0.4 or song.title ilike '%' || $1 || '%'
+                  inner join artist on song.artist_id = artist.id
+        where (SIMILARITY(song.title,$1) > 0.4 or song.title ilike '%' || $1 || '%'
             or SIMILARITY(album.name,$1) > 0.4 or album.name ilike '%' || $1 || '%'
-        or SIMILARITY(artist.name,$1) > 0.4 or artist.name ilike '%' || $1 || '%'
+        or SIMILARITY(artist.name,$1) > 0.4 or artist.name ilike '%' || $1 || '%')
+        and ($4::int is null or song.music_folder_id = $4)
         order by SIMILARITY(song.title,$1) + SIMILARITY(album.name,$1) * 0.3 + SIMILARITY(artist.name,$1) * 0.15 desc
         LIMIT $2
         OFFSET $3;"#,
         query.query,
         query.song_count.unwrap_or(10) as i64,
-        query.song_offset.unwrap_or(0) as i64
+        query.song_offset.unwrap_or(0) as i64,
+        query.music_folder_id
     )
     .fetch_all(&state.pool)
     .await
     .unwrap();
-    let ret =
-        SubsonicResponse::<SearchResponse>::from_search_result(artist_rows, album_rows, song_rows);
-    Json(ret).into_response()
+    let links = song_links(&state.pool, song_rows.iter().map(|s| s.id).collect()).await;
+    let ret = SubsonicResponse::<SearchResponse>::from_search_result(
+        artist_rows,
+        album_rows,
+        song_rows,
+        &links,
+        &state.config.transcoding,
+    );
+    ret.render(&format)
+}
+
+/// The artists and genres of these songs. Responses make do with each song's main artist when
+/// they can't be read.
+async fn song_links(pool: &Pool<Postgres>, song_ids: Vec<Uuid>) -> SongLinks {
+    let artists = queries::get_song_artists(pool, &song_ids).await;
+    let genres = queries::get_song_genres(pool, &song_ids).await;
+    match (artists, genres) {
+        (Ok(artists), Ok(genres)) => SongLinks::new(artists, genres),
+        (Err(err), _) | (_, Err(err)) => {
+            error!("Error fetching song artists and genres: {}", err);
+            SongLinks::default()
+        }
+    }
 }
 
 async fn get_db_playlist(
@@ -165,10 +320,12 @@ async fn get_db_songs_playlist(
     sqlx::query_as!(
         SongSqlxModel,
         r#"select song.id, song.title,song.path,song.genre,song.suffix,song.content_type,song.track,
-        song.duration, song.album_id, song.disc_number,
-         album.name as album_name, artist.name as artist_name, album.year, artist.id as artist_id
+        song.duration, song.album_id, song.disc_number, song.art_source,
+         album.name as album_name, artist.name as artist_name, album.year, artist.id as artist_id,
+         song.musicbrainz_id, song.track_gain, song.track_peak, song.album_gain, song.album_peak,
+         song.size, song.bit_rate, song.sample_rate, song.bit_depth, song.channels
         from song inner join album on song.album_id = album.id
-                  inner join artist on album.artist_id = artist.id
+                  inner join artist on song.artist_id = artist.id
          where song.id in (
                 select song_id from playlist_items where playlist_id = $1 
             );
@@ -181,6 +338,7 @@ async fn get_db_songs_playlist(
 
 pub async fn get_playlist(
     axum_state: State<DatabaseState>,
+    format: ResponseFormat,
     id_query_option: Option<Query<IdQuery>>,
 ) -> impl IntoResponse {
     if id_query_option.is_none() {
@@ -188,7 +346,7 @@ pub async fn get_playlist(
             10,
             r#"required parameter "id" is missing"#.to_string(),
         );
-        return Json(ret).into_response();
+        return ret.render(&format);
     }
     let id_query = id_query_option.unwrap();
     let playlist_result = get_db_playlist(axum_state.to_owned(), id_query.id).await;
@@ -201,14 +359,21 @@ pub async fn get_playlist(
         error!("{}", err);
         return StatusCode::INTERNAL_SERVER_ERROR.into_response();
     }
-    Json(SubsonicResponse::<PlaylistResponse>::from_playlist(
+    let songs = songs_result.unwrap();
+    let links = song_links(&axum_state.pool, songs.iter().map(|s| s.id).collect()).await;
+    SubsonicResponse::<PlaylistResponse>::from_playlist(
         playlist_result.unwrap(),
-        songs_result.unwrap(),
-    ))
-    .into_response()
+        songs,
+        &links,
+        &axum_state.config.transcoding,
+    )
+    .render(&format)
 }
 
-pub async fn get_playlists(State(state): State<DatabaseState>) -> impl IntoResponse {
+pub async fn get_playlists(
+    State(state): State<DatabaseState>,
+    format: ResponseFormat,
+) -> impl IntoResponse {
     let playlists_result = sqlx::query_as!(Playlist, r#"select * from playlists;"#)
         .fetch_all(&state.pool)
         .await;
@@ -217,14 +382,12 @@ pub async fn get_playlists(State(state): State<DatabaseState>) -> impl IntoRespo
         return StatusCode::INTERNAL_SERVER_ERROR.into_response();
     }
     let playlists = playlists_result.unwrap();
-    Json(SubsonicResponse::<PlaylistsResponse>::from_playlist_list(
-        playlists,
-    ))
-    .into_response()
+    SubsonicResponse::<PlaylistsResponse>::from_playlist_list(playlists).render(&format)
 }
 
 pub async fn create_update_playlist(
     axum_state: State<DatabaseState>,
+    format: ResponseFormat,
     query_option: Option<axum_extra::extract::Query<CreatePlaylistQuery>>,
 ) -> impl IntoResponse {
     let State(state) = axum_state.to_owned();
@@ -246,100 +409,22 @@ pub async fn create_update_playlist(
         }
         let playlist_id = playlist_insert_result.unwrap();
         let playlist_result = get_db_playlist(axum_state.to_owned(), playlist_id).await;
-        let songs_result = get_db_songs_playlist(axum_state, playlist_id).await;
-        return Json(SubsonicResponse::<PlaylistResponse>::from_playlist(
+        let songs = get_db_songs_playlist(axum_state, playlist_id)
+            .await
+            .unwrap();
+        let links = song_links(&state.pool, songs.iter().map(|s| s.id).collect()).await;
+        return SubsonicResponse::<PlaylistResponse>::from_playlist(
             playlist_result.unwrap(),
-            songs_result.unwrap(),
-        ))
-        .into_response();
-    } else {
+            songs,
+            &links,
+            &state.config.transcoding,
+        )
+        .render(&format);
     }
     // info!("{}", serde_json::to_string(&q).unwrap());
     StatusCode::OK.into_response()
 }
 
-async fn update_playlist(
-    State(state): State<DatabaseState>,
-    id: Uuid,
-    name: String,
-    song_ids: Vec<Uuid>,
-) -> Result<(), &'static str> {
-    let playlist_result = sqlx::query_as!(
-        CountQuery,
-        r#"select count(*) from playlists where id = $1;"#,
-        id
-    )
-    .fetch_one(&state.pool)
-    .await;
-
-    if let Err(err) = playlist_result {
-        error!("{}", err);
-        return Err("There was an error fetching the playlists.");
-    }
-
-    if playlist_result.unwrap().count.unwrap() != 1 {
-        return Err("The provided id does not match any playlists in the database");
-    }
-
-    let songs_result = sqlx::query_as!(
-        CountQuery,
-        r#"select count(*) from song where id in (SELECT unnest($1::uuid[]));"#,
-        song_ids.to_owned() as Vec<Uuid>
-    )
-    .fetch_one(&state.pool)
-    .await;
-    if let Err(err) = songs_result {
-        error!("{}", err);
-        return Err("There was an error fetching songs.");
-    }
-
-    let songs = songs_result.unwrap();
-    let song_count = songs.count.unwrap();
-    if song_count != song_ids.to_owned().into_iter().count() as i64 {
-        return Err("At least one song id was not in the database");
-    }
-    let update_result = sqlx::query_as!(
-        IdQuery,
-        r#"
-            update playlists set name = $1 where id = $2;
-        "#,
-        name,
-        id
-    )
-    .fetch_one(&state.pool)
-    .await;
-    if let Err(err) = update_result {
-        error!("{}", err);
-        return Err("There was an error updating the playlist.");
-    }
-    info!("{}", song_count);
-    let order: Vec<i32> = (0..song_count).map(|x| (x + 1) as i32).collect();
-    let playlist_id_vec: Vec<Uuid> = (0..song_count).map(|_| id).collect();
-    let modified_vec: Vec<DateTime<Local>> = (0..song_count).map(|_| Local::now()).collect();
-    info!("{:?}", serde_json::to_string(&order.to_owned()));
-    let remove_songs_result =
-        sqlx::query!(r#"delete from playlist_items where playlist_id = $1"#, id)
-            .execute(&state.pool)
-            .await;
-    if let Err(err) = remove_songs_result {
-        error!("{}", err);
-        return Err("There was an error deleting songs from the database.");
-    }
-    let insert_songs_result = insert_songs_query(
-        playlist_id_vec,
-        modified_vec,
-        song_ids.to_owned(),
-        order,
-        &state,
-    )
-    .await;
-    if let Err(err) = insert_songs_result {
-        error!("{}", err);
-        return Err("There was an error inserting songs in the playlist");
-    }
-    Ok(())
-}
-
 async fn insert_songs_query(
     playlist_id_vec: Vec<Uuid>,
     modified_vec: Vec<DateTime<Local>>,
@@ -425,6 +510,7 @@ async fn create_playlist(
 
 pub async fn get_album(
     State(state): State<DatabaseState>,
+    format: ResponseFormat,
     query_option: Option<Query<IdQuery>>,
 ) -> impl IntoResponse {
     if query_option.is_none() {
@@ -432,7 +518,7 @@ pub async fn get_album(
             10,
             r#"required parameter "id" is missing"#.to_string(),
         );
-        return Json(ret).into_response();
+        return ret.render(&format);
     }
 
     let id = query_option.unwrap().id;
@@ -451,7 +537,7 @@ pub async fn get_album(
             10,
             r#"resource with provided id does not exist"#.to_string(),
         );
-        return Json(ret).into_response();
+        return ret.render(&format);
     }
     let album = album_option.unwrap();
 
@@ -463,14 +549,31 @@ pub async fn get_album(
     let songs = queries::get_songs_by_album_id(&state.pool, album.id)
         .await
         .unwrap();
+    let artist_ids = songs.iter().map(|i| i.artist_id).collect::<Vec<Uuid>>();
+    let track_artists = queries::get_artists_by_id(&state.pool, &artist_ids)
+        .await
+        .unwrap_or_default();
+    let genres = queries::get_album_genres(&state.pool, album.id)
+        .await
+        .unwrap_or_default();
+    let links = song_links(&state.pool, songs.iter().map(|s| s.id).collect()).await;
     let ret = SubsonicResponse {
-        subsonic_response: AlbumResponse::from_album(artist, album, songs),
+        subsonic_response: AlbumResponse::from_album(
+            artist,
+            album,
+            genres,
+            songs,
+            &track_artists,
+            &links,
+            &state.config.transcoding,
+        ),
     };
-    Json(ret).into_response()
+    ret.render(&format)
 }
 
 pub async fn get_albums(
     State(state): State<DatabaseState>,
+    format: ResponseFormat,
     query_option: Option<Query<GetAlbumsQuery>>,
 ) -> impl IntoResponse {
     if query_option.is_none() {
@@ -478,7 +581,7 @@ pub async fn get_albums(
             10,
             r#"required parameter "type" is missing"#.to_string(),
         );
-        return Json(ret).into_response();
+        return ret.render(&format);
     }
     let mut query = query_option.unwrap();
     if query.offset.is_none() {
@@ -499,11 +602,16 @@ pub async fn get_albums(
             10,
             r#"required parameter "type" is missing"#.to_string(),
         );
-        return Json(ret).into_response();
+        return ret.render(&format);
+    }
+    if let Some(response) = unknown_music_folder(&state, query.music_folder_id, &format).await {
+        return response;
     }
     match query.r#type.as_str() {
         "random" => {
-            let db_albums_res = queries::get_random_albums(&state.pool, query.size.unwrap()).await;
+            let db_albums_res =
+                queries::get_random_albums(&state.pool, query.size.unwrap(), query.music_folder_id)
+                    .await;
             if db_albums_res.is_err() {
                 return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
@@ -516,11 +624,16 @@ pub async fn get_albums(
             }
             let artists = db_artists_res.unwrap();
             let ret = SubsonicResponse::album_list2_from_album_list(&albums, &artists);
-            return Json(ret).into_response();
+            return ret.render(&format);
         }
         "frequent" | "newest" | "recent" | "alphabeticalByName" => {
-            let db_albums_res =
-                queries::get_albums(&state.pool, query.size.unwrap(), query.offset.unwrap()).await;
+            let db_albums_res = queries::get_albums(
+                &state.pool,
+                query.size.unwrap(),
+                query.offset.unwrap(),
+                query.music_folder_id,
+            )
+            .await;
             if db_albums_res.is_err() {
                 return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
@@ -532,7 +645,7 @@ pub async fn get_albums(
             }
             let artists = db_artists_res.unwrap();
             let ret = SubsonicResponse::album_list2_from_album_list(&albums, &artists);
-            return Json(ret).into_response();
+            return ret.render(&format);
         }
         _ => {}
     }
@@ -541,6 +654,7 @@ pub async fn get_albums(
 
 pub async fn get_artist(
     State(state): State<DatabaseState>,
+    format: ResponseFormat,
     query_option: Option<Query<IdQuery>>,
 ) -> impl IntoResponse {
     if query_option.is_none() {
@@ -548,7 +662,7 @@ pub async fn get_artist(
             10,
             r#"required parameter "id" is missing"#.to_string(),
         );
-        return Json(ret).into_response();
+        return ret.render(&format);
     }
     let query = query_option.unwrap();
     let artist_result = queries::get_artist_by_id(&state.pool, query.id).await;
@@ -561,14 +675,27 @@ pub async fn get_artist(
         return StatusCode::NOT_FOUND.into_response();
     }
     let artist = artist.unwrap();
-    let albums_result = queries::get_albums_by_artist_id(&state.pool, artist.id).await;
+    // Their own albums along with the ones they only play some tracks on
+    let albums_result = queries::get_albums_with_artist(&state.pool, artist.id).await;
     let albums = albums_result.unwrap_or_default();
-    let ret = SubsonicResponse::artist_from_album_list(albums, artist);
-    Json(ret).into_response()
+    let artist_ids = albums.iter().map(|i| i.artist_id).collect::<Vec<Uuid>>();
+    let album_artists = queries::get_artists_by_id(&state.pool, &artist_ids)
+        .await
+        .unwrap_or_default();
+    let ret = SubsonicResponse::artist_from_album_list(albums, artist, &album_artists);
+    ret.render(&format)
 }
 
-pub async fn get_artists(State(state): State<DatabaseState>) -> impl IntoResponse {
-    let artists_result = queries::get_all_artists(&state.pool).await;
+pub async fn get_artists(
+    State(state): State<DatabaseState>,
+    format: ResponseFormat,
+    query_option: Option<Query<MusicFolderQuery>>,
+) -> impl IntoResponse {
+    let query = query_option.map(|q| q.0).unwrap_or_default();
+    if let Some(response) = unknown_music_folder(&state, query.music_folder_id, &format).await {
+        return response;
+    }
+    let artists_result = queries::get_all_artists(&state.pool, query.music_folder_id).await;
     if artists_result.is_err() {
         return StatusCode::UNAUTHORIZED.into_response();
     }
@@ -604,6 +731,7 @@ pub async fn get_artists(State(state): State<DatabaseState>) -> impl IntoRespons
                 name: artist.name,
                 album_count: 0,
                 artist_image_url: "".to_string(),
+                musicbrainz_id: artist.musicbrainz_id.to_owned(),
             }]);
         } else {
             let vec: &mut Vec<ArtistItem> = artists_hashmap.get_mut(&first_letter).unwrap();
@@ -612,6 +740,7 @@ pub async fn get_artists(State(state): State<DatabaseState>) -> impl IntoRespons
                 name: artist.name,
                 album_count: 0,
                 artist_image_url: "".to_string(),
+                musicbrainz_id: artist.musicbrainz_id.to_owned(),
             });
         }
     }
@@ -627,7 +756,7 @@ pub async fn get_artists(State(state): State<DatabaseState>) -> impl IntoRespons
     keys.sort();
     for artist_key in keys {
         let mut artists_vec = artists_hashmap.get(artist_key).unwrap().to_vec();
-        artists_vec.sort_by(|a, b| a.name.to_uppercase().cmp(&b.name.to_uppercase()));
+        artists_vec.sort_by_key(|a| a.name.to_uppercase());
         let index = ArtistIndex {
             name: artist_key.to_string(),
             artist: artists_vec,
@@ -637,5 +766,94 @@ pub async fn get_artists(State(state): State<DatabaseState>) -> impl IntoRespons
     let ret = SubsonicResponse {
         subsonic_response: artists_endpoint_response,
     };
-    Json(ret).into_response()
+    ret.render(&format)
+}
+
+pub async fn get_cover_art(
+    State(state): State<DatabaseState>,
+    format: ResponseFormat,
+    query_option: Option<Query<CoverArtQuery>>,
+) -> impl IntoResponse {
+    if query_option.is_none() {
+        let ret: SubsonicResponse<ErrorResponse> = SubsonicResponse::from_error_code(
+            10,
+            r#"required parameter "id" is missing"#.to_string(),
+        );
+        return ret.render(&format);
+    }
+    let query = query_option.unwrap();
+
+    // Cover art ids are either song ids or album ids
+    let art = match queries::get_song_by_id(&state.pool, query.id).await {
+        Ok(Some(song)) => Some((song.art_source, song.art_path)),
+        Ok(None) => match queries::get_album_by_id(&state.pool, query.id).await {
+            Ok(album_option) => album_option.map(|album| (album.art_source, album.art_path)),
+            Err(err) => {
+                error!("Error fetching album: {}", err);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        },
+        Err(err) => {
+            error!("Error fetching song: {}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let (source, path) = match art {
+        Some((source, Some(path))) if source != cover_art::ART_NONE => (source, path),
+        _ => {
+            let ret: SubsonicResponse<ErrorResponse> =
+                SubsonicResponse::from_error_code(70, r#"cover art not found"#.to_string());
+            return ret.render(&format);
+        }
+    };
+
+    // A size of 0 asks for the original, like no size at all
+    let size = query
+        .size
+        .filter(|size| *size > 0)
+        .map(cover_art::thumbnail_size);
+    let modified = match size {
+        Some(_) => tokio::fs::metadata(&path)
+            .await
+            .and_then(|m| m.modified())
+            .ok(),
+        None => None,
+    };
+    let cached = size.zip(modified).map(|(size, modified)| {
+        cover_art::thumbnail_path(&state.config.cache_path, &path, modified, size)
+    });
+    if let Some(cached_path) = &cached {
+        if let Ok(bytes) = tokio::fs::read(cached_path).await {
+            return ([(header::CONTENT_TYPE, "image/jpeg")], bytes).into_response();
+        }
+    }
+
+    let art_result = tokio::task::spawn_blocking(move || {
+        let bytes = cover_art::read_art(&source, &path)?;
+        match size {
+            Some(size) => match cover_art::resize(&bytes, size, cached.as_deref()) {
+                Ok(resized) => Some(("image/jpeg", resized)),
+                Err(err) => {
+                    error!("Error resizing cover art {}: {}", path, err);
+                    None
+                }
+            },
+            None => Some((cover_art::mime_type(&bytes), bytes)),
+        }
+    })
+    .await;
+    match art_result {
+        Ok(Some((mime_type, bytes))) => {
+            ([(header::CONTENT_TYPE, mime_type)], bytes).into_response()
+        }
+        Ok(None) => {
+            let ret: SubsonicResponse<ErrorResponse> =
+                SubsonicResponse::from_error_code(70, r#"cover art not found"#.to_string());
+            ret.render(&format)
+        }
+        Err(err) => {
+            error!("Error loading cover art: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
 }
@@ -1,88 +1,463 @@
-use async_recursion::async_recursion;
-use log::{error, info};
-use std::fs;
+use chrono::{DateTime, SubsecRound, Utc};
+use crossbeam_channel::Sender;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use log::{debug, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::scan::{ScanCounters, ScanStage};
+use crate::Config;
+
+// How often the walk reports how far along it is
+const PROGRESS_EVERY: usize = 10_000;
+// gitignore-style patterns of what to leave out of the directory it's in and everything below
+pub const IGNORE_FILE: &str = ".soniccaveignore";
 
 pub enum TagType {
     Id3,
     Flac,
+    // Vorbis comments in an Ogg container, Vorbis, Opus or FLAC audio
+    Ogg,
+    // ilst atoms, AAC or ALAC audio
+    Mp4,
+    // APEv2, Monkey's Audio or WavPack audio
+    Ape,
 }
 
-#[async_recursion]
-pub async fn list(
-    path: &str,
-    indentation: usize,
-    percentage: bool,
-    files_on_db: &mut Vec<String>,
-) -> Vec<(String, TagType)> {
-    let mut ret = Vec::new();
-    let mut count = 0;
-    if percentage {
-        count = fs::read_dir(path).unwrap().count();
+/// Size and modification time, to tell whether a file changed since it was parsed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct FileState {
+    pub size: i64,
+    pub mtime: Option<DateTime<Utc>>,
+}
+
+impl FileState {
+    pub fn of(metadata: &fs::Metadata) -> Self {
+        Self {
+            size: metadata.len() as i64,
+            // Postgres keeps microseconds, so that's all we can compare
+            mtime: metadata
+                .modified()
+                .ok()
+                .map(|m| DateTime::<Utc>::from(m).trunc_subsecs(6)),
+        }
     }
-    let paths = fs::read_dir(path).unwrap();
-    let mut parsed = 0;
-    for item in paths.map(|p| p.unwrap()) {
-        let is_dir = item.file_type().unwrap().is_dir();
-        let path: String = item.path().into_os_string().into_string().unwrap();
-        let search_ret = files_on_db.binary_search(&path);
-        if let Ok(index) = search_ret {
-            files_on_db.remove(index);
-            continue;
+}
+
+/// A file the walk found that needs its tags read.
+pub struct FoundFile {
+    pub path: String,
+    pub state: FileState,
+    // Whether it's in the database already
+    pub known: bool,
+}
+
+pub struct ScannedFile {
+    pub path: String,
+    pub tag_type: TagType,
+    pub state: FileState,
+}
+
+/// What the walk leaves out: hidden files, the configured exclude patterns, whatever
+/// `.soniccaveignore` files say, and symbolic links unless they're to be followed.
+pub struct WalkRules {
+    // The configured exclude patterns, rooted at each music folder
+    folders: Vec<(PathBuf, Arc<Gitignore>)>,
+    hidden: bool,
+    follow_symlinks: bool,
+}
+
+impl WalkRules {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let mut folders = Vec::new();
+        for folder in &config.music_folders {
+            let mut builder = GitignoreBuilder::new(&folder.path);
+            for pattern in &config.scan_exclude {
+                builder
+                    .add_line(None, pattern)
+                    .map_err(|e| format!("Bad exclude pattern {}: {}", pattern, e))?;
+            }
+            let excludes = builder.build().map_err(|e| e.to_string())?;
+            folders.push((PathBuf::from(&folder.path), Arc::new(excludes)));
         }
-        if is_dir {
-            info!("Parsing directory {}", &path);
-            let inner = &mut list(&path, indentation + 2, false, files_on_db).await;
-            if !inner.is_empty() {
-                ret.append(inner);
+        Ok(Self {
+            folders,
+            hidden: config.scan_hidden,
+            follow_symlinks: config.follow_symlinks,
+        })
+    }
+
+    /// Whether `path` is left out. The closest ignore file with something to say about it
+    /// decides, so a `!pattern` deeper down can take back what's excluded above.
+    fn ignored(&self, ignores: &[Arc<Gitignore>], path: &Path, is_dir: bool) -> bool {
+        if !self.hidden && is_hidden(path) {
+            return true;
+        }
+        for ignore in ignores.iter().rev() {
+            match ignore.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+
+    /// The ignore rules in force inside `root`, None if `root` itself is left out. Roots can be
+    /// anywhere in a music folder, so the ignore files of the directories above them count too.
+    fn root_ignores(
+        &self,
+        root: &Path,
+        is_dir: bool,
+        counters: &ScanCounters,
+    ) -> Option<Vec<Arc<Gitignore>>> {
+        let (folder, excludes) = match self.folders.iter().find(|(f, _)| root.starts_with(f)) {
+            Some(f) => f,
+            None => return Some(Vec::new()),
+        };
+        // The configured patterns come first, any ignore file takes precedence over them
+        let mut ignores = vec![excludes.to_owned()];
+        let components: Vec<_> = root.strip_prefix(folder).ok()?.components().collect();
+        let mut path = folder.to_owned();
+        for (i, component) in components.iter().enumerate() {
+            ignores.extend(read_ignore_file(&path, counters));
+            path.push(component);
+            let last = i + 1 == components.len();
+            if self.ignored(&ignores, &path, !last || is_dir) {
+                return None;
             }
+        }
+        Some(ignores)
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+/// The directory's ignore file, if it has one. Patterns that don't parse are reported and left
+/// out, the rest of the file still applies.
+fn read_ignore_file(directory: &Path, counters: &ScanCounters) -> Option<Arc<Gitignore>> {
+    let path = directory.join(IGNORE_FILE);
+    if !path.is_file() {
+        return None;
+    }
+    let (ignore, err) = Gitignore::new(&path);
+    if let Some(err) = err {
+        counters.problem(ScanStage::Walk, &path.to_string_lossy(), err.to_string());
+    }
+    Some(Arc::new(ignore))
+}
+
+/// What a walk didn't find, and where it couldn't look.
+pub struct Walked {
+    // Files in the database that weren't found
+    pub missing: HashMap<String, FileState>,
+    // Directories and files that couldn't be read, whatever is below them may well still be there
+    pub unreadable: Vec<PathBuf>,
+    // One of the roots couldn't be read, or was empty as an unmounted drive's mount point is
+    pub root_unreadable: bool,
+}
+
+impl Walked {
+    /// The files that are really gone, None when a root couldn't be read and nothing can be said.
+    pub fn deleted(self) -> Option<Vec<String>> {
+        if self.root_unreadable {
+            return None;
+        }
+        Some(
+            self.missing
+                .into_keys()
+                .filter(|p| !self.unreadable.iter().any(|u| Path::new(p).starts_with(u)))
+                .collect(),
+        )
+    }
+}
+
+/// Walks `roots`, which can be directories or single files, and sends every file whose tags need
+/// to be read: new ones, changed ones, and on a `full` scan all of them. Sending blocks while the
+/// parsers are behind, so only a bounded number of paths is ever waiting in memory. Returns what's
+/// left of `files_on_db`, the files that weren't found, along with where the walk couldn't look.
+pub fn walk(
+    roots: Vec<PathBuf>,
+    // Files in the database, the ones found on disk are removed so only deleted files remain
+    mut files_on_db: HashMap<String, FileState>,
+    // Read the tags of unchanged files again too
+    full: bool,
+    rules: &WalkRules,
+    sender: Sender<FoundFile>,
+    counters: &ScanCounters,
+) -> Walked {
+    // Directories left to walk, with the ignore rules of the directories above them
+    let mut directories: Vec<(PathBuf, Vec<Arc<Gitignore>>)> = Vec::new();
+    // Where the directories walked really are, so links back up the tree don't go round forever.
+    // Without following links there's no way to come back to a directory
+    let mut walked: Option<HashSet<PathBuf>> = rules.follow_symlinks.then(HashSet::new);
+    let mut found: usize = 0;
+    let mut walked_roots: HashSet<PathBuf> = HashSet::new();
+    let mut result = Walked {
+        missing: HashMap::new(),
+        unreadable: Vec::new(),
+        root_unreadable: false,
+    };
+    for root in roots {
+        let metadata = match fs::metadata(&root) {
+            Ok(m) => m,
+            Err(err) => {
+                counters.problem(ScanStage::Walk, &root.to_string_lossy(), err.to_string());
+                result.root_unreadable = true;
+                continue;
+            }
+        };
+        let ignores = match rules.root_ignores(&root, metadata.is_dir(), counters) {
+            Some(i) => i,
+            None => {
+                debug!("Ignoring {}", root.display());
+                continue;
+            }
+        };
+        if metadata.is_dir() {
+            walked_roots.insert(root.to_owned());
+            directories.push((root, ignores));
             continue;
         }
-        if path.ends_with(".flac") {
-            if parse_flac(&path) {
-                ret.push((path, TagType::Flac));
-            } else if parse_id3(&path) {
-                ret.push((path, TagType::Id3));
-            } else {
-                error!("File {path} does not have a tag we can read");
+        found += 1;
+        if !visit(root, &metadata, &mut files_on_db, full, &sender, counters) {
+            result.missing = files_on_db;
+            return result;
+        }
+    }
+    while let Some((directory, mut ignores)) = directories.pop() {
+        if let Some(walked) = &mut walked {
+            match fs::canonicalize(&directory) {
+                Ok(real) if !walked.insert(real.to_owned()) => {
+                    info!(
+                        "Skipping {}, {} was walked already",
+                        directory.display(),
+                        real.display()
+                    );
+                    continue;
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    counters.problem(
+                        ScanStage::Walk,
+                        &directory.to_string_lossy(),
+                        err.to_string(),
+                    );
+                    result.unreadable.push(directory);
+                    continue;
+                }
+            }
+        }
+        debug!("Walking directory {}", directory.display());
+        counters.directory_walked();
+        ignores.extend(read_ignore_file(&directory, counters));
+        // Unreadable directories are skipped, along with everything in them
+        let is_root = walked_roots.contains(&directory);
+        let entries = match fs::read_dir(&directory) {
+            Ok(e) => e,
+            Err(err) => {
+                counters.problem(
+                    ScanStage::Walk,
+                    &directory.to_string_lossy(),
+                    err.to_string(),
+                );
+                result.root_unreadable |= is_root;
+                result.unreadable.push(directory);
+                continue;
+            }
+        };
+        let mut empty = true;
+        for entry in entries {
+            empty = false;
+            let entry = match entry {
+                Ok(e) => e,
+                Err(err) => {
+                    counters.problem(
+                        ScanStage::Walk,
+                        &directory.to_string_lossy(),
+                        err.to_string(),
+                    );
+                    // Which entry it was is anyone's guess
+                    result.unreadable.push(directory.to_owned());
+                    continue;
+                }
+            };
+            let path = entry.path();
+            let metadata = match entry.file_type() {
+                Ok(t) if t.is_symlink() && !rules.follow_symlinks => {
+                    debug!("Skipping symbolic link {}", path.display());
+                    continue;
+                }
+                // Whatever the link points to
+                Ok(t) if t.is_symlink() => fs::metadata(&path),
+                Ok(_) => entry.metadata(),
+                Err(err) => Err(err),
+            };
+            let metadata = match metadata {
+                Ok(m) => m,
+                Err(err) => {
+                    counters.problem(ScanStage::Walk, &path.to_string_lossy(), err.to_string());
+                    result.unreadable.push(path);
+                    continue;
+                }
+            };
+            if rules.ignored(&ignores, &path, metadata.is_dir()) {
+                debug!("Ignoring {}", path.display());
+                continue;
             }
-        } else {
-            if parse_id3(&path) {
-                ret.push((path, TagType::Id3));
-            } else if parse_flac(&path) {
-                ret.push((path, TagType::Flac));
-            } else {
-                error!("File {path} does not have a tag we can read");
+            if metadata.is_dir() {
+                directories.push((path, ignores.to_owned()));
+                continue;
+            }
+            found += 1;
+            if found.is_multiple_of(PROGRESS_EVERY) {
+                info!("Found {} files", found);
+            }
+            if !visit(path, &metadata, &mut files_on_db, full, &sender, counters) {
+                // The parsers are gone, which only happens when writing to the database failed
+                result.missing = files_on_db;
+                return result;
             }
         }
-        if percentage {
-            parsed += 1;
-            info!(
-                "Parsed {:.5}% of directories in parent",
-                parsed as f32 * 100_f32 / count as f32
+        if empty && is_root {
+            warn!(
+                "Music folder {} is empty, keeping its songs in case it isn't mounted",
+                directory.display()
             );
+            result.root_unreadable = true;
         }
     }
-    ret
+    info!("Found {} files", found);
+    result.missing = files_on_db;
+    result
+}
+
+/// Sends the file on unless it's unchanged since the last scan. False if nobody is listening.
+fn visit(
+    path: PathBuf,
+    metadata: &fs::Metadata,
+    files_on_db: &mut HashMap<String, FileState>,
+    full: bool,
+    sender: &Sender<FoundFile>,
+    counters: &ScanCounters,
+) -> bool {
+    let path = match path.into_os_string().into_string() {
+        Ok(p) => p,
+        Err(p) => {
+            counters.problem(
+                ScanStage::Walk,
+                &p.to_string_lossy(),
+                "the path isn't valid UTF-8".to_string(),
+            );
+            return true;
+        }
+    };
+    let state = FileState::of(metadata);
+    let known = match files_on_db.remove(&path) {
+        Some(db_state) if db_state != state => {
+            info!("{} changed since the last scan", path);
+            true
+        }
+        Some(_) if !full => return true,
+        Some(_) => true,
+        None => false,
+    };
+    sender.send(FoundFile { path, state, known }).is_ok()
+}
+
+/// Works out which kind of tag a file has, None if it isn't a format we read.
+pub fn scan_file(path: &str, state: FileState) -> io::Result<Option<ScannedFile>> {
+    let tag_type = match probe(path)? {
+        Some(t) => t,
+        None => {
+            debug!("Skipping {}, it isn't a format we can read", path);
+            return Ok(None);
+        }
+    };
+    Ok(Some(ScannedFile {
+        path: path.to_string(),
+        tag_type,
+        state,
+    }))
 }
 
-pub fn parse_flac(path: &String) -> bool {
-    let tag_result = metaflac::Tag::read_from_path(path);
-    match tag_result {
-        Ok(_) => true,
-        Err(_) => {
-            error!("File {path} does not have flac tags we can read");
-            false
+/// Tells the format from the first bytes of the file, whatever its name says.
+pub fn probe(path: &str) -> io::Result<Option<TagType>> {
+    let mut header = [0u8; 12];
+    let mut read = 0;
+    let mut file = File::open(path)?;
+    while read < header.len() {
+        match file.read(&mut header[read..])? {
+            0 => break,
+            n => read += n,
         }
     }
+    Ok(tag_type(&header[..read]))
+}
+
+/// The format a file starting with `header` is in, if it's one we read tags from.
+fn tag_type(header: &[u8]) -> Option<TagType> {
+    match header {
+        // Whatever follows an ID3v2 tag, that's where the tags are
+        [b'I', b'D', b'3', ..] => Some(TagType::Id3),
+        [b'f', b'L', b'a', b'C', ..] => Some(TagType::Flac),
+        [b'O', b'g', b'g', b'S', ..] => Some(TagType::Ogg),
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some(TagType::Mp4),
+        [b'M', b'A', b'C', b' ', ..] | [b'w', b'v', b'p', b'k', ..] => Some(TagType::Ape),
+        // An MPEG audio frame, an MP3 with its tag at the end if it has one. ADTS AAC shares the
+        // sync word but has 0 in the layer bits
+        [0xff, b, ..] if b & 0xe0 == 0xe0 && b & 0x06 != 0 => Some(TagType::Id3),
+        _ => None,
+    }
 }
 
-pub fn parse_id3(path: &String) -> bool {
-    let tag_result = id3::Tag::read_from_path(path);
-    match tag_result {
-        Ok(_) => true,
-        Err(_) => {
-            error!("File {path} does not have id3 tags we can read");
-            false
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walked(missing: &[&str], unreadable: &[&str], root_unreadable: bool) -> Walked {
+        let state = FileState {
+            size: 0,
+            mtime: None,
+        };
+        Walked {
+            missing: missing.iter().map(|p| (p.to_string(), state)).collect(),
+            unreadable: unreadable.iter().map(PathBuf::from).collect(),
+            root_unreadable,
         }
     }
+
+    #[test]
+    fn files_under_unreadable_directories_are_kept() {
+        let mut deleted = walked(
+            &["/music/a/1.mp3", "/music/b/2.mp3", "/music/bb/3.mp3"],
+            &["/music/b"],
+            false,
+        )
+        .deleted()
+        .unwrap();
+        deleted.sort();
+        assert_eq!(deleted, vec!["/music/a/1.mp3", "/music/bb/3.mp3"]);
+    }
+
+    #[test]
+    fn nothing_is_deleted_when_a_root_is_unreadable() {
+        assert!(walked(&["/music/a/1.mp3"], &[], true).deleted().is_none());
+    }
+
+    #[test]
+    fn adts_aac_is_not_taken_for_mp3() {
+        assert!(matches!(
+            tag_type(&[0xff, 0xfb, 0x90, 0x64]),
+            Some(TagType::Id3)
+        ));
+        assert!(tag_type(&[0xff, 0xf1, 0x50, 0x80]).is_none());
+    }
 }
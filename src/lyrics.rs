@@ -0,0 +1,378 @@
+use std::fs;
+use std::path::Path;
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use entities::lyrics::{Lyrics, LyricsLine, SongLyrics};
+use log::error;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::responses::format::ResponseFormat;
+use crate::responses::lyrics_response::{LyricsData, LyricsListResponse, LyricsResponse};
+use crate::responses::responses::{ErrorResponse, SubsonicResponse};
+use crate::DatabaseState;
+
+// What ID3 and LRC files use when they don't say which language
+pub const UNKNOWN_LANGUAGE: &str = "xxx";
+
+// Files next to a song, with the same name, that hold its lyrics. Like folder art, changes to them
+// alone don't make an incremental scan read the song again
+const SIDECAR_EXTENSIONS: [&str; 4] = ["lrc", "LRC", "txt", "TXT"];
+
+#[derive(Deserialize)]
+pub struct LyricsQuery {
+    #[serde(default)]
+    artist: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LyricsBySongIdQuery {
+    id: Uuid,
+}
+
+/// A three letter language code, `xxx` for anything else.
+pub fn language(lang: &str) -> String {
+    let lang = lang.trim().to_ascii_lowercase();
+    if lang.len() == 3 && lang.chars().all(|c| c.is_ascii_lowercase()) {
+        lang
+    } else {
+        UNKNOWN_LANGUAGE.to_string()
+    }
+}
+
+/// Lyrics from a tag or a file, synced when it has LRC timestamps and plain text otherwise.
+pub fn from_text(text: &str, lang: &str) -> Option<SongLyrics> {
+    let mut lang = language(lang);
+    let mut offset: i64 = 0;
+    let mut timed: Vec<(i64, String)> = Vec::new();
+    let mut plain: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let (stamps, rest) = timestamps(line);
+        if !stamps.is_empty() {
+            for stamp in stamps {
+                timed.push((stamp, rest.trim().to_string()));
+            }
+            continue;
+        }
+        // ID tags like `[ar:Artist]`, only some of which matter here
+        if let Some((key, value)) = id_tag(line) {
+            match key.as_str() {
+                "la" | "lang" => lang = language(value),
+                "offset" => offset = value.trim().parse().unwrap_or(0),
+                _ => {}
+            }
+            continue;
+        }
+        plain.push(line.trim_end().to_string());
+    }
+    if !timed.is_empty() {
+        timed.sort_by_key(|(start, _)| *start);
+        return Some(SongLyrics {
+            lang,
+            synced: true,
+            lines: timed
+                .into_iter()
+                .map(|(start, value)| LyricsLine {
+                    lyrics_id: Uuid::nil(),
+                    // A positive offset shows the lines earlier
+                    start: Some((start - offset).clamp(0, i32::MAX as i64) as i32),
+                    value,
+                })
+                .collect(),
+        });
+    }
+    while plain.last().is_some_and(|l| l.is_empty()) {
+        plain.pop();
+    }
+    let first = plain.iter().position(|l| !l.is_empty())?;
+    Some(SongLyrics {
+        lang,
+        synced: false,
+        lines: plain[first..]
+            .iter()
+            .map(|value| LyricsLine {
+                lyrics_id: Uuid::nil(),
+                start: None,
+                value: value.to_owned(),
+            })
+            .collect(),
+    })
+}
+
+/// Lyrics with a start time in milliseconds for every line, as ID3 SYLT frames have them.
+pub fn from_timed_lines(lines: &[(u32, String)], lang: &str) -> Option<SongLyrics> {
+    if lines.is_empty() {
+        return None;
+    }
+    Some(SongLyrics {
+        lang: language(lang),
+        synced: true,
+        lines: lines
+            .iter()
+            .map(|(start, value)| LyricsLine {
+                lyrics_id: Uuid::nil(),
+                start: Some((*start).min(i32::MAX as u32) as i32),
+                // Each piece of text usually starts with the line break that precedes it
+                value: value.trim_matches(['\n', '\r']).to_string(),
+            })
+            .collect(),
+    })
+}
+
+/// The `.lrc` and `.txt` files next to a song.
+pub fn find_sidecar_lyrics(path: &str) -> Vec<SongLyrics> {
+    let path = Path::new(path);
+    let mut ret = Vec::new();
+    for extension in SIDECAR_EXTENSIONS {
+        let sidecar = path.with_extension(extension);
+        if !sidecar.is_file() {
+            continue;
+        }
+        match fs::read(&sidecar) {
+            Ok(bytes) => {
+                let text = String::from_utf8_lossy(&bytes);
+                // Editors on Windows like to start files with a byte order mark
+                if let Some(lyrics) = from_text(text.trim_start_matches('\u{feff}'), "") {
+                    ret.push(lyrics);
+                }
+            }
+            Err(err) => error!("Error reading lyrics {}: {}", sidecar.display(), err),
+        }
+    }
+    ret
+}
+
+/// Adds lyrics unless the exact same ones are already there, as when a sidecar repeats the tag.
+pub fn push_unique(lyrics: &mut Vec<SongLyrics>, new: SongLyrics) {
+    if !lyrics
+        .iter()
+        .any(|l| l.synced == new.synced && l.lines == new.lines)
+    {
+        lyrics.push(new);
+    }
+}
+
+/// The `[mm:ss.xx]` timestamps a line starts with, in milliseconds, and the text after them.
+fn timestamps(line: &str) -> (Vec<i64>, &str) {
+    let mut stamps = Vec::new();
+    let mut rest = line.trim_start();
+    while let Some(inner) = rest.strip_prefix('[') {
+        let Some(end) = inner.find(']') else { break };
+        let Some(stamp) = parse_timestamp(&inner[..end]) else {
+            break;
+        };
+        stamps.push(stamp);
+        rest = &inner[end + 1..];
+    }
+    (stamps, rest)
+}
+
+fn parse_timestamp(stamp: &str) -> Option<i64> {
+    let (minutes, seconds) = stamp.split_once(':')?;
+    let minutes: i64 = minutes.trim().parse().ok()?;
+    // Some files use `mm:ss:xx`
+    let seconds = seconds.replacen(':', ".", 1);
+    let seconds: f64 = seconds.trim().parse().ok()?;
+    if minutes < 0 || !(0.0..60.0).contains(&seconds) {
+        return None;
+    }
+    Some(minutes * 60_000 + (seconds * 1000.0).round() as i64)
+}
+
+fn id_tag(line: &str) -> Option<(String, &str)> {
+    let inner = line.trim().strip_prefix('[')?.strip_suffix(']')?;
+    let (key, value) = inner.split_once(':')?;
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some((key.to_ascii_lowercase(), value))
+}
+
+/// Subsonic `getLyrics`, the plain text lyrics of the first song matching an artist and title.
+pub async fn get_lyrics(
+    State(state): State<DatabaseState>,
+    format: ResponseFormat,
+    query_option: Option<Query<LyricsQuery>>,
+) -> Response {
+    let (artist, title) = match query_option {
+        Some(Query(query)) => (query.artist, query.title),
+        None => (None, None),
+    };
+    if artist.is_none() && title.is_none() {
+        return SubsonicResponse::<LyricsResponse>::from_lyrics(LyricsData::default())
+            .render(&format);
+    }
+    let song = match queries::find_song_with_lyrics(
+        &state.pool,
+        artist.as_deref().filter(|a| !a.is_empty()),
+        title.as_deref().filter(|t| !t.is_empty()),
+    )
+    .await
+    {
+        Ok(Some(song)) => song,
+        Ok(None) => {
+            return SubsonicResponse::<LyricsResponse>::from_lyrics(LyricsData::default())
+                .render(&format)
+        }
+        Err(err) => {
+            error!("Error looking up lyrics: {}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let (lyrics, lines) = match song_lyrics(&state, song.id).await {
+        Ok(l) => l,
+        Err(response) => return response,
+    };
+    // Plain lyrics if there are any, the text of synced ones otherwise
+    let chosen = lyrics
+        .iter()
+        .find(|l| !l.synced)
+        .or(lyrics.first())
+        .map(|l| l.id);
+    let value = lines
+        .iter()
+        .filter(|line| Some(line.lyrics_id) == chosen)
+        .map(|line| line.value.as_str())
+        .collect::<Vec<&str>>()
+        .join("\n");
+    let artist = queries::get_artist_by_id(&state.pool, song.artist_id)
+        .await
+        .ok()
+        .flatten()
+        .map(|a| a.name);
+    SubsonicResponse::<LyricsResponse>::from_lyrics(LyricsData {
+        artist,
+        title: Some(song.title),
+        value: Some(value),
+    })
+    .render(&format)
+}
+
+/// OpenSubsonic `getLyricsBySongId`, every set of lyrics of a song with its lines.
+pub async fn get_lyrics_by_song_id(
+    State(state): State<DatabaseState>,
+    format: ResponseFormat,
+    query_option: Option<Query<LyricsBySongIdQuery>>,
+) -> Response {
+    if query_option.is_none() {
+        let ret: SubsonicResponse<ErrorResponse> = SubsonicResponse::from_error_code(
+            10,
+            r#"required parameter "id" is missing"#.to_string(),
+        );
+        return ret.render(&format);
+    }
+    let id = query_option.unwrap().id;
+    let song = match queries::get_song_by_id(&state.pool, id).await {
+        Ok(Some(song)) => song,
+        Ok(None) => {
+            let ret: SubsonicResponse<ErrorResponse> =
+                SubsonicResponse::from_error_code(70, r#"song not found"#.to_string());
+            return ret.render(&format);
+        }
+        Err(err) => {
+            error!("Error fetching song: {}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let (lyrics, lines) = match song_lyrics(&state, song.id).await {
+        Ok(l) => l,
+        Err(response) => return response,
+    };
+    let artist = queries::get_artist_by_id(&state.pool, song.artist_id)
+        .await
+        .ok()
+        .flatten()
+        .map(|a| a.name)
+        .unwrap_or_default();
+    SubsonicResponse::<LyricsListResponse>::from_structured_lyrics(
+        &artist,
+        &song.title,
+        lyrics,
+        lines,
+    )
+    .render(&format)
+}
+
+async fn song_lyrics(
+    state: &DatabaseState,
+    song_id: Uuid,
+) -> Result<(Vec<Lyrics>, Vec<LyricsLine>), Response> {
+    let lyrics = queries::get_lyrics_by_song_id(&state.pool, song_id)
+        .await
+        .map_err(|err| {
+            error!("Error fetching lyrics: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })?;
+    let ids: Vec<Uuid> = lyrics.iter().map(|l| l.id).collect();
+    let lines = queries::get_lyrics_lines(&state.pool, &ids)
+        .await
+        .map_err(|err| {
+            error!("Error fetching lyrics: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })?;
+    Ok((lyrics, lines))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn starts(lyrics: &SongLyrics) -> Vec<Option<i32>> {
+        lyrics.lines.iter().map(|l| l.start).collect()
+    }
+
+    fn values(lyrics: &SongLyrics) -> Vec<&str> {
+        lyrics.lines.iter().map(|l| l.value.as_str()).collect()
+    }
+
+    #[test]
+    fn lrc_lines_are_sorted_and_shifted_by_the_offset() {
+        let text =
+            "[ar:Someone]\n[la:ENG]\n[offset:500]\n[00:02.00][01:00:50]Chorus\n[00:01.25] Verse\n";
+        let lyrics = from_text(text, "").unwrap();
+        assert!(lyrics.synced);
+        assert_eq!(lyrics.lang, "eng");
+        assert_eq!(starts(&lyrics), vec![Some(750), Some(1500), Some(60000)]);
+        assert_eq!(values(&lyrics), vec!["Verse", "Chorus", "Chorus"]);
+    }
+
+    #[test]
+    fn plain_text_keeps_inner_blank_lines() {
+        let lyrics = from_text("\n\nFirst\n\nSecond  \n\n", "deu").unwrap();
+        assert!(!lyrics.synced);
+        assert_eq!(lyrics.lang, "deu");
+        assert_eq!(starts(&lyrics), vec![None, None, None]);
+        assert_eq!(values(&lyrics), vec!["First", "", "Second"]);
+        assert!(from_text("\n \n", "").is_none());
+    }
+
+    #[test]
+    fn unknown_languages_are_xxx() {
+        assert_eq!(language(" ENG "), "eng");
+        assert_eq!(language("en"), UNKNOWN_LANGUAGE);
+        assert_eq!(language("e1g"), UNKNOWN_LANGUAGE);
+    }
+
+    #[test]
+    fn timed_lines_lose_their_line_breaks() {
+        let lyrics =
+            from_timed_lines(&[(0, "First".into()), (1200, "\r\nSecond".into())], "").unwrap();
+        assert_eq!(lyrics.lang, UNKNOWN_LANGUAGE);
+        assert_eq!(starts(&lyrics), vec![Some(0), Some(1200)]);
+        assert_eq!(values(&lyrics), vec!["First", "Second"]);
+        assert!(from_timed_lines(&[], "eng").is_none());
+    }
+
+    #[test]
+    fn the_same_lyrics_are_kept_once() {
+        let mut lyrics = Vec::new();
+        push_unique(&mut lyrics, from_text("Line", "eng").unwrap());
+        push_unique(&mut lyrics, from_text("Line", "xxx").unwrap());
+        push_unique(&mut lyrics, from_text("[00:01.00]Line", "").unwrap());
+        assert_eq!(lyrics.len(), 2);
+    }
+}
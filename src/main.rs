@@ -1,92 +1,301 @@
 use std::fs;
 use std::net::Ipv4Addr;
-use std::str::FromStr;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
 
-use axum::body::Body;
-use axum::extract::{Query, State};
-use axum::http::{header, StatusCode};
-use axum::response::IntoResponse;
 use axum::{middleware, routing::get, Router};
-use clap::Parser;
-use log::{error, info};
+use clap::{Parser, Subcommand};
+use log::{error, info, warn};
 use serde::Deserialize;
-use sqlx::migrate::MigrateError;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{Pool, Postgres};
 
 use tokio::main;
-use tokio_util::io::ReaderStream;
 use tower_http::cors::CorsLayer;
 use uuid::Uuid;
 
-use crate::auth_middleware::auth_middleware;
+use crate::auth_middleware::{auth_middleware, role_middleware, Role};
+use crate::download::download;
 use crate::endpoint_handlers::{
-    create_update_playlist, get_album, get_albums, get_artist, get_artists, get_playlist,
-    get_playlists, search,
+    create_update_playlist, get_album, get_albums, get_artist, get_artists, get_cover_art,
+    get_music_folders, get_open_subsonic_extensions, get_playlist, get_playlists, get_random_songs,
+    ping, search,
 };
+use crate::explorer::WalkRules;
+use crate::lyrics::{get_lyrics, get_lyrics_by_song_id};
+use crate::password_cipher::PasswordCipher;
+use crate::scan::{get_scan_errors, get_scan_report, get_scan_status, start_scan, ScanState};
+use crate::stream::get_stream;
+use crate::transcoding::TranscodingProfile;
+use crate::users::{change_password, create_user, delete_user, get_user, get_users, update_user};
 
+mod ape;
 mod auth_middleware;
+mod cli;
+mod cover_art;
 mod database_sync;
+mod download;
 mod endpoint_handlers;
 mod explorer;
+mod lyrics;
+mod password_cipher;
 mod responses;
+mod scan;
+mod stream;
 mod tag_parser;
-
-async fn sync(connection: &mut Pool<Postgres>, path: &str) {
-    info!("Gathering paths");
-    let song_paths_ret = queries::get_song_paths(connection).await;
-    if let Err(e) = song_paths_ret {
-        error!("There was an error reading from the database. {e}");
-        return;
-    }
-    let mut song_paths = song_paths_ret.unwrap();
-    song_paths.sort();
-    let list = explorer::list(path, 0, true, &mut song_paths).await;
-    info!("Parsing tags");
-    let hashmap_result = tag_parser::parse(list);
-
-    let hashmap = match hashmap_result {
-        Ok(h) => h,
-        Err(_) => {
-            error!("Failed to parse tags");
-            return;
-        }
-    };
-    info!("Syncing database");
-    let ret = database_sync::sync_database(hashmap, &song_paths, connection).await;
-    match ret {
-        Ok(_) => {}
-        Err(error) => {
-            error!("{}", error);
-        }
-    }
-}
+mod transcoding;
+mod users;
+mod watcher;
+mod zip_stream;
 
 #[derive(Clone)]
 pub struct DatabaseState {
     pool: Pool<Postgres>,
+    config: Arc<Config>,
+    scan: Arc<ScanState>,
+    cipher: Arc<PasswordCipher>,
 }
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(long, short, default_value_t = 3)]
+    #[arg(long, short, default_value_t = 3, global = true)]
     verbosity: usize,
-    #[arg(long, short, default_value_t = false)]
+    #[arg(long, short, default_value_t = false, global = true)]
     quiet: bool,
     #[arg(long, short)]
     config: String,
+    // Serves when no command is given
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run migrations and serve the API
+    Serve,
+    /// Scan the music folders and exit
+    Scan {
+        /// Read the tags of every file again, not only new ones
+        #[arg(long, default_value_t = false)]
+        full: bool,
+    },
+    /// List the files the last scans couldn't read
+    ScanErrors {
+        /// Only errors from this stage of the scan: walk, probe, tag or insert
+        #[arg(long)]
+        stage: Option<String>,
+    },
+    /// Run database migrations and exit
+    Migrate,
+    /// Manage users
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Manage the API keys OpenSubsonic clients log in with
+    ApiKey {
+        #[command(subcommand)]
+        command: ApiKeyCommand,
+    },
+    /// Inspect playlists
+    Playlist {
+        #[command(subcommand)]
+        command: PlaylistCommand,
+    },
+    /// Manage the key stored passwords are encrypted with
+    Key {
+        #[command(subcommand)]
+        command: KeyCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Create a user, the password is read from stdin unless given
+    Add {
+        username: String,
+        #[arg(long)]
+        password: Option<String>,
+        /// Give the user every role
+        #[arg(long, default_value_t = false)]
+        admin: bool,
+    },
+    /// Change a user's password, read from stdin unless given
+    Passwd {
+        username: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// List users
+    List,
+    /// Delete a user
+    Delete { username: String },
+}
+
+#[derive(Subcommand)]
+enum ApiKeyCommand {
+    /// Create a key for a user and print it, it can't be shown again
+    Add {
+        username: String,
+        /// What the key is for, like the client it's given to
+        #[arg(long)]
+        name: String,
+    },
+    /// List a user's keys
+    List { username: String },
+    /// Revoke one of a user's keys
+    Revoke { username: String, id: Uuid },
+}
+
+#[derive(Subcommand)]
+enum PlaylistCommand {
+    /// List playlists
+    List,
+}
+
+#[derive(Subcommand)]
+enum KeyCommand {
+    /// Re-encrypt stored passwords with a newly generated key. Replaces the configured key file,
+    /// or writes the new key to `--new-key-file` for the configuration to point to afterwards.
+    Rotate {
+        #[arg(long)]
+        new_key_file: Option<String>,
+    },
 }
 
 #[derive(Deserialize)]
 struct Config {
     port: i32,
-    path: String,
+    // A single music folder, as configurations from before `music_folders` have it
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    music_folders: Vec<MusicFolderConfig>,
     postgres: String,
+    // Where resized cover art thumbnails are kept
+    #[serde(default = "default_cache_path")]
+    cache_path: String,
+    #[serde(default)]
+    transcoding: Vec<TranscodingProfile>,
+    // Whether clients may send the password itself (`p=`) instead of a salted token
+    #[serde(default = "default_true")]
+    allow_password_auth: bool,
+    // Keeps the old unauthenticated `/search`, `/playlist`, `/playlists` and `/startScan` routes
+    #[serde(default)]
+    legacy_public_routes: bool,
+    // Secret user passwords are encrypted with, takes precedence over `password_key_file`
+    #[serde(default)]
+    password_key: Option<String>,
+    // Generated on first start if it doesn't exist
+    #[serde(default = "default_password_key_file")]
+    password_key_file: String,
+    // Pick up changes in the music folder as they happen instead of waiting for a scan
+    #[serde(default)]
+    watch: bool,
+    #[serde(default = "default_watch_debounce_ms")]
+    watch_debounce_ms: u64,
+    // Threads reading tags during a scan, 0 for one per core
+    #[serde(default)]
+    scan_workers: usize,
+    // Songs written to the database at a time during a scan
+    #[serde(default = "default_scan_batch_size")]
+    scan_batch_size: usize,
+    // gitignore-style patterns left out of every music folder, on top of `.soniccaveignore` files
+    #[serde(default = "default_scan_exclude")]
+    scan_exclude: Vec<String>,
+    // Scan files and directories whose name starts with a dot
+    #[serde(default)]
+    scan_hidden: bool,
+    // Follow symbolic links instead of skipping them, directories already walked are skipped
+    #[serde(default)]
+    follow_symlinks: bool,
+    // What splits a single artist or genre tag value into several
+    #[serde(default = "default_artist_separators")]
+    artist_separators: Vec<String>,
+    #[serde(default = "default_genre_separators")]
+    genre_separators: Vec<String>,
+}
+
+#[derive(Deserialize, Clone)]
+struct MusicFolderConfig {
+    name: String,
+    path: String,
+}
+
+impl Config {
+    /// Folds the legacy `path` into `music_folders` and checks the folders make sense: there's at
+    /// least one, and none of them is inside another, so every song belongs to exactly one.
+    fn normalize_music_folders(&mut self) -> Result<(), String> {
+        if let Some(path) = self.path.take() {
+            if self.music_folders.iter().all(|f| f.path != path) {
+                self.music_folders.push(MusicFolderConfig {
+                    name: "Music".to_string(),
+                    path,
+                });
+            }
+        }
+        if self.music_folders.is_empty() {
+            return Err("No music folders configured".to_string());
+        }
+        for folder in &mut self.music_folders {
+            if folder.path.len() > 1 {
+                folder.path = folder.path.trim_end_matches('/').to_string();
+            }
+        }
+        for (i, folder) in self.music_folders.iter().enumerate() {
+            for other in &self.music_folders[i + 1..] {
+                if Path::new(&folder.path).starts_with(&other.path)
+                    || Path::new(&other.path).starts_with(&folder.path)
+                {
+                    return Err(format!(
+                        "Music folders {} and {} overlap",
+                        folder.path, other.path
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_watch_debounce_ms() -> u64 {
+    2000
+}
+
+fn default_scan_batch_size() -> usize {
+    500
+}
+
+fn default_scan_exclude() -> Vec<String> {
+    // Thumbnails and metadata Synology NASes leave in every directory
+    vec!["@eaDir/".to_string()]
+}
+
+fn default_artist_separators() -> Vec<String> {
+    // Not `/`, it's part of too many names
+    vec![";".to_string()]
+}
+
+fn default_genre_separators() -> Vec<String> {
+    vec![";".to_string(), "/".to_string()]
+}
+
+fn default_password_key_file() -> String {
+    "password.key".to_string()
+}
+
+fn default_cache_path() -> String {
+    "cache".to_string()
 }
 
 #[main]
-async fn main() -> Result<(), sqlx::Error> {
+async fn main() -> ExitCode {
     let args = Args::parse();
     stderrlog::new()
         .verbosity(args.verbosity)
@@ -99,71 +308,202 @@ async fn main() -> Result<(), sqlx::Error> {
     let config_string_result = fs::read_to_string(args.config);
     if let Err(err) = config_string_result {
         error!("Error opening configuration file: {}", err);
-        return Ok(());
+        return ExitCode::FAILURE;
     }
     let config_string = config_string_result.unwrap();
     let config_result = serde_json::from_str(config_string.as_str());
     if let Err(err) = config_result {
         error!("Malformed configuration: {}", err);
-        return Ok(());
+        return ExitCode::FAILURE;
+    }
+    let mut config: Config = config_result.unwrap();
+    if let Err(err) = config
+        .normalize_music_folders()
+        .and_then(|_| WalkRules::from_config(&config).map(|_| ()))
+    {
+        error!("Malformed configuration: {}", err);
+        return ExitCode::FAILURE;
     }
-    let config: Config = config_result.unwrap();
+    let config: Arc<Config> = Arc::new(config);
     let pool_result = PgPoolOptions::new()
         .max_connections(5)
         .connect(config.postgres.to_owned().as_str())
         .await;
     if let Err(err) = pool_result {
         error!("Error connecting to database: {}", err);
-        return Ok(());
+        return ExitCode::FAILURE;
+    }
+    let pool = pool_result.unwrap();
+
+    let result = match args.command.unwrap_or(Command::Serve) {
+        Command::Serve => match migrate(&pool, &config).await {
+            Ok(cipher) => {
+                serve(pool, config, cipher).await;
+                Ok(())
+            }
+            Err(err) => Err(format!("There was an error runing migrations: {err}")),
+        },
+        Command::Migrate => migrate(&pool, &config).await.map(|_| ()),
+        Command::Scan { full } => scan::scan_now(&pool, &config, full).await,
+        Command::ScanErrors { stage } => cli::scan_errors(&pool, stage).await,
+        Command::User { command } => match command {
+            UserCommand::Add {
+                username,
+                password,
+                admin,
+            } => cli::user_add(&pool, &config, username, password, admin).await,
+            UserCommand::Passwd { username, password } => {
+                cli::user_passwd(&pool, &config, username, password).await
+            }
+            UserCommand::List => cli::user_list(&pool).await,
+            UserCommand::Delete { username } => cli::user_delete(&pool, username).await,
+        },
+        Command::ApiKey { command } => match command {
+            ApiKeyCommand::Add { username, name } => cli::api_key_add(&pool, username, name).await,
+            ApiKeyCommand::List { username } => cli::api_key_list(&pool, username).await,
+            ApiKeyCommand::Revoke { username, id } => {
+                cli::api_key_revoke(&pool, username, id).await
+            }
+        },
+        Command::Playlist { command } => match command {
+            PlaylistCommand::List => cli::playlist_list(&pool).await,
+        },
+        Command::Key { command } => match command {
+            KeyCommand::Rotate { new_key_file } => {
+                cli::key_rotate(&pool, &config, new_key_file).await
+            }
+        },
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{}", err);
+            ExitCode::FAILURE
+        }
     }
-    let mut pool = pool_result.unwrap();
+}
+
+async fn serve(pool: Pool<Postgres>, config: Arc<Config>, cipher: PasswordCipher) {
     let state = DatabaseState {
         pool: pool.to_owned(),
+        config: config.to_owned(),
+        cipher: Arc::new(cipher),
+        scan: Arc::new(ScanState::default()),
     };
-
-    info!("Running migrations...");
-    let migration_result = migrate(&pool).await;
-    if let Err(e) = migration_result {
-        error!("There was an error runing migrations: {e}");
+    if config.watch {
+        if let Err(err) = watcher::start(state.to_owned()) {
+            error!("{}", err);
+        }
     }
+
     // build our application with a single route
 
     let authenticated: Router = Router::new()
         // Root
         .route("/", get(|| async { "Hello, World!" }))
+        .route("/ping", get(ping))
         // Stream
-        .route("/stream", get(get_stream))
+        .route(
+            "/stream",
+            get(get_stream).layer(middleware::from_fn_with_state(
+                Role::Stream,
+                role_middleware,
+            )),
+        )
+        .route(
+            "/download",
+            get(download).layer(middleware::from_fn_with_state(
+                Role::Download,
+                role_middleware,
+            )),
+        )
+        .route("/getMusicFolders", get(get_music_folders))
         .route("/getArtists", get(get_artists))
         .route("/getArtist", get(get_artist))
         .route("/search3", get(search))
         .route("/getAlbumList2", get(get_albums))
         .route("/getAlbum", get(get_album))
+        .route("/getRandomSongs", get(get_random_songs))
+        .route(
+            "/getCoverArt",
+            get(get_cover_art).layer(middleware::from_fn_with_state(
+                Role::CoverArt,
+                role_middleware,
+            )),
+        )
+        .route("/getLyrics", get(get_lyrics))
+        .route("/getLyricsBySongId", get(get_lyrics_by_song_id))
         .route("/getPlaylists", get(get_playlists))
         .route("/getPlaylist", get(get_playlist))
-        .route("/createPlaylist", get(create_update_playlist))
+        .route(
+            "/createPlaylist",
+            get(create_update_playlist).layer(middleware::from_fn_with_state(
+                Role::Playlist,
+                role_middleware,
+            )),
+        )
+        .route("/getUser", get(get_user))
+        .route(
+            "/getUsers",
+            get(get_users).layer(middleware::from_fn_with_state(Role::Admin, role_middleware)),
+        )
+        .route(
+            "/createUser",
+            get(create_user).layer(middleware::from_fn_with_state(Role::Admin, role_middleware)),
+        )
+        .route(
+            "/updateUser",
+            get(update_user).layer(middleware::from_fn_with_state(Role::Admin, role_middleware)),
+        )
+        .route(
+            "/deleteUser",
+            get(delete_user).layer(middleware::from_fn_with_state(Role::Admin, role_middleware)),
+        )
+        .route("/changePassword", get(change_password))
+        .route(
+            "/startScan",
+            get(start_scan).layer(middleware::from_fn_with_state(Role::Admin, role_middleware)),
+        )
+        .route("/getScanStatus", get(get_scan_status))
         .layer(CorsLayer::permissive())
         .layer(middleware::from_fn_with_state(
             state.to_owned(),
             auth_middleware,
         ))
         .with_state(state.to_owned());
-    let app: Router = Router::new()
+    // Administrative API, same authentication as `/rest` but only for admins
+    let admin: Router = Router::new()
         .route("/search", get(search))
         .route("/playlist", get(create_update_playlist))
         .route("/playlists", get(get_playlists))
-        // StartScan
+        .route("/startScan", get(start_scan))
+        .route("/scanStatus", get(get_scan_report))
+        .route("/scanErrors", get(get_scan_errors))
+        .layer(middleware::from_fn_with_state(Role::Admin, role_middleware))
+        .layer(middleware::from_fn_with_state(
+            state.to_owned(),
+            auth_middleware,
+        ))
+        .with_state(state.to_owned());
+    let mut app: Router = Router::new()
+        // OpenSubsonic requires this one to work without authentication
         .route(
-            "/startScan",
-            get(|| async {
-                tokio::spawn(async move {
-                    // `move` makes the closure take ownership of `slf`
-                    sync(&mut pool, config.path.as_str()).await;
-                });
-                "Syncing. Check console output!"
-            }),
+            "/rest/getOpenSubsonicExtensions",
+            get(get_open_subsonic_extensions),
         )
         .with_state(state.to_owned())
-        .nest("/rest", authenticated);
+        .nest("/rest", authenticated)
+        .nest("/admin", admin);
+    if config.legacy_public_routes {
+        warn!("Serving /search, /playlist, /playlists and /startScan without authentication");
+        let public: Router = Router::new()
+            .route("/search", get(search))
+            .route("/playlist", get(create_update_playlist))
+            .route("/playlists", get(get_playlists))
+            .route("/startScan", get(start_scan))
+            .with_state(state.to_owned());
+        app = app.merge(public);
+    }
 
     // Welcome messages
     info!(
@@ -204,66 +544,17 @@ async fn main() -> Result<(), sqlx::Error> {
     .await
     .unwrap();
     axum::serve(listener, app).await.unwrap();
-    Ok(())
-}
-
-#[derive(Deserialize)]
-struct IdQuery {
-    id: Uuid,
 }
 
-#[axum::debug_handler]
-async fn get_stream(
-    query: Option<Query<IdQuery>>,
-    State(state): State<DatabaseState>,
-) -> impl IntoResponse {
-    if query.is_none() {
-        return Err((
-            StatusCode::NOT_FOUND,
-            "No id of resource provided".to_string(),
-        ));
-    }
-    let id = query.unwrap().id;
-
-    let song_result = queries::get_song_by_id(&state.pool, id).await;
-
-    if song_result.is_err() {
-        return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Error connecting to database".to_string(),
-        ));
-    }
-    let song_option = song_result.unwrap();
-    if song_option.is_none() {
-        return Err((
-            StatusCode::NOT_FOUND,
-            "No song matching provided id".to_string(),
-        ));
-    }
-    let song = song_option.unwrap();
-    info!("Streaming song {} with id {}", song.title, song.id);
-
-    // `File` implements `AsyncRead`
-    let file = match tokio::fs::File::open(song.path).await {
-        Ok(file) => file,
-        Err(err) => return Err((StatusCode::NOT_FOUND, format!("File not found: {}", err))),
-    };
-    // convert the `AsyncRead` into a `Stream`
-    let stream = ReaderStream::new(file);
-    // convert the `Stream` into an `axum::body::HttpBody`
-    let body = Body::from_stream(stream);
-
-    let headers = [
-        (header::X_CONTENT_TYPE_OPTIONS, "no-sniff"),
-        (
-            header::HeaderName::from_str("X-Content-Duration").unwrap(),
-            "0.0",
-        ),
-    ];
-
-    Ok((headers, body))
-}
-
-async fn migrate(pool: &Pool<Postgres>) -> Result<(), MigrateError> {
-    sqlx::migrate!("./migrations").run(pool).await
+/// Runs the migrations, then encrypts any passwords still stored in plaintext.
+async fn migrate(pool: &Pool<Postgres>, config: &Config) -> Result<PasswordCipher, String> {
+    let cipher = PasswordCipher::from_config(config)?;
+    info!("Running migrations...");
+    sqlx::migrate!("./migrations")
+        .run(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    password_cipher::encrypt_existing(pool, &cipher).await?;
+    scan::sync_music_folders(pool, config).await?;
+    Ok(cipher)
 }
@@ -0,0 +1,174 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use log::info;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::Config;
+
+// Marks stored passwords that are encrypted, so plaintext rows can be told apart
+const PREFIX: &str = "aes256gcm:";
+const NONCE_LENGTH: usize = 12;
+// blake3 context used to turn the configured secret into a 256 bit key
+const KEY_CONTEXT: &str = "soniccave 2025-04 user password encryption";
+
+/// Encrypts user passwords for storage. Token authentication needs the plaintext password, so
+/// they can't be hashed, but at least a database dump without the key is useless.
+pub struct PasswordCipher {
+    cipher: Aes256Gcm,
+}
+
+impl PasswordCipher {
+    pub fn from_secret(secret: &str) -> Self {
+        let key = blake3::derive_key(KEY_CONTEXT, secret.trim().as_bytes());
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        match &config.password_key {
+            Some(secret) if !secret.trim().is_empty() => Ok(Self::from_secret(secret)),
+            _ => Self::from_key_file(&config.password_key_file),
+        }
+    }
+
+    /// Reads the secret from `path`, generating a new one there if the file doesn't exist yet.
+    pub fn from_key_file(path: &str) -> Result<Self, String> {
+        if !Path::new(path).exists() {
+            info!("Generating password key file {}", path);
+            write_key_file(path, &generate_secret())?;
+        }
+        let secret = fs::read_to_string(path)
+            .map_err(|e| format!("Error reading password key file {}: {}", path, e))?;
+        if secret.trim().is_empty() {
+            return Err(format!("Password key file {} is empty", path));
+        }
+        Ok(Self::from_secret(&secret))
+    }
+
+    pub fn encrypt(&self, password: &str) -> Result<String, String> {
+        // An empty password means token auth is off for the user, there's nothing to hide
+        if password.is_empty() {
+            return Ok(String::new());
+        }
+        let nonce_bytes: [u8; NONCE_LENGTH] = rand::random();
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce_bytes), password.as_bytes())
+            .map_err(|e| format!("Error encrypting password: {}", e))?;
+        let mut stored = nonce_bytes.to_vec();
+        stored.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", PREFIX, to_hex(&stored)))
+    }
+
+    pub fn decrypt(&self, stored: &str) -> Result<String, String> {
+        if stored.is_empty() {
+            return Ok(String::new());
+        }
+        let hex = stored
+            .strip_prefix(PREFIX)
+            .ok_or("Stored password isn't encrypted")?;
+        let bytes = from_hex(hex).ok_or("Stored password is malformed")?;
+        if bytes.len() < NONCE_LENGTH {
+            return Err("Stored password is malformed".to_string());
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Stored password can't be decrypted with this key".to_string())?;
+        String::from_utf8(plaintext).map_err(|e| e.to_string())
+    }
+}
+
+pub fn is_encrypted(stored: &str) -> bool {
+    stored.is_empty() || stored.starts_with(PREFIX)
+}
+
+pub fn generate_secret() -> String {
+    let bytes: [u8; 32] = rand::random();
+    to_hex(&bytes)
+}
+
+/// Writes a new key file, readable only by the owner. Refuses to overwrite an existing one.
+pub fn write_key_file(path: &str, secret: &str) -> Result<(), String> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("Error creating password key file {}: {}", path, e))?;
+    writeln!(file, "{}", secret)
+        .map_err(|e| format!("Error writing password key file {}: {}", path, e))
+}
+
+/// One-time migration for databases from before passwords were encrypted: encrypts every row
+/// that is still in plaintext. Rows that are already encrypted are left alone.
+pub async fn encrypt_existing(
+    pool: &Pool<Postgres>,
+    cipher: &PasswordCipher,
+) -> Result<usize, String> {
+    let users = queries::get_users(pool).await.map_err(|e| e.to_string())?;
+    let mut ids: Vec<Uuid> = Vec::new();
+    let mut passwords: Vec<String> = Vec::new();
+    for user in users.iter().filter(|u| !is_encrypted(&u.password)) {
+        ids.push(user.id);
+        passwords.push(cipher.encrypt(&user.password)?);
+    }
+    if !ids.is_empty() {
+        queries::update_user_passwords(pool, &ids, &passwords)
+            .await
+            .map_err(|e| e.to_string())?;
+        info!("Encrypted the stored passwords of {} users", ids.len());
+    }
+    Ok(ids.len())
+}
+
+/// Re-encrypts every stored password from the old key to the new one, in a single statement so
+/// it's all or nothing.
+pub async fn reencrypt(
+    pool: &Pool<Postgres>,
+    old: &PasswordCipher,
+    new: &PasswordCipher,
+) -> Result<usize, String> {
+    let users = queries::get_users(pool).await.map_err(|e| e.to_string())?;
+    let mut ids: Vec<Uuid> = Vec::new();
+    let mut passwords: Vec<String> = Vec::new();
+    for user in &users {
+        let password = if is_encrypted(&user.password) {
+            old.decrypt(&user.password)
+                .map_err(|e| format!("User {}: {}", user.username, e))?
+        } else {
+            user.password.to_owned()
+        };
+        ids.push(user.id);
+        passwords.push(new.encrypt(&password)?);
+    }
+    queries::update_user_passwords(pool, &ids, &passwords)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(ids.len())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
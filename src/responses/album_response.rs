@@ -1,8 +1,16 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
+use entities::artist::SongArtist;
+use entities::genre::{Genre, SongGenre};
+use entities::song::SongSqlxModel;
 use entities::{album::Album, artist::Artist, song::Song};
 use serde::Serialize;
 use uuid::Uuid;
 
+use crate::cover_art::cover_art_id;
+use crate::transcoding::{default_profile, TranscodingProfile};
+
 #[derive(Serialize, Clone)]
 pub struct AlbumResponse {
     pub(crate) status: String,
@@ -14,7 +22,15 @@ pub struct AlbumResponse {
 }
 
 impl AlbumResponse {
-    pub fn from_album(artist: Artist, album: Album, songs: Vec<Song>) -> Self {
+    pub fn from_album(
+        artist: Artist,
+        album: Album,
+        genres: Vec<Genre>,
+        songs: Vec<Song>,
+        track_artists: &[Artist],
+        links: &SongLinks,
+        transcoding: &[TranscodingProfile],
+    ) -> Self {
         let mut duration = 0;
         let genre = songs[0].genre.to_owned();
         let songs_vec = songs
@@ -22,30 +38,49 @@ impl AlbumResponse {
             .into_iter()
             .map(|i| {
                 duration += i.duration;
+                let transcoded = default_profile(transcoding, &i.path);
+                let track_artist = track_artists
+                    .iter()
+                    .find(|a| a.id == i.artist_id)
+                    .unwrap_or(&artist);
                 SongResponseData {
                     id: i.id,
                     parent: i.album_id,
                     is_dir: false,
                     title: i.title.to_string(),
                     album: album.name.to_string(),
-                    artist: artist.name.to_string(),
+                    artist: track_artist.name.to_string(),
                     track: i.track,
                     year: album.year,
-                    genre: genre.to_string(),
-                    cover_art: album.id.to_string(),
-                    size: 0,
+                    genre: i.genre.to_owned(),
+                    cover_art: cover_art_id(i.id, &i.art_source),
+                    size: i.size,
                     content_type: i.content_type,
                     suffix: i.suffix,
                     duration: i.duration,
-                    bit_rate: 0,
+                    bit_rate: i.bit_rate,
                     path: i.path,
                     play_count: 0,
                     disc_number: 0,
                     created: Utc::now(),
                     album_id: album.id,
-                    artist_id: artist.id,
+                    artist_id: track_artist.id,
                     r#type: "audio".to_string(),
                     is_video: false,
+                    transcoded_suffix: transcoded.map(|p| p.to.to_owned()),
+                    transcoded_content_type: transcoded.map(|p| p.content_type.to_owned()),
+                    artists: links.artists_of(i.id, track_artist.id, &track_artist.name),
+                    genres: links.genres_of(i.id),
+                    musicbrainz_id: i.musicbrainz_id,
+                    replay_gain: ReplayGain::new(
+                        i.track_gain,
+                        i.track_peak,
+                        i.album_gain,
+                        i.album_peak,
+                    ),
+                    sampling_rate: i.sample_rate,
+                    bit_depth: i.bit_depth,
+                    channel_count: i.channels,
                 }
             })
             .collect();
@@ -54,13 +89,18 @@ impl AlbumResponse {
             name: album.name,
             artist: artist.name,
             artist_id: artist.id,
-            cover_art: "".to_string(),
+            cover_art: cover_art_id(album.id, &album.art_source),
             song_count: songs.to_owned().len() as i32,
             duration,
             play_count: 0,
             created: Utc::now(),
             year: album.year,
             genre,
+            genres: genres
+                .into_iter()
+                .map(|g| ItemGenre { name: g.name })
+                .collect(),
+            musicbrainz_id: album.musicbrainz_id,
             song: songs_vec,
         };
         Self {
@@ -80,8 +120,8 @@ pub struct AlbumResponseData {
     pub(crate) artist: String,
     #[serde(rename = "artistId")]
     pub(crate) artist_id: Uuid,
-    #[serde(rename = "coverArt")]
-    pub(crate) cover_art: String,
+    #[serde(rename = "coverArt", skip_serializing_if = "Option::is_none")]
+    pub(crate) cover_art: Option<String>,
     #[serde(rename = "song_count")]
     pub(crate) song_count: i32,
     pub(crate) duration: i32,
@@ -90,6 +130,9 @@ pub struct AlbumResponseData {
     pub(crate) created: DateTime<Utc>,
     pub(crate) year: i32,
     pub(crate) genre: String,
+    pub(crate) genres: Vec<ItemGenre>,
+    #[serde(rename = "musicBrainzId", skip_serializing_if = "Option::is_none")]
+    pub(crate) musicbrainz_id: Option<String>,
     pub(crate) song: Vec<SongResponseData>,
 }
 
@@ -105,8 +148,8 @@ pub struct SongResponseData {
     pub(crate) track: i32,
     pub(crate) year: i32,
     pub(crate) genre: String,
-    #[serde(rename = "coverArt")]
-    pub(crate) cover_art: String,
+    #[serde(rename = "coverArt", skip_serializing_if = "Option::is_none")]
+    pub(crate) cover_art: Option<String>,
     pub(crate) size: i64,
     #[serde(rename = "contentType")]
     pub(crate) content_type: String,
@@ -127,4 +170,165 @@ pub struct SongResponseData {
     pub(crate) r#type: String,
     #[serde(rename = "isVideo")]
     pub(crate) is_video: bool,
+    #[serde(rename = "transcodedSuffix", skip_serializing_if = "Option::is_none")]
+    pub(crate) transcoded_suffix: Option<String>,
+    #[serde(
+        rename = "transcodedContentType",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) transcoded_content_type: Option<String>,
+    // OpenSubsonic, every artist and genre of the song where `artist` and `genre` have the first
+    pub(crate) artists: Vec<ArtistRef>,
+    pub(crate) genres: Vec<ItemGenre>,
+    #[serde(rename = "musicBrainzId", skip_serializing_if = "Option::is_none")]
+    pub(crate) musicbrainz_id: Option<String>,
+    #[serde(rename = "replayGain", skip_serializing_if = "Option::is_none")]
+    pub(crate) replay_gain: Option<ReplayGain>,
+    #[serde(rename = "samplingRate")]
+    pub(crate) sampling_rate: i32,
+    #[serde(rename = "bitDepth")]
+    pub(crate) bit_depth: i32,
+    #[serde(rename = "channelCount")]
+    pub(crate) channel_count: i32,
+}
+
+/// OpenSubsonic `replayGain`, left out for songs whose tags have none.
+#[derive(Serialize, Clone)]
+pub struct ReplayGain {
+    #[serde(rename = "trackGain", skip_serializing_if = "Option::is_none")]
+    pub(crate) track_gain: Option<f64>,
+    #[serde(rename = "trackPeak", skip_serializing_if = "Option::is_none")]
+    pub(crate) track_peak: Option<f64>,
+    #[serde(rename = "albumGain", skip_serializing_if = "Option::is_none")]
+    pub(crate) album_gain: Option<f64>,
+    #[serde(rename = "albumPeak", skip_serializing_if = "Option::is_none")]
+    pub(crate) album_peak: Option<f64>,
+}
+
+impl ReplayGain {
+    fn new(
+        track_gain: Option<f64>,
+        track_peak: Option<f64>,
+        album_gain: Option<f64>,
+        album_peak: Option<f64>,
+    ) -> Option<Self> {
+        if [track_gain, track_peak, album_gain, album_peak]
+            .iter()
+            .all(Option::is_none)
+        {
+            return None;
+        }
+        Some(ReplayGain {
+            track_gain,
+            track_peak,
+            album_gain,
+            album_peak,
+        })
+    }
+}
+
+/// An artist as OpenSubsonic lists them on a song.
+#[derive(Serialize, Clone)]
+pub struct ArtistRef {
+    pub(crate) id: Uuid,
+    pub(crate) name: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ItemGenre {
+    pub(crate) name: String,
+}
+
+/// The artists and genres of a set of songs, by song ID.
+#[derive(Default)]
+pub struct SongLinks {
+    artists: HashMap<Uuid, Vec<ArtistRef>>,
+    genres: HashMap<Uuid, Vec<ItemGenre>>,
+}
+
+impl SongLinks {
+    pub fn new(artists: Vec<SongArtist>, genres: Vec<SongGenre>) -> Self {
+        let mut links = SongLinks::default();
+        for a in artists {
+            links.artists.entry(a.song_id).or_default().push(ArtistRef {
+                id: a.artist_id,
+                name: a.name,
+            });
+        }
+        for g in genres {
+            links
+                .genres
+                .entry(g.song_id)
+                .or_default()
+                .push(ItemGenre { name: g.name });
+        }
+        links
+    }
+
+    /// The song's artists, or just its main one when there's nothing else on record.
+    fn artists_of(&self, song_id: Uuid, artist_id: Uuid, artist_name: &str) -> Vec<ArtistRef> {
+        match self.artists.get(&song_id) {
+            Some(artists) => artists.to_owned(),
+            None => vec![ArtistRef {
+                id: artist_id,
+                name: artist_name.to_string(),
+            }],
+        }
+    }
+
+    fn genres_of(&self, song_id: Uuid) -> Vec<ItemGenre> {
+        self.genres.get(&song_id).cloned().unwrap_or_default()
+    }
+}
+
+impl SongResponseData {
+    /// A song from one of the queries that join in its album and artist.
+    pub fn from_sqlx_model(
+        item: SongSqlxModel,
+        links: &SongLinks,
+        transcoding: &[TranscodingProfile],
+    ) -> Self {
+        let transcoded = default_profile(transcoding, &item.path);
+        let artists = links.artists_of(item.id, item.artist_id, &item.artist_name);
+        let genres = links.genres_of(item.id);
+        SongResponseData {
+            id: item.id,
+            parent: item.album_id,
+            is_dir: false,
+            title: item.title,
+            album: item.album_name,
+            artist: item.artist_name,
+            track: item.track,
+            year: item.year,
+            genre: item.genre,
+            cover_art: cover_art_id(item.id, &item.art_source),
+            size: item.size,
+            content_type: item.content_type,
+            suffix: item.suffix,
+            duration: item.duration,
+            bit_rate: item.bit_rate,
+            path: item.path,
+            play_count: 0,
+            disc_number: item.disc_number,
+            created: Utc::now(),
+            album_id: item.album_id,
+            artist_id: item.artist_id,
+            r#type: "audio".to_string(),
+            is_video: false,
+            transcoded_suffix: transcoded.map(|p| p.to.to_owned()),
+            transcoded_content_type: transcoded.map(|p| p.content_type.to_owned()),
+            artists,
+            genres,
+            musicbrainz_id: item.musicbrainz_id,
+            replay_gain: ReplayGain::new(
+                item.track_gain,
+                item.track_peak,
+                item.album_gain,
+                item.album_peak,
+            ),
+            sampling_rate: item.sample_rate,
+            bit_depth: item.bit_depth,
+            channel_count: item.channels,
+        }
+    }
 }
@@ -0,0 +1,287 @@
+use std::convert::Infallible;
+
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Query};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::responses::SubsonicResponse;
+
+const XML_NAMESPACE: &str = "http://subsonic.org/restapi";
+
+/// The response format negotiated through the Subsonic `f` parameter.
+/// Per the spec, XML is the default when the client doesn't ask for anything else.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Xml,
+    Json,
+    Jsonp(String),
+}
+
+#[derive(Deserialize)]
+struct FormatQuery {
+    f: Option<String>,
+    callback: Option<String>,
+}
+
+impl ResponseFormat {
+    fn from_query(query: FormatQuery) -> Self {
+        match query.f.as_deref() {
+            Some("json") => ResponseFormat::Json,
+            Some("jsonp") => match query.callback {
+                Some(callback) if valid_callback(&callback) => ResponseFormat::Jsonp(callback),
+                // A jsonp request without a callback can't be wrapped, plain json is the closest
+                // thing. The same goes for callbacks that are more than a function name, which
+                // would let whoever wrote the link run their own script
+                _ => ResponseFormat::Json,
+            },
+            _ => ResponseFormat::Xml,
+        }
+    }
+}
+
+/// A function name, possibly a dotted path like `jQuery.handlers.cb1`.
+fn valid_callback(callback: &str) -> bool {
+    !callback.is_empty()
+        && callback.split('.').all(|part| {
+            part.chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        })
+}
+
+/// Wraps the json in a call to `callback`. Line and paragraph separators are fine in json strings
+/// but end the line in older JavaScript engines, so they're escaped.
+fn to_jsonp(callback: &str, value: &Value) -> String {
+    let json = value
+        .to_string()
+        .replace('\u{2028}', "\\u2028")
+        .replace('\u{2029}', "\\u2029");
+    format!("{}({});", callback, json)
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ResponseFormat
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let format = match Query::<FormatQuery>::try_from_uri(&parts.uri) {
+            Ok(Query(query)) => ResponseFormat::from_query(query),
+            Err(_) => ResponseFormat::Xml,
+        };
+        Ok(format)
+    }
+}
+
+impl<T: Serialize> SubsonicResponse<T> {
+    /// Renders the response in the format the client asked for.
+    pub fn render(&self, format: &ResponseFormat) -> Response {
+        let mut value = match serde_json::to_value(self) {
+            Ok(v) => v,
+            Err(err) => {
+                error!("Error serializing response: {}", err);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        // OpenSubsonic clients look for this flag on every response
+        if let Some(Value::Object(inner)) = value.get_mut("subsonic-response") {
+            inner.insert("openSubsonic".to_string(), Value::Bool(true));
+        }
+        match format {
+            ResponseFormat::Json => (
+                [(header::CONTENT_TYPE, "application/json; charset=utf-8")],
+                value.to_string(),
+            )
+                .into_response(),
+            ResponseFormat::Jsonp(callback) => (
+                [(
+                    header::CONTENT_TYPE,
+                    "application/javascript; charset=utf-8",
+                )],
+                to_jsonp(callback, &value),
+            )
+                .into_response(),
+            ResponseFormat::Xml => (
+                [(header::CONTENT_TYPE, "text/xml; charset=utf-8")],
+                to_xml(&value),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// Turns the json representation of a response into the shape the Subsonic XSD expects:
+/// scalars become attributes, objects become child elements, arrays become repeated
+/// child elements and a `value` key becomes the element's text content.
+fn to_xml(value: &Value) -> String {
+    let mut out = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    if let Value::Object(map) = value {
+        for (name, inner) in map {
+            write_element(&mut out, name, inner, Some(XML_NAMESPACE));
+        }
+    }
+    out
+}
+
+fn write_element(out: &mut String, name: &str, value: &Value, namespace: Option<&str>) {
+    out.push('<');
+    out.push_str(name);
+    if let Some(ns) = namespace {
+        out.push_str(r#" xmlns=""#);
+        out.push_str(ns);
+        out.push('"');
+    }
+    let map = match value {
+        Value::Object(map) => map,
+        Value::Null => {
+            out.push_str("/>");
+            return;
+        }
+        scalar => {
+            out.push('>');
+            out.push_str(&escape(&scalar_to_string(scalar)));
+            close_element(out, name);
+            return;
+        }
+    };
+
+    let mut text: Option<String> = None;
+    for (key, inner) in map {
+        match inner {
+            Value::Object(_) | Value::Array(_) | Value::Null => {}
+            scalar if key == "value" => text = Some(scalar_to_string(scalar)),
+            scalar => {
+                out.push(' ');
+                out.push_str(key);
+                out.push_str(r#"=""#);
+                out.push_str(&escape(&scalar_to_string(scalar)));
+                out.push('"');
+            }
+        }
+    }
+
+    let has_children = map
+        .values()
+        .any(|v| matches!(v, Value::Object(_)) || matches!(v, Value::Array(a) if !a.is_empty()));
+    if !has_children && text.is_none() {
+        out.push_str("/>");
+        return;
+    }
+    out.push('>');
+    if let Some(text) = text {
+        out.push_str(&escape(&text));
+    }
+    for (key, inner) in map {
+        match inner {
+            Value::Object(_) => write_element(out, key, inner, None),
+            Value::Array(items) => {
+                for item in items {
+                    write_element(out, key, item, None);
+                }
+            }
+            _ => {}
+        }
+    }
+    close_element(out, name);
+}
+
+fn close_element(out: &mut String, name: &str) {
+    out.push_str("</");
+    out.push_str(name);
+    out.push('>');
+}
+
+fn scalar_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.to_owned(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        _ => "".to_string(),
+    }
+}
+
+fn escape(input: &str) -> String {
+    let mut ret = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn format(f: &str, callback: Option<&str>) -> ResponseFormat {
+        ResponseFormat::from_query(FormatQuery {
+            f: Some(f.to_string()),
+            callback: callback.map(|c| c.to_string()),
+        })
+    }
+
+    #[test]
+    fn scalars_are_attributes_and_objects_are_elements() {
+        let value = json!({"subsonic-response": {
+            "status": "ok",
+            "album": {
+                "id": "1",
+                "name": "Salt & \"Pepper\"",
+                "song": [{"id": "2", "track": 1}, {"id": "3", "track": 2}],
+                "genres": [],
+                "year": null
+            },
+            "lyrics": {"artist": "A", "value": "<line>"}
+        }});
+        assert_eq!(
+            to_xml(&value),
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                r#"<subsonic-response xmlns="http://subsonic.org/restapi" status="ok">"#,
+                r#"<album id="1" name="Salt &amp; &quot;Pepper&quot;">"#,
+                r#"<song id="2" track="1"/><song id="3" track="2"/></album>"#,
+                r#"<lyrics artist="A">&lt;line&gt;</lyrics>"#,
+                r#"</subsonic-response>"#
+            )
+        );
+    }
+
+    #[test]
+    fn jsonp_wraps_the_json_in_the_callback() {
+        assert_eq!(
+            format("jsonp", Some("jQuery.cb_1")),
+            ResponseFormat::Jsonp("jQuery.cb_1".to_string())
+        );
+        assert_eq!(
+            to_jsonp("cb", &json!({"value": "a\u{2028}b"})),
+            r#"cb({"value":"a\u2028b"});"#
+        );
+    }
+
+    #[test]
+    fn jsonp_callbacks_that_are_not_names_fall_back_to_json() {
+        for callback in ["", "alert(1);cb", "cb</script>", "1cb", "cb.", "a b"] {
+            assert_eq!(format("jsonp", Some(callback)), ResponseFormat::Json);
+        }
+        assert_eq!(format("jsonp", None), ResponseFormat::Json);
+        assert_eq!(format("xml", None), ResponseFormat::Xml);
+    }
+}
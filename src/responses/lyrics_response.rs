@@ -0,0 +1,116 @@
+use entities::lyrics::{Lyrics, LyricsLine};
+use serde::Serialize;
+
+use super::responses::{
+    get_server_version, get_status_ok, get_type, get_version, SubsonicResponse,
+};
+
+#[derive(Serialize, Clone)]
+pub struct LyricsResponse {
+    pub(crate) status: String,
+    pub(crate) version: String,
+    pub(crate) r#type: String,
+    #[serde(rename = "serverVersion")]
+    pub(crate) server_version: String,
+    pub(crate) lyrics: LyricsData,
+}
+
+/// Subsonic `lyrics`, plain text. Empty when nothing matched.
+#[derive(Serialize, Clone, Default)]
+pub struct LyricsData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) value: Option<String>,
+}
+
+impl SubsonicResponse<LyricsResponse> {
+    pub fn from_lyrics(lyrics: LyricsData) -> Self {
+        Self {
+            subsonic_response: LyricsResponse {
+                status: get_status_ok(),
+                version: get_version(),
+                r#type: get_type(),
+                server_version: get_server_version(),
+                lyrics,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct LyricsListResponse {
+    pub(crate) status: String,
+    pub(crate) version: String,
+    pub(crate) r#type: String,
+    #[serde(rename = "serverVersion")]
+    pub(crate) server_version: String,
+    #[serde(rename = "lyricsList")]
+    pub(crate) lyrics_list: LyricsListData,
+}
+
+/// OpenSubsonic `lyricsList`, every set of lyrics a song has.
+#[derive(Serialize, Clone)]
+pub struct LyricsListData {
+    #[serde(rename = "structuredLyrics")]
+    pub(crate) structured_lyrics: Vec<StructuredLyricsData>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct StructuredLyricsData {
+    #[serde(rename = "displayArtist")]
+    pub(crate) display_artist: String,
+    #[serde(rename = "displayTitle")]
+    pub(crate) display_title: String,
+    pub(crate) lang: String,
+    // Start times are already corrected for the `[offset:]` of `.lrc` files
+    pub(crate) offset: i32,
+    pub(crate) synced: bool,
+    pub(crate) line: Vec<LineData>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct LineData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) start: Option<i32>,
+    pub(crate) value: String,
+}
+
+impl SubsonicResponse<LyricsListResponse> {
+    pub fn from_structured_lyrics(
+        artist: &str,
+        title: &str,
+        lyrics: Vec<Lyrics>,
+        lines: Vec<LyricsLine>,
+    ) -> Self {
+        let structured_lyrics = lyrics
+            .into_iter()
+            .map(|l| StructuredLyricsData {
+                display_artist: artist.to_string(),
+                display_title: title.to_string(),
+                lang: l.lang,
+                offset: 0,
+                synced: l.synced,
+                line: lines
+                    .iter()
+                    .filter(|line| line.lyrics_id == l.id)
+                    .map(|line| LineData {
+                        start: line.start,
+                        value: line.value.to_owned(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self {
+            subsonic_response: LyricsListResponse {
+                status: get_status_ok(),
+                version: get_version(),
+                r#type: get_type(),
+                server_version: get_server_version(),
+                lyrics_list: LyricsListData { structured_lyrics },
+            },
+        }
+    }
+}
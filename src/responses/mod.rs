@@ -1,2 +1,7 @@
+pub mod album_response;
+pub mod format;
+pub mod lyrics_response;
+#[allow(clippy::module_inception)]
 pub mod responses;
-pub mod album_response;
\ No newline at end of file
+pub mod scan_response;
+pub mod user_response;
@@ -2,26 +2,29 @@ use chrono::Utc;
 use chrono::{self, DateTime, Local};
 use entities::album::{Album, AlbumSqlxModel};
 use entities::artist::{Artist, ArtistSqlxModel};
+use entities::music_folder::MusicFolder;
 use entities::playlist::Playlist;
 use entities::song::SongSqlxModel;
 use serde::Serialize;
 use uuid::Uuid;
 
-use super::album_response::SongResponseData;
+use super::album_response::{SongLinks, SongResponseData};
+use crate::cover_art::cover_art_id;
+use crate::transcoding::TranscodingProfile;
 
-fn get_status_ok() -> String {
+pub(super) fn get_status_ok() -> String {
     "ok".to_string()
 }
 
-fn get_version() -> String {
+pub(super) fn get_version() -> String {
     "1.16.1".to_string()
 }
 
-fn get_type() -> String {
+pub(super) fn get_type() -> String {
     "soniccave".to_string()
 }
 
-fn get_server_version() -> String {
+pub(super) fn get_server_version() -> String {
     "0.0.1".to_string()
 }
 
@@ -48,7 +51,7 @@ pub struct ErrorResponseContainer {
 }
 
 impl SubsonicResponse<ErrorResponse> {
-    pub fn from_message(message: String) -> Self {
+    pub fn from_error_code(code: i32, message: String) -> Self {
         Self {
             subsonic_response: {
                 ErrorResponse {
@@ -56,21 +59,143 @@ impl SubsonicResponse<ErrorResponse> {
                     version: get_version(),
                     r#type: get_type(),
                     server_version: get_server_version(),
-                    error: ErrorResponseContainer { code: 0, message },
+                    error: ErrorResponseContainer { code, message },
                 }
             },
         }
     }
-    pub fn from_error_code(code: i32, message: String) -> Self {
+}
+
+#[derive(Serialize, Clone)]
+pub struct EmptyResponse {
+    pub(crate) status: String,
+    pub(crate) version: String,
+    pub(crate) r#type: String,
+    #[serde(rename = "serverVersion")]
+    pub(crate) server_version: String,
+}
+
+impl SubsonicResponse<EmptyResponse> {
+    pub fn ok() -> Self {
         Self {
-            subsonic_response: {
-                ErrorResponse {
-                    status: "failed".to_string(),
-                    version: "1.1.16".to_string(),
-                    r#type: "soniccave".to_string(),
-                    server_version: "0.0.1".to_string(),
-                    error: ErrorResponseContainer { code, message },
-                }
+            subsonic_response: EmptyResponse {
+                status: get_status_ok(),
+                version: get_version(),
+                r#type: get_type(),
+                server_version: get_server_version(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct OpenSubsonicExtensionsResponse {
+    pub(crate) status: String,
+    pub(crate) version: String,
+    pub(crate) r#type: String,
+    #[serde(rename = "serverVersion")]
+    pub(crate) server_version: String,
+    #[serde(rename = "openSubsonicExtensions")]
+    pub(crate) open_subsonic_extensions: Vec<OpenSubsonicExtension>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct OpenSubsonicExtension {
+    pub(crate) name: String,
+    pub(crate) versions: Vec<i32>,
+}
+
+impl SubsonicResponse<OpenSubsonicExtensionsResponse> {
+    pub fn from_extensions(extensions: &[(&str, &[i32])]) -> Self {
+        Self {
+            subsonic_response: OpenSubsonicExtensionsResponse {
+                status: get_status_ok(),
+                version: get_version(),
+                r#type: get_type(),
+                server_version: get_server_version(),
+                open_subsonic_extensions: extensions
+                    .iter()
+                    .map(|(name, versions)| OpenSubsonicExtension {
+                        name: name.to_string(),
+                        versions: versions.to_vec(),
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct ScanStatusResponse {
+    pub(crate) status: String,
+    pub(crate) version: String,
+    pub(crate) r#type: String,
+    #[serde(rename = "serverVersion")]
+    pub(crate) server_version: String,
+    #[serde(rename = "scanStatus")]
+    pub(crate) scan_status: ScanStatus,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ScanStatus {
+    pub(crate) scanning: bool,
+    pub(crate) count: i64,
+}
+
+impl SubsonicResponse<ScanStatusResponse> {
+    pub fn from_status(scanning: bool, count: i64) -> Self {
+        Self {
+            subsonic_response: ScanStatusResponse {
+                status: get_status_ok(),
+                version: get_version(),
+                r#type: get_type(),
+                server_version: get_server_version(),
+                scan_status: ScanStatus { scanning, count },
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct MusicFoldersResponse {
+    pub(crate) status: String,
+    pub(crate) version: String,
+    pub(crate) r#type: String,
+    #[serde(rename = "serverVersion")]
+    pub(crate) server_version: String,
+    #[serde(rename = "musicFolders")]
+    pub(crate) music_folders: MusicFolders,
+}
+
+#[derive(Serialize, Clone)]
+pub struct MusicFolders {
+    #[serde(rename = "musicFolder")]
+    pub(crate) music_folder: Vec<MusicFolderItem>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct MusicFolderItem {
+    pub(crate) id: i32,
+    pub(crate) name: String,
+}
+
+impl SubsonicResponse<MusicFoldersResponse> {
+    pub fn from_music_folders(folders: Vec<MusicFolder>) -> Self {
+        Self {
+            subsonic_response: MusicFoldersResponse {
+                status: get_status_ok(),
+                version: get_version(),
+                r#type: get_type(),
+                server_version: get_server_version(),
+                music_folders: MusicFolders {
+                    music_folder: folders
+                        .into_iter()
+                        .map(|f| MusicFolderItem {
+                            id: f.id,
+                            name: f.name,
+                        })
+                        .collect(),
+                },
             },
         }
     }
@@ -105,6 +230,8 @@ pub struct ArtistItem {
     pub(crate) album_count: i32,
     #[serde(rename = "artistImageUrl")]
     pub(crate) artist_image_url: String,
+    #[serde(rename = "musicBrainzId", skip_serializing_if = "Option::is_none")]
+    pub(crate) musicbrainz_id: Option<String>,
 }
 
 #[derive(Serialize, Clone)]
@@ -135,8 +262,8 @@ pub struct AlbumList2Item {
     pub(crate) artist: String,
     pub(crate) year: i32,
     pub(crate) genre: String,
-    #[serde(rename = "coverArt")]
-    pub(crate) cover_art: Uuid,
+    #[serde(rename = "coverArt", skip_serializing_if = "Option::is_none")]
+    pub(crate) cover_art: Option<String>,
     pub(crate) duration: i32,
     #[serde(rename = "playCount")]
     pub(crate) play_count: i32,
@@ -147,10 +274,12 @@ pub struct AlbumList2Item {
     pub(crate) song_count: i32,
     #[serde(rename = "isVideo")]
     pub(crate) is_video: bool,
+    #[serde(rename = "musicBrainzId", skip_serializing_if = "Option::is_none")]
+    pub(crate) musicbrainz_id: Option<String>,
 }
 
 impl SubsonicResponse<AlbumList2Response> {
-    pub fn album_list2_from_album_list(list: &Vec<Album>, artists_list: &Vec<Artist>) -> Self {
+    pub fn album_list2_from_album_list(list: &[Album], artists_list: &[Artist]) -> Self {
         let mut ret = Vec::new();
         for item in list {
             // I'm sure I have the artist
@@ -168,13 +297,14 @@ impl SubsonicResponse<AlbumList2Response> {
                 artist: artist.name.to_owned(),
                 year: item.year,
                 genre: "".to_string(),
-                cover_art: Uuid::nil(),
+                cover_art: cover_art_id(item.id, &item.art_source),
                 duration: 0,
                 play_count: 0,
                 created: Utc::now(),
                 artist_id: artist.id,
                 song_count: item.song_count,
                 is_video: false,
+                musicbrainz_id: item.musicbrainz_id.to_owned(),
             })
         }
         Self {
@@ -206,30 +336,44 @@ pub struct ArtistResponseItem {
     album_count: i32,
     #[serde(rename = "artistImageUrl")]
     artist_image_url: String,
+    #[serde(rename = "musicBrainzId", skip_serializing_if = "Option::is_none")]
+    musicbrainz_id: Option<String>,
     album: Vec<AlbumList2Item>,
 }
 
 impl SubsonicResponse<ArtistResponse> {
-    pub fn artist_from_album_list(list: Vec<Album>, artist: Artist) -> Self {
+    /// `album_artists` holds the album artists of albums where the artist only appears on some tracks.
+    pub fn artist_from_album_list(
+        list: Vec<Album>,
+        artist: Artist,
+        album_artists: &[Artist],
+    ) -> Self {
         let ret: Vec<AlbumList2Item> = list
             .iter()
-            .map(|item| AlbumList2Item {
-                id: item.id,
-                parent: artist.id,
-                is_dir: true,
-                title: item.name.to_owned(),
-                name: item.name.to_owned(),
-                album: item.name.to_owned(),
-                artist: artist.name.to_owned(),
-                year: item.year,
-                genre: "".to_string(),
-                cover_art: Uuid::nil(),
-                duration: 0,
-                play_count: 0,
-                created: Utc::now(),
-                artist_id: artist.id,
-                song_count: item.song_count,
-                is_video: false,
+            .map(|item| {
+                let album_artist = album_artists
+                    .iter()
+                    .find(|i| i.id == item.artist_id)
+                    .unwrap_or(&artist);
+                AlbumList2Item {
+                    id: item.id,
+                    parent: album_artist.id,
+                    is_dir: true,
+                    title: item.name.to_owned(),
+                    name: item.name.to_owned(),
+                    album: item.name.to_owned(),
+                    artist: album_artist.name.to_owned(),
+                    year: item.year,
+                    genre: "".to_string(),
+                    cover_art: cover_art_id(item.id, &item.art_source),
+                    duration: 0,
+                    play_count: 0,
+                    created: Utc::now(),
+                    artist_id: album_artist.id,
+                    song_count: item.song_count,
+                    is_video: false,
+                    musicbrainz_id: item.musicbrainz_id.to_owned(),
+                }
             })
             .collect();
         Self {
@@ -241,8 +385,9 @@ impl SubsonicResponse<ArtistResponse> {
                 artist: ArtistResponseItem {
                     id: artist.id,
                     name: artist.name,
-                    album_count: list.capacity() as i32,
+                    album_count: list.len() as i32,
                     artist_image_url: "".to_string(),
+                    musicbrainz_id: artist.musicbrainz_id,
                     album: ret,
                 },
             },
@@ -273,6 +418,8 @@ impl SubsonicResponse<SearchResponse> {
         artist_list: Vec<ArtistSqlxModel>,
         album_list: Vec<AlbumSqlxModel>,
         song_list: Vec<SongSqlxModel>,
+        links: &SongLinks,
+        transcoding: &[TranscodingProfile],
     ) -> Self {
         let albums: Vec<AlbumList2Item> = album_list
             .iter()
@@ -286,13 +433,14 @@ impl SubsonicResponse<SearchResponse> {
                 artist: item.artist_name.to_owned(),
                 year: item.year,
                 genre: "".to_string(),
-                cover_art: Uuid::nil(),
+                cover_art: cover_art_id(item.id, &item.art_source),
                 duration: 0,
                 play_count: 0,
                 created: Utc::now(),
                 artist_id: item.artist_id,
                 song_count: item.song_count,
                 is_video: false,
+                musicbrainz_id: item.musicbrainz_id.to_owned(),
             })
             .collect();
 
@@ -303,35 +451,12 @@ impl SubsonicResponse<SearchResponse> {
                 name: item.name.to_owned(),
                 album_count: 0,
                 artist_image_url: "".to_string(),
+                musicbrainz_id: item.musicbrainz_id.to_owned(),
             })
             .collect();
         let songs: Vec<SongResponseData> = song_list
-            .iter()
-            .map(|item| SongResponseData {
-                id: item.id,
-                parent: item.album_id,
-                is_dir: false,
-                title: item.title.to_owned(),
-                album: item.album_name.to_owned(),
-                artist: item.artist_name.to_owned(),
-                track: item.track,
-                year: item.year,
-                genre: item.genre.to_owned(),
-                cover_art: "".to_string(),
-                size: 0,
-                content_type: item.content_type.to_owned(),
-                suffix: item.suffix.to_owned(),
-                duration: item.duration,
-                bit_rate: 0,
-                path: item.path.to_owned(),
-                play_count: 0,
-                disc_number: item.disc_number,
-                created: Utc::now(),
-                album_id: item.album_id,
-                artist_id: item.artist_id,
-                r#type: "audio".to_string(),
-                is_video: false,
-            })
+            .into_iter()
+            .map(|item| SongResponseData::from_sqlx_model(item, links, transcoding))
             .collect();
         Self {
             subsonic_response: SearchResponse {
@@ -349,6 +474,45 @@ impl SubsonicResponse<SearchResponse> {
     }
 }
 
+#[derive(Serialize, Clone)]
+pub struct RandomSongsResponse {
+    pub(crate) status: String,
+    pub(crate) version: String,
+    pub(crate) r#type: String,
+    #[serde(rename = "serverVersion")]
+    pub(crate) server_version: String,
+    #[serde(rename = "randomSongs")]
+    pub(crate) random_songs: RandomSongs,
+}
+
+#[derive(Serialize, Clone)]
+pub struct RandomSongs {
+    pub(crate) song: Vec<SongResponseData>,
+}
+
+impl SubsonicResponse<RandomSongsResponse> {
+    pub fn from_random_songs(
+        songs: Vec<SongSqlxModel>,
+        links: &SongLinks,
+        transcoding: &[TranscodingProfile],
+    ) -> Self {
+        Self {
+            subsonic_response: RandomSongsResponse {
+                status: get_status_ok(),
+                version: get_version(),
+                r#type: get_type(),
+                server_version: get_server_version(),
+                random_songs: RandomSongs {
+                    song: songs
+                        .into_iter()
+                        .map(|s| SongResponseData::from_sqlx_model(s, links, transcoding))
+                        .collect(),
+                },
+            },
+        }
+    }
+}
+
 #[derive(Serialize, Clone)]
 pub struct PlaylistsResponse {
     pub(crate) status: String,
@@ -432,34 +596,15 @@ pub struct PlaylistResult {
 }
 
 impl SubsonicResponse<PlaylistResponse> {
-    pub fn from_playlist(playlist: Playlist, songs: Vec<SongSqlxModel>) -> Self {
+    pub fn from_playlist(
+        playlist: Playlist,
+        songs: Vec<SongSqlxModel>,
+        links: &SongLinks,
+        transcoding: &[TranscodingProfile],
+    ) -> Self {
         let entry: Vec<SongResponseData> = songs
             .into_iter()
-            .map(|x: SongSqlxModel| SongResponseData {
-                id: x.id,
-                parent: x.album_id,
-                is_dir: false,
-                title: x.title,
-                album: x.album_name,
-                artist: x.artist_name,
-                track: x.track,
-                year: x.year,
-                genre: x.genre,
-                cover_art: "".to_owned(),
-                size: 0,
-                content_type: x.content_type,
-                suffix: x.suffix,
-                duration: x.duration,
-                bit_rate: 0,
-                path: x.path,
-                play_count: 0,
-                disc_number: x.disc_number,
-                created: Utc::now(),
-                album_id: x.album_id,
-                artist_id: x.artist_id,
-                r#type: "audio".to_owned(),
-                is_video: false,
-            })
+            .map(|x| SongResponseData::from_sqlx_model(x, links, transcoding))
             .collect();
         Self {
             subsonic_response: PlaylistResponse {
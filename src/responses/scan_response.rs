@@ -0,0 +1,169 @@
+use chrono::{DateTime, Utc};
+use entities::scan_error::ScanError;
+use serde::Serialize;
+
+use super::responses::{
+    get_server_version, get_status_ok, get_type, get_version, SubsonicResponse,
+};
+use crate::scan::{ScanKind, ScanReport, ScanTotals};
+
+#[derive(Serialize, Clone)]
+pub struct ScanReportResponse {
+    pub(crate) status: String,
+    pub(crate) version: String,
+    pub(crate) r#type: String,
+    #[serde(rename = "serverVersion")]
+    pub(crate) server_version: String,
+    #[serde(rename = "scanStatus")]
+    pub(crate) scan_status: ScanReportData,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ScanReportData {
+    pub(crate) scanning: bool,
+    // Songs in the library
+    pub(crate) count: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) current: Option<RunningScanData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) last: Option<FinishedScanData>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct RunningScanData {
+    pub(crate) kind: String,
+    pub(crate) started: DateTime<Utc>,
+    #[serde(rename = "elapsedMs")]
+    pub(crate) elapsed_ms: i64,
+    #[serde(flatten)]
+    pub(crate) totals: ScanTotalsData,
+}
+
+#[derive(Serialize, Clone)]
+pub struct FinishedScanData {
+    pub(crate) kind: String,
+    pub(crate) started: DateTime<Utc>,
+    pub(crate) finished: DateTime<Utc>,
+    #[serde(rename = "durationMs")]
+    pub(crate) duration_ms: i64,
+    #[serde(flatten)]
+    pub(crate) totals: ScanTotalsData,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ScanTotalsData {
+    pub(crate) directories: u64,
+    pub(crate) parsed: u64,
+    pub(crate) added: u64,
+    pub(crate) updated: u64,
+    pub(crate) moved: u64,
+    pub(crate) removed: u64,
+    pub(crate) failed: u64,
+}
+
+impl ScanTotalsData {
+    fn from_totals(totals: &ScanTotals) -> Self {
+        Self {
+            directories: totals.directories,
+            parsed: totals.parsed,
+            added: totals.added,
+            updated: totals.updated,
+            moved: totals.moved,
+            removed: totals.removed,
+            failed: totals.failed,
+        }
+    }
+}
+
+impl SubsonicResponse<ScanReportResponse> {
+    pub fn from_scan_state(
+        count: i64,
+        current: Option<(ScanKind, DateTime<Utc>, ScanTotals)>,
+        last: Option<ScanReport>,
+    ) -> Self {
+        Self {
+            subsonic_response: ScanReportResponse {
+                status: get_status_ok(),
+                version: get_version(),
+                r#type: get_type(),
+                server_version: get_server_version(),
+                scan_status: ScanReportData {
+                    scanning: current.is_some(),
+                    count,
+                    current: current.map(|(kind, started, totals)| RunningScanData {
+                        kind: kind.as_str().to_string(),
+                        started,
+                        elapsed_ms: (Utc::now() - started).num_milliseconds(),
+                        totals: ScanTotalsData::from_totals(&totals),
+                    }),
+                    last: last.map(|report| FinishedScanData {
+                        kind: report.kind.as_str().to_string(),
+                        started: report.started,
+                        finished: report.finished,
+                        duration_ms: (report.finished - report.started).num_milliseconds(),
+                        totals: ScanTotalsData::from_totals(&report.totals),
+                        error: report.error,
+                    }),
+                },
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct ScanErrorsResponse {
+    pub(crate) status: String,
+    pub(crate) version: String,
+    pub(crate) r#type: String,
+    #[serde(rename = "serverVersion")]
+    pub(crate) server_version: String,
+    #[serde(rename = "scanErrors")]
+    pub(crate) scan_errors: ScanErrorsData,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ScanErrorsData {
+    // All the matching errors, not only the ones in this page
+    pub(crate) count: i64,
+    #[serde(rename = "scanError")]
+    pub(crate) scan_error: Vec<ScanErrorData>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ScanErrorData {
+    pub(crate) path: String,
+    pub(crate) stage: String,
+    pub(crate) message: String,
+    #[serde(rename = "firstSeen")]
+    pub(crate) first_seen: DateTime<Utc>,
+    #[serde(rename = "lastSeen")]
+    pub(crate) last_seen: DateTime<Utc>,
+}
+
+impl SubsonicResponse<ScanErrorsResponse> {
+    pub fn from_scan_errors(count: i64, errors: Vec<ScanError>) -> Self {
+        Self {
+            subsonic_response: ScanErrorsResponse {
+                status: get_status_ok(),
+                version: get_version(),
+                r#type: get_type(),
+                server_version: get_server_version(),
+                scan_errors: ScanErrorsData {
+                    count,
+                    scan_error: errors
+                        .into_iter()
+                        .map(|e| ScanErrorData {
+                            path: e.path,
+                            stage: e.stage,
+                            message: e.message,
+                            first_seen: e.first_seen,
+                            last_seen: e.last_seen,
+                        })
+                        .collect(),
+                },
+            },
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use entities::user::User;
+use serde::Serialize;
+
+use super::responses::{
+    get_server_version, get_status_ok, get_type, get_version, SubsonicResponse,
+};
+
+#[derive(Serialize, Clone)]
+pub struct UserResponse {
+    pub(crate) status: String,
+    pub(crate) version: String,
+    pub(crate) r#type: String,
+    #[serde(rename = "serverVersion")]
+    pub(crate) server_version: String,
+    pub(crate) user: UserResponseData,
+}
+
+#[derive(Serialize, Clone)]
+pub struct UsersResponse {
+    pub(crate) status: String,
+    pub(crate) version: String,
+    pub(crate) r#type: String,
+    #[serde(rename = "serverVersion")]
+    pub(crate) server_version: String,
+    pub(crate) users: UsersResponseData,
+}
+
+#[derive(Serialize, Clone)]
+pub struct UsersResponseData {
+    pub(crate) user: Vec<UserResponseData>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct UserResponseData {
+    pub(crate) username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) email: Option<String>,
+    #[serde(rename = "adminRole")]
+    pub(crate) admin_role: bool,
+    #[serde(rename = "settingsRole")]
+    pub(crate) settings_role: bool,
+    #[serde(rename = "downloadRole")]
+    pub(crate) download_role: bool,
+    #[serde(rename = "uploadRole")]
+    pub(crate) upload_role: bool,
+    #[serde(rename = "playlistRole")]
+    pub(crate) playlist_role: bool,
+    #[serde(rename = "coverArtRole")]
+    pub(crate) cover_art_role: bool,
+    #[serde(rename = "commentRole")]
+    pub(crate) comment_role: bool,
+    #[serde(rename = "podcastRole")]
+    pub(crate) podcast_role: bool,
+    #[serde(rename = "shareRole")]
+    pub(crate) share_role: bool,
+    #[serde(rename = "streamRole")]
+    pub(crate) stream_role: bool,
+    #[serde(rename = "maxBitRate")]
+    pub(crate) max_bit_rate: i32,
+}
+
+impl From<&User> for UserResponseData {
+    fn from(user: &User) -> Self {
+        Self {
+            username: user.username.to_owned(),
+            email: user.email.to_owned(),
+            admin_role: user.admin_role,
+            settings_role: user.settings_role,
+            download_role: user.download_role,
+            upload_role: user.upload_role,
+            playlist_role: user.playlist_role,
+            cover_art_role: user.cover_art_role,
+            comment_role: user.comment_role,
+            podcast_role: user.podcast_role,
+            share_role: user.share_role,
+            stream_role: user.stream_role,
+            max_bit_rate: user.max_bit_rate,
+        }
+    }
+}
+
+impl SubsonicResponse<UserResponse> {
+    pub fn from_user(user: &User) -> Self {
+        Self {
+            subsonic_response: UserResponse {
+                status: get_status_ok(),
+                version: get_version(),
+                r#type: get_type(),
+                server_version: get_server_version(),
+                user: user.into(),
+            },
+        }
+    }
+}
+
+impl SubsonicResponse<UsersResponse> {
+    pub fn from_user_list(users: &[User]) -> Self {
+        Self {
+            subsonic_response: UsersResponse {
+                status: get_status_ok(),
+                version: get_version(),
+                r#type: get_type(),
+                server_version: get_server_version(),
+                users: UsersResponseData {
+                    user: users.iter().map(UserResponseData::from).collect(),
+                },
+            },
+        }
+    }
+}
@@ -0,0 +1,800 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use crossbeam_channel::Receiver;
+use entities::artist::Artist;
+use entities::lyrics::SongLyrics;
+use entities::music_folder::MusicFolder;
+use entities::song::Song;
+use log::{error, info, warn};
+use serde::Deserialize;
+use sqlx::{PgConnection, Pool, Postgres};
+use tokio::sync::mpsc;
+use tokio::task;
+use uuid::Uuid;
+
+use crate::explorer::{FileState, FoundFile, WalkRules, Walked};
+use crate::responses::format::ResponseFormat;
+use crate::responses::responses::ErrorResponse;
+use crate::responses::responses::{ScanStatusResponse, SubsonicResponse};
+use crate::responses::scan_response::{ScanErrorsResponse, ScanReportResponse};
+use crate::tag_parser::{ParsedSong, TagSeparators};
+use crate::{database_sync, explorer, tag_parser, Config, DatabaseState};
+
+// Paths waiting for a parser, and parsed songs waiting to be written
+const CHANNEL_CAPACITY: usize = 1024;
+// Scan errors listed at a time when the request doesn't say
+const DEFAULT_SCAN_ERRORS_SIZE: i64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanKind {
+    Full,
+    Incremental,
+    // Changes reported by the file watcher
+    Watch,
+}
+
+impl ScanKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScanKind::Full => "full",
+            ScanKind::Incremental => "incremental",
+            ScanKind::Watch => "watch",
+        }
+    }
+}
+
+/// Where in the scan a file ran into trouble.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanStage {
+    // Listing a directory or reading a file's metadata
+    Walk,
+    // Reading the first bytes to tell the format
+    Probe,
+    Tag,
+    // Writing the song to the database
+    Insert,
+}
+
+impl ScanStage {
+    pub const ALL: [ScanStage; 4] = [
+        ScanStage::Walk,
+        ScanStage::Probe,
+        ScanStage::Tag,
+        ScanStage::Insert,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScanStage::Walk => "walk",
+            ScanStage::Probe => "probe",
+            ScanStage::Tag => "tag",
+            ScanStage::Insert => "insert",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.as_str() == name)
+    }
+}
+
+struct ScanProblem {
+    path: String,
+    stage: ScanStage,
+    message: String,
+}
+
+/// What the running scan has done so far, updated by every stage of the pipeline as it goes.
+#[derive(Default)]
+pub struct ScanCounters {
+    directories: AtomicU64,
+    parsed: AtomicU64,
+    added: AtomicU64,
+    updated: AtomicU64,
+    moved: AtomicU64,
+    removed: AtomicU64,
+    failed: AtomicU64,
+    // Problems not written to the database yet
+    problems: Mutex<Vec<ScanProblem>>,
+}
+
+impl ScanCounters {
+    pub fn directory_walked(&self) {
+        self.directories.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Logs a problem with `path` and keeps it to be recorded in the scan error table.
+    pub fn problem(&self, stage: ScanStage, path: &str, message: String) {
+        error!("Error scanning {} ({}): {}", path, stage.as_str(), message);
+        self.problems.lock().unwrap().push(ScanProblem {
+            path: path.to_string(),
+            stage,
+            message,
+        });
+    }
+
+    pub fn snapshot(&self) -> ScanTotals {
+        ScanTotals {
+            directories: self.directories.load(Ordering::Relaxed),
+            parsed: self.parsed.load(Ordering::Relaxed),
+            added: self.added.load(Ordering::Relaxed),
+            updated: self.updated.load(Ordering::Relaxed),
+            moved: self.moved.load(Ordering::Relaxed),
+            removed: self.removed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct ScanTotals {
+    pub directories: u64,
+    pub parsed: u64,
+    pub added: u64,
+    pub updated: u64,
+    pub moved: u64,
+    pub removed: u64,
+    pub failed: u64,
+}
+
+pub struct RunningScan {
+    pub kind: ScanKind,
+    pub started: DateTime<Utc>,
+    pub counters: Arc<ScanCounters>,
+    // Holds the database lock that keeps other processes from scanning too
+    lock: PgConnection,
+}
+
+#[derive(Clone)]
+pub struct ScanReport {
+    pub kind: ScanKind,
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+    pub totals: ScanTotals,
+    pub error: Option<String>,
+}
+
+/// Shared between requests so only one scan of the library runs at a time, and so its progress
+/// and the outcome of the last one can be looked up. Other processes, like `soniccave scan`, are
+/// kept out by an advisory lock in the database.
+#[derive(Default)]
+pub struct ScanState {
+    scanning: AtomicBool,
+    current: Mutex<Option<RunningScan>>,
+    last: Mutex<Option<ScanReport>>,
+}
+
+impl ScanState {
+    /// Takes the scan lock, None if another scan holds it, in this process or another one. The
+    /// scan reports its progress through the returned counters.
+    pub async fn try_begin(
+        &self,
+        connection: &Pool<Postgres>,
+        kind: ScanKind,
+    ) -> Result<Option<Arc<ScanCounters>>, String> {
+        if self
+            .scanning
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Ok(None);
+        }
+        let lock = match queries::try_lock_scan(connection).await {
+            Ok(Some(lock)) => lock,
+            Ok(None) => {
+                self.scanning.store(false, Ordering::SeqCst);
+                return Ok(None);
+            }
+            Err(err) => {
+                self.scanning.store(false, Ordering::SeqCst);
+                return Err(format!("Error taking the scan lock: {err}"));
+            }
+        };
+        let counters = Arc::new(ScanCounters::default());
+        *self.current.lock().unwrap() = Some(RunningScan {
+            kind,
+            started: Utc::now(),
+            counters: counters.to_owned(),
+            lock,
+        });
+        Ok(Some(counters))
+    }
+
+    /// Releases the lock, keeping what the scan did as the last scan's results.
+    pub fn finish(&self, result: &Result<(), String>) {
+        if let Some(running) = self.current.lock().unwrap().take() {
+            let report = ScanReport {
+                kind: running.kind,
+                started: running.started,
+                finished: Utc::now(),
+                totals: running.counters.snapshot(),
+                error: result.as_ref().err().cloned(),
+            };
+            let totals = report.totals;
+            info!(
+                "Scan ({}) finished in {:.1}s: {} directories, {} files parsed, {} added, {} updated, {} moved, {} removed, {} failed",
+                report.kind.as_str(),
+                (report.finished - report.started).num_milliseconds() as f64 / 1000.0,
+                totals.directories,
+                totals.parsed,
+                totals.added,
+                totals.updated,
+                totals.moved,
+                totals.removed,
+                totals.failed
+            );
+            *self.last.lock().unwrap() = Some(report);
+            // Closing the connection releases the database lock
+            drop(running.lock);
+        }
+        self.scanning.store(false, Ordering::SeqCst);
+    }
+
+    /// Kind, start time and counts so far of the running scan.
+    pub fn progress(&self) -> Option<(ScanKind, DateTime<Utc>, ScanTotals)> {
+        self.current
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|r| (r.kind, r.started, r.counters.snapshot()))
+    }
+
+    pub fn last(&self) -> Option<ScanReport> {
+        self.last.lock().unwrap().to_owned()
+    }
+}
+
+async fn files_on_db(connection: &Pool<Postgres>) -> Result<HashMap<String, FileState>, String> {
+    let file_states = queries::get_song_file_states(connection)
+        .await
+        .map_err(|e| format!("There was an error reading from the database. {e}"))?;
+    Ok(file_states
+        .into_iter()
+        .map(|s| {
+            (
+                s.path,
+                FileState {
+                    size: s.size,
+                    mtime: s.mtime,
+                },
+            )
+        })
+        .collect())
+}
+
+/// Brings the music folder rows in line with the configuration, returning them with their ids.
+pub async fn sync_music_folders(
+    connection: &Pool<Postgres>,
+    config: &Config,
+) -> Result<Vec<MusicFolder>, String> {
+    let names: Vec<String> = config
+        .music_folders
+        .iter()
+        .map(|f| f.name.to_owned())
+        .collect();
+    let paths: Vec<String> = config
+        .music_folders
+        .iter()
+        .map(|f| f.path.to_owned())
+        .collect();
+    queries::sync_music_folders(connection, &names, &paths)
+        .await
+        .map_err(|e| format!("Error updating the music folders: {e}"))
+}
+
+/// The folder a file belongs to. Configured folders don't overlap, so there's at most one.
+fn music_folder_of(folders: &[MusicFolder], path: &str) -> Option<i32> {
+    folders
+        .iter()
+        .find(|f| Path::new(path).starts_with(&f.path))
+        .map(|f| f.id)
+}
+
+/// Writes the problems the scan ran into since the last time to the scan error table.
+async fn record_problems(
+    connection: &Pool<Postgres>,
+    counters: &ScanCounters,
+) -> Result<(), String> {
+    let problems: Vec<ScanProblem> = counters.problems.lock().unwrap().drain(..).collect();
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let mut paths: Vec<String> = Vec::with_capacity(problems.len());
+    let mut stages: Vec<String> = Vec::with_capacity(problems.len());
+    let mut messages: Vec<String> = Vec::with_capacity(problems.len());
+    for problem in problems {
+        paths.push(problem.path);
+        stages.push(problem.stage.as_str().to_string());
+        messages.push(problem.message);
+    }
+    queries::add_scan_errors(connection, &paths, &stages, &messages)
+        .await
+        .map_err(|e| format!("Error recording scan errors: {e}"))
+}
+
+/// Scans the library right away, for the command line. Fails if a server or another command is
+/// scanning already.
+pub async fn scan_now(
+    connection: &Pool<Postgres>,
+    config: &Config,
+    full: bool,
+) -> Result<(), String> {
+    let state = ScanState::default();
+    let kind = if full {
+        ScanKind::Full
+    } else {
+        ScanKind::Incremental
+    };
+    let counters = state
+        .try_begin(connection, kind)
+        .await?
+        .ok_or("A scan is already running")?;
+    let result = sync(connection, config, full, &counters).await;
+    state.finish(&result);
+    result
+}
+
+pub async fn sync(
+    connection: &Pool<Postgres>,
+    config: &Config,
+    full: bool,
+    counters: &Arc<ScanCounters>,
+) -> Result<(), String> {
+    let started = Utc::now();
+    let folders = sync_music_folders(connection, config).await?;
+    info!("Gathering paths");
+    let files_on_db = files_on_db(connection).await?;
+    let roots = folders.iter().map(|f| PathBuf::from(&f.path)).collect();
+    let walked = run_pipeline(
+        connection,
+        config,
+        &folders,
+        roots,
+        files_on_db,
+        full,
+        counters,
+    )
+    .await?;
+    // Whatever wasn't found on disk is gone, unless it's somewhere the walk couldn't look. An
+    // error reading a directory mustn't cost its songs and their places in playlists
+    match walked.deleted() {
+        Some(deleted) => {
+            // Moved files were relinked already, their old paths match nothing anymore
+            let removed = queries::prune_songs(connection, &deleted)
+                .await
+                .map_err(|e| e.to_string())?;
+            counters.removed.fetch_add(removed, Ordering::Relaxed);
+        }
+        None => warn!("Not removing missing songs, a music folder couldn't be read"),
+    }
+    // Every file that failed before was looked at again, whatever wasn't seen this time is fixed
+    // or gone
+    let cleared = queries::clear_scan_errors_before(connection, started)
+        .await
+        .map_err(|e| e.to_string())?;
+    if cleared > 0 {
+        info!("{} scan errors no longer apply", cleared);
+    }
+    Ok(())
+}
+
+/// Syncs only the given files and directories, for changes we were told about instead of found by
+/// walking the whole library. Paths that no longer exist are removed, with everything below them.
+pub async fn sync_paths(
+    connection: &Pool<Postgres>,
+    config: &Config,
+    paths: &[PathBuf],
+    counters: &Arc<ScanCounters>,
+) -> Result<(), String> {
+    let folders = sync_music_folders(connection, config).await?;
+    let files_on_db = files_on_db(connection).await?;
+    let mut roots: Vec<PathBuf> = Vec::new();
+    let mut deleted: HashSet<String> = HashSet::new();
+    for path in paths {
+        let path_string = match path.to_str() {
+            Some(p) => p.to_string(),
+            None => continue,
+        };
+        if path.exists() {
+            roots.push(path.to_owned());
+            continue;
+        }
+        let prefix = format!("{}/", path_string.trim_end_matches('/'));
+        deleted.extend(
+            files_on_db
+                .keys()
+                .filter(|p| **p == path_string || p.starts_with(&prefix))
+                .cloned(),
+        );
+    }
+    // A new directory and the files in it usually all show up in the same batch, walking the
+    // directory is enough
+    roots.sort();
+    roots.dedup_by(|a, b| a.starts_with(b));
+    if !roots.is_empty() {
+        run_pipeline(
+            connection,
+            config,
+            &folders,
+            roots,
+            files_on_db,
+            false,
+            counters,
+        )
+        .await?;
+    }
+    let totals = counters.snapshot();
+    if totals.added + totals.updated + totals.moved == 0 && deleted.is_empty() {
+        return Ok(());
+    }
+    let removed = queries::prune_songs(connection, &deleted.into_iter().collect())
+        .await
+        .map_err(|e| e.to_string())?;
+    counters.removed.fetch_add(removed, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Walks `roots`, reads tags on a pool of workers and writes to the database in batches, each
+/// stage feeding the next through a bounded channel so memory use doesn't grow with the library.
+/// Returns the files in `files_on_db` that weren't found, and where the walk couldn't look.
+async fn run_pipeline(
+    connection: &Pool<Postgres>,
+    config: &Config,
+    folders: &[MusicFolder],
+    roots: Vec<PathBuf>,
+    files_on_db: HashMap<String, FileState>,
+    full: bool,
+    counters: &Arc<ScanCounters>,
+) -> Result<Walked, String> {
+    let (path_sender, path_receiver) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+    let (song_sender, song_receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    let rules = WalkRules::from_config(config)?;
+    let walk_counters = counters.to_owned();
+    let walker = task::spawn_blocking(move || {
+        explorer::walk(
+            roots,
+            files_on_db,
+            full,
+            &rules,
+            path_sender,
+            &walk_counters,
+        )
+    });
+    let workers = worker_count(config);
+    info!("Reading tags with {} workers", workers);
+    let separators = TagSeparators::from_config(config);
+    let parsers: Vec<_> = (0..workers)
+        .map(|_| {
+            let receiver = path_receiver.clone();
+            let sender = song_sender.clone();
+            let counters = counters.to_owned();
+            let separators = separators.to_owned();
+            task::spawn_blocking(move || parse_files(receiver, sender, &separators, &counters))
+        })
+        .collect();
+    // The writer stops once every parser is done with the last file and drops its sender
+    drop(path_receiver);
+    drop(song_sender);
+    let written = write_batches(
+        connection,
+        folders,
+        song_receiver,
+        config.scan_batch_size.max(1),
+        counters,
+    )
+    .await;
+    // If writing failed, the parsers and the walk notice their channel is gone and stop early
+    let walked = walker
+        .await
+        .map_err(|e| format!("Error walking the music folders: {e}"))?;
+    for parser in parsers {
+        parser
+            .await
+            .map_err(|e| format!("Error reading tags: {e}"))?;
+    }
+    written?;
+    record_problems(connection, counters).await?;
+    Ok(walked)
+}
+
+fn worker_count(config: &Config) -> usize {
+    match config.scan_workers {
+        0 => thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1),
+        n => n,
+    }
+}
+
+/// A parser worker, reads tags until the walk is over. Songs are sent on along with whether
+/// they're already in the database.
+fn parse_files(
+    receiver: Receiver<FoundFile>,
+    sender: mpsc::Sender<(ParsedSong, bool)>,
+    separators: &TagSeparators,
+    counters: &ScanCounters,
+) {
+    let mut folder_art = HashMap::new();
+    for found in receiver {
+        let file = match explorer::scan_file(&found.path, found.state) {
+            Ok(Some(f)) => f,
+            // Not audio, or not a format we read
+            Ok(None) => continue,
+            Err(err) => {
+                counters.failed.fetch_add(1, Ordering::Relaxed);
+                counters.problem(ScanStage::Probe, &found.path, err.to_string());
+                continue;
+            }
+        };
+        match tag_parser::parse_file(file, separators, &mut folder_art) {
+            Ok(song) => {
+                counters.parsed.fetch_add(1, Ordering::Relaxed);
+                if sender.blocking_send((song, found.known)).is_err() {
+                    return;
+                }
+            }
+            Err(message) => {
+                counters.failed.fetch_add(1, Ordering::Relaxed);
+                counters.problem(ScanStage::Tag, &found.path, message);
+            }
+        }
+    }
+}
+
+async fn write_batches(
+    connection: &Pool<Postgres>,
+    folders: &[MusicFolder],
+    mut receiver: mpsc::Receiver<(ParsedSong, bool)>,
+    batch_size: usize,
+    counters: &ScanCounters,
+) -> Result<(), String> {
+    let mut batch: Vec<(ParsedSong, bool)> = Vec::with_capacity(batch_size);
+    while let Some(song) = receiver.recv().await {
+        batch.push(song);
+        if batch.len() >= batch_size {
+            write_batch(connection, folders, &mut batch, counters).await?;
+            record_problems(connection, counters).await?;
+            let totals = counters.snapshot();
+            info!(
+                "{} files written to the database",
+                totals.added + totals.updated + totals.moved
+            );
+        }
+    }
+    write_batch(connection, folders, &mut batch, counters).await
+}
+
+async fn write_batch(
+    connection: &Pool<Postgres>,
+    folders: &[MusicFolder],
+    batch: &mut Vec<(ParsedSong, bool)>,
+    counters: &ScanCounters,
+) -> Result<(), String> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    for (parsed, _) in batch.iter_mut() {
+        parsed.song.music_folder_id = music_folder_of(folders, &parsed.song.path);
+    }
+    let result = write_songs(connection, batch, counters).await;
+    match result {
+        Ok(()) => {
+            batch.clear();
+            return Ok(());
+        }
+        // The database is unreachable or gone, there's no point going on
+        Err(err) if !rejected(&err) => return Err(format!("Error writing to the database: {err}")),
+        Err(err) => error!(
+            "Error writing {} songs to the database, retrying one by one: {}",
+            batch.len(),
+            err
+        ),
+    }
+    // Most likely a single song the database won't take, write them one at a time to find it
+    for song in batch.drain(..) {
+        let mut single = [song];
+        match write_songs(connection, &mut single, counters).await {
+            Ok(()) => {}
+            Err(err) if !rejected(&err) => {
+                return Err(format!("Error writing to the database: {err}"))
+            }
+            Err(err) => {
+                let [(parsed, _)] = single;
+                counters.failed.fetch_add(1, Ordering::Relaxed);
+                counters.problem(ScanStage::Insert, &parsed.song.path, err.to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether the database turned down what we sent, or didn't have a row it should have had, as
+/// opposed to not being there to ask.
+fn rejected(err: &sqlx::Error) -> bool {
+    matches!(err, sqlx::Error::Database(_) | sqlx::Error::RowNotFound)
+}
+
+/// Writes songs and counts them as added, updated or moved. Clears the scan errors of their files.
+/// Everything is written in one transaction, so a song is never left without its artists or
+/// genres.
+async fn write_songs(
+    connection: &Pool<Postgres>,
+    songs: &mut [(ParsedSong, bool)],
+    counters: &ScanCounters,
+) -> Result<(), sqlx::Error> {
+    let mut tx = connection.begin().await?;
+    let track_artists: Vec<Artist> = songs
+        .iter()
+        .flat_map(|(p, _)| p.track_artists.iter().cloned())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let db_artists = queries::get_or_add_artists(&mut tx, &track_artists).await?;
+    let mut song_artists: Vec<Vec<Uuid>> = Vec::with_capacity(songs.len());
+    for (parsed, _) in songs.iter_mut() {
+        let ids: Vec<Uuid> = parsed
+            .track_artists
+            .iter()
+            .map(|a| database_sync::find_artist(&db_artists, a).map(|a| a.id))
+            .collect::<Option<_>>()
+            .ok_or(sqlx::Error::RowNotFound)?;
+        parsed.song.artist_id = *ids.first().ok_or(sqlx::Error::RowNotFound)?;
+        song_artists.push(ids);
+    }
+    let genres: Vec<&[String]> = songs.iter().map(|(p, _)| &p.genres[..]).collect();
+    let lyrics: Vec<&[SongLyrics]> = songs.iter().map(|(p, _)| &p.lyrics[..]).collect();
+    let new_songs: Vec<&Song> = songs
+        .iter()
+        .filter(|(_, known)| !known)
+        .map(|(parsed, _)| &parsed.song)
+        .collect();
+    // Moved files take over their old rows first, the upsert below then updates those in place
+    let moved = database_sync::relink_moved(&mut tx, &new_songs).await? as u64;
+    let updated = songs.iter().filter(|(_, known)| *known).count() as u64;
+    let added = songs.len() as u64 - updated - moved;
+    let paths: Vec<String> = songs.iter().map(|(p, _)| p.song.path.to_owned()).collect();
+    database_sync::add_to_database(
+        tag_parser::group(songs.iter().map(|(song, _)| song)),
+        &mut tx,
+    )
+    .await?;
+    queries::set_song_artists(&mut tx, &paths, &song_artists).await?;
+    queries::set_song_genres(&mut tx, &paths, &genres).await?;
+    queries::set_song_lyrics(&mut tx, &paths, &lyrics).await?;
+    queries::clear_scan_errors(&mut tx, &paths).await?;
+    tx.commit().await?;
+    counters.added.fetch_add(added, Ordering::Relaxed);
+    counters.updated.fetch_add(updated, Ordering::Relaxed);
+    counters.moved.fetch_add(moved, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Starts a scan in the background, unless one is already running.
+pub async fn start(state: &DatabaseState, full: bool) -> Result<bool, String> {
+    let kind = if full {
+        ScanKind::Full
+    } else {
+        ScanKind::Incremental
+    };
+    let counters = match state.scan.try_begin(&state.pool, kind).await? {
+        Some(c) => c,
+        None => return Ok(false),
+    };
+    let pool = state.pool.to_owned();
+    let config = state.config.to_owned();
+    let scan = state.scan.to_owned();
+    tokio::spawn(async move {
+        info!("Scan ({}) started", kind.as_str());
+        let result = sync(&pool, &config, full, &counters).await;
+        if let Err(err) = &result {
+            error!("{}", err);
+        }
+        scan.finish(&result);
+    });
+    Ok(true)
+}
+
+async fn scan_status(state: &DatabaseState, format: &ResponseFormat) -> Response {
+    // While scanning, clients show how many files the scan got through so far
+    if let Some((_, _, totals)) = state.scan.progress() {
+        let count = (totals.parsed + totals.failed) as i64;
+        return SubsonicResponse::<ScanStatusResponse>::from_status(true, count).render(format);
+    }
+    match queries::count_songs(&state.pool).await {
+        Ok(count) => {
+            SubsonicResponse::<ScanStatusResponse>::from_status(false, count).render(format)
+        }
+        Err(err) => {
+            error!("Error counting songs: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn start_scan(State(state): State<DatabaseState>, format: ResponseFormat) -> Response {
+    match start(&state, false).await {
+        Ok(true) => {}
+        Ok(false) => info!("Scan requested while another one is running"),
+        Err(err) => error!("{}", err),
+    }
+    scan_status(&state, &format).await
+}
+
+pub async fn get_scan_status(
+    State(state): State<DatabaseState>,
+    format: ResponseFormat,
+) -> Response {
+    scan_status(&state, &format).await
+}
+
+/// The admin view: progress of the running scan and the results of the last one, with timings.
+pub async fn get_scan_report(
+    State(state): State<DatabaseState>,
+    format: ResponseFormat,
+) -> Response {
+    match queries::count_songs(&state.pool).await {
+        Ok(count) => SubsonicResponse::<ScanReportResponse>::from_scan_state(
+            count,
+            state.scan.progress(),
+            state.scan.last(),
+        )
+        .render(&format),
+        Err(err) => {
+            error!("Error counting songs: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct ScanErrorsQuery {
+    #[serde(default)]
+    stage: Option<String>,
+    #[serde(default)]
+    size: Option<i64>,
+    #[serde(default)]
+    offset: Option<i64>,
+}
+
+/// The admin list of files the scanner couldn't read, most recently seen first.
+pub async fn get_scan_errors(
+    State(state): State<DatabaseState>,
+    format: ResponseFormat,
+    query_option: Option<Query<ScanErrorsQuery>>,
+) -> Response {
+    let query = query_option.map(|q| q.0).unwrap_or_default();
+    if let Some(stage) = &query.stage {
+        if ScanStage::from_name(stage).is_none() {
+            let ret: SubsonicResponse<ErrorResponse> = SubsonicResponse::from_error_code(
+                0,
+                format!("unknown stage {stage}, expected walk, probe, tag or insert"),
+            );
+            return ret.render(&format);
+        }
+    }
+    let stage = query.stage.as_deref();
+    let errors = queries::get_scan_errors(
+        &state.pool,
+        stage,
+        query.size.unwrap_or(DEFAULT_SCAN_ERRORS_SIZE).max(0),
+        query.offset.unwrap_or(0).max(0),
+    )
+    .await;
+    let count = queries::count_scan_errors(&state.pool, stage).await;
+    match (count, errors) {
+        (Ok(count), Ok(errors)) => {
+            SubsonicResponse::<ScanErrorsResponse>::from_scan_errors(count, errors).render(&format)
+        }
+        (Err(err), _) | (_, Err(err)) => {
+            error!("Error fetching scan errors: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -0,0 +1,431 @@
+use std::io::SeekFrom;
+use std::str::FromStr;
+
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use chrono::{DateTime, Utc};
+use entities::user::User;
+use log::info;
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::transcoding;
+use crate::DatabaseState;
+
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    id: Uuid,
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(rename = "maxBitRate", default)]
+    max_bit_rate: Option<u32>,
+    #[serde(rename = "timeOffset", default)]
+    time_offset: Option<u32>,
+}
+
+#[axum::debug_handler]
+pub async fn get_stream(
+    query: Option<Query<StreamQuery>>,
+    State(state): State<DatabaseState>,
+    Extension(user): Extension<User>,
+    request_headers: HeaderMap,
+) -> impl IntoResponse {
+    if query.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "No id of resource provided".to_string(),
+        ));
+    }
+    let Query(query) = query.unwrap();
+    let id = query.id;
+
+    let song_result = queries::get_song_by_id(&state.pool, id).await;
+
+    if song_result.is_err() {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error connecting to database".to_string(),
+        ));
+    }
+    let song_option = song_result.unwrap();
+    if song_option.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "No song matching provided id".to_string(),
+        ));
+    }
+    let song = song_option.unwrap();
+
+    let mut profile = transcoding::select(
+        &state.config.transcoding,
+        &song.path,
+        query.format.as_deref(),
+    );
+    // A user with a bitrate limit doesn't get more, even asking for the original file. A bitrate
+    // of 0 is one the scan didn't find out, which could be anything
+    if user.max_bit_rate > 0 {
+        let limit = user.max_bit_rate;
+        let within = match profile {
+            Some(p) => p.encodes(),
+            None => song.bit_rate > 0 && song.bit_rate <= limit,
+        };
+        if !within {
+            profile = transcoding::limiting_profile(&state.config.transcoding, &song.path);
+            if profile.is_none() {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    format!(
+                        "No transcoding profile brings this song under the user's {}kbps",
+                        limit
+                    ),
+                ));
+            }
+        }
+    }
+    if let Some(profile) = profile {
+        // The user's limit applies on top of whatever the client asks for
+        let max_bit_rate = match (query.max_bit_rate.filter(|m| *m > 0), user.max_bit_rate) {
+            (Some(requested), limit) if limit > 0 => Some(requested.min(limit as u32)),
+            (None, limit) if limit > 0 => Some(limit as u32),
+            (requested, _) => requested,
+        };
+        let bitrate = profile.bitrate_for(max_bit_rate);
+        let time_offset = query.time_offset.unwrap_or(0);
+        let gain = profile.gain_for(&song);
+        info!(
+            "Streaming song {} with id {} transcoded with profile {} at {}kbps, {:+.2} dB",
+            song.title, song.id, profile.name, bitrate, gain
+        );
+        let body = match transcoding::transcode(profile, &song.path, bitrate, time_offset, gain) {
+            Ok(b) => b,
+            Err(err) => return Err((StatusCode::INTERNAL_SERVER_ERROR, err)),
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("none"));
+        if let Ok(value) = HeaderValue::from_str(&profile.content_type) {
+            headers.insert(header::CONTENT_TYPE, value);
+        }
+        headers.insert(
+            HeaderName::from_str("X-Content-Duration").unwrap(),
+            HeaderValue::from((song.duration - time_offset as i32).max(0)),
+        );
+        // No Content-Length, so the encoder output goes out chunked
+        return Ok((StatusCode::OK, headers, body).into_response());
+    }
+
+    info!("Streaming song {} with id {}", song.title, song.id);
+
+    let mut response = serve_file(&song.path, &song.content_type, &request_headers).await?;
+    response.headers_mut().insert(
+        HeaderName::from_str("X-Content-Duration").unwrap(),
+        HeaderValue::from(song.duration),
+    );
+    Ok(response)
+}
+
+/// Sends a file from disk honoring `Range`, `If-Range`, `If-None-Match` and `If-Modified-Since`.
+pub async fn serve_file(
+    path: &str,
+    content_type: &str,
+    request_headers: &HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    // `File` implements `AsyncRead`
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(err) => return Err((StatusCode::NOT_FOUND, format!("File not found: {}", err))),
+    };
+    let metadata = match file.metadata().await {
+        Ok(m) => m,
+        Err(err) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Error reading file metadata: {}", err),
+            ))
+        }
+    };
+    let length = metadata.len();
+    let modified: Option<DateTime<Utc>> = metadata.modified().ok().map(DateTime::from);
+    let etag = format!(
+        "\"{:x}-{:x}\"",
+        length,
+        modified.map(|m| m.timestamp()).unwrap_or(0)
+    );
+    let last_modified = modified.map(http_date);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    if let Ok(value) = HeaderValue::from_str(content_type) {
+        headers.insert(header::CONTENT_TYPE, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        headers.insert(header::ETAG, value);
+    }
+    if let Some(value) = last_modified
+        .as_ref()
+        .and_then(|l| HeaderValue::from_str(l).ok())
+    {
+        headers.insert(header::LAST_MODIFIED, value);
+    }
+
+    if !modified_since(request_headers, &etag, modified) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+
+    let range_request = request_headers
+        .get(header::RANGE)
+        .and_then(|r| r.to_str().ok())
+        .filter(|_| if_range_matches(request_headers, &etag, last_modified.as_deref()))
+        .map(|r| parse_range(r, length))
+        .unwrap_or(RangeRequest::Ignored);
+    let (start, end, partial) = match range_request {
+        RangeRequest::Ignored => (0, length.saturating_sub(1), false),
+        RangeRequest::Satisfiable(start, end) => (start, end, true),
+        RangeRequest::Unsatisfiable => {
+            headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{}", length)).unwrap(),
+            );
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+        }
+    };
+
+    let body_length = if length == 0 { 0 } else { end - start + 1 };
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body_length));
+    if start > 0 {
+        if let Err(err) = file.seek(SeekFrom::Start(start)).await {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Error seeking file: {}", err),
+            ));
+        }
+    }
+    // convert the `AsyncRead` into a `Stream`
+    let stream = ReaderStream::new(file.take(body_length));
+    // convert the `Stream` into an `axum::body::HttpBody`
+    let body = Body::from_stream(stream);
+
+    if partial {
+        headers.insert(
+            header::CONTENT_RANGE,
+            HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, length)).unwrap(),
+        );
+        return Ok((StatusCode::PARTIAL_CONTENT, headers, body).into_response());
+    }
+    Ok((StatusCode::OK, headers, body).into_response())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RangeRequest {
+    Satisfiable(u64, u64),
+    Unsatisfiable,
+    // Malformed or multipart ranges, which we answer with the whole file
+    Ignored,
+}
+
+fn parse_range(range: &str, length: u64) -> RangeRequest {
+    let spec = match range.trim().strip_prefix("bytes=") {
+        Some(s) if !s.contains(',') => s.trim(),
+        _ => return RangeRequest::Ignored,
+    };
+    let (start_str, end_str) = match spec.split_once('-') {
+        Some(parts) => parts,
+        None => return RangeRequest::Ignored,
+    };
+    if start_str.is_empty() {
+        // Suffix range, the last N bytes of the file
+        return match end_str.parse::<u64>() {
+            Ok(0) => RangeRequest::Unsatisfiable,
+            Ok(_) if length == 0 => RangeRequest::Unsatisfiable,
+            Ok(suffix) => RangeRequest::Satisfiable(length.saturating_sub(suffix), length - 1),
+            Err(_) => RangeRequest::Ignored,
+        };
+    }
+    let start = match start_str.parse::<u64>() {
+        Ok(s) => s,
+        Err(_) => return RangeRequest::Ignored,
+    };
+    let end = if end_str.is_empty() {
+        length.saturating_sub(1)
+    } else {
+        match end_str.parse::<u64>() {
+            Ok(e) if e >= start => e.min(length.saturating_sub(1)),
+            _ => return RangeRequest::Ignored,
+        }
+    };
+    if start >= length {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Satisfiable(start, end)
+}
+
+/// Returns false when the client's cached copy is still current and a 304 should be sent.
+fn modified_since(
+    request_headers: &HeaderMap,
+    etag: &str,
+    modified: Option<DateTime<Utc>>,
+) -> bool {
+    if let Some(if_none_match) = request_headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|h| h.to_str().ok())
+    {
+        return !if_none_match
+            .split(',')
+            .map(|t| t.trim().trim_start_matches("W/"))
+            .any(|t| t == "*" || t == etag);
+    }
+    let since = request_headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| DateTime::parse_from_rfc2822(h).ok());
+    match (since, modified) {
+        (Some(since), Some(modified)) => modified.timestamp() > since.timestamp(),
+        _ => true,
+    }
+}
+
+/// A range only applies if the `If-Range` validator (when present) still matches the file.
+fn if_range_matches(request_headers: &HeaderMap, etag: &str, last_modified: Option<&str>) -> bool {
+    match request_headers
+        .get(header::IF_RANGE)
+        .and_then(|h| h.to_str().ok())
+    {
+        None => true,
+        Some(validator) => validator == etag || Some(validator) == last_modified,
+    }
+}
+
+fn http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn suffix_ranges_give_the_end_of_the_file() {
+        assert_eq!(
+            parse_range("bytes=-500", 2000),
+            RangeRequest::Satisfiable(1500, 1999)
+        );
+        assert_eq!(
+            parse_range("bytes=-5000", 2000),
+            RangeRequest::Satisfiable(0, 1999)
+        );
+        assert_eq!(parse_range("bytes=-0", 2000), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn open_ended_ranges_run_to_the_end_of_the_file() {
+        assert_eq!(
+            parse_range("bytes=100-", 2000),
+            RangeRequest::Satisfiable(100, 1999)
+        );
+        assert_eq!(
+            parse_range("bytes=100-5000", 2000),
+            RangeRequest::Satisfiable(100, 1999)
+        );
+    }
+
+    #[test]
+    fn ranges_starting_past_the_end_are_unsatisfiable() {
+        assert_eq!(
+            parse_range("bytes=2000-", 2000),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            parse_range("bytes=3000-4000", 2000),
+            RangeRequest::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn malformed_ranges_are_ignored() {
+        for range in [
+            "bytes=abc-",
+            "bytes=10",
+            "bytes=20-10",
+            "bytes=0-1,5-6",
+            "items=0-1",
+        ] {
+            assert_eq!(parse_range(range, 2000), RangeRequest::Ignored, "{}", range);
+        }
+    }
+
+    #[test]
+    fn stale_if_range_validators_drop_the_range() {
+        let etag = "\"abc\"";
+        let date = "Mon, 02 Jun 2025 10:00:00 GMT";
+        assert!(if_range_matches(&HeaderMap::new(), etag, Some(date)));
+        assert!(if_range_matches(
+            &headers(header::IF_RANGE, etag),
+            etag,
+            Some(date)
+        ));
+        assert!(if_range_matches(
+            &headers(header::IF_RANGE, date),
+            etag,
+            Some(date)
+        ));
+        assert!(!if_range_matches(
+            &headers(header::IF_RANGE, "\"old\""),
+            etag,
+            Some(date)
+        ));
+    }
+
+    #[test]
+    fn matching_etags_are_not_modified() {
+        let etag = "\"abc\"";
+        assert!(!modified_since(
+            &headers(header::IF_NONE_MATCH, etag),
+            etag,
+            None
+        ));
+        assert!(!modified_since(
+            &headers(header::IF_NONE_MATCH, "W/\"abc\""),
+            etag,
+            None
+        ));
+        assert!(!modified_since(
+            &headers(header::IF_NONE_MATCH, "\"x\", *"),
+            etag,
+            None
+        ));
+        assert!(modified_since(
+            &headers(header::IF_NONE_MATCH, "\"old\""),
+            etag,
+            None
+        ));
+    }
+
+    #[test]
+    fn if_modified_since_compares_whole_seconds() {
+        let modified = DateTime::parse_from_rfc3339("2025-06-02T10:00:00.5Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let at = headers(header::IF_MODIFIED_SINCE, "Mon, 02 Jun 2025 10:00:00 GMT");
+        let before = headers(header::IF_MODIFIED_SINCE, "Mon, 02 Jun 2025 09:59:59 GMT");
+        assert!(!modified_since(&at, "\"abc\"", Some(modified)));
+        assert!(modified_since(&before, "\"abc\"", Some(modified)));
+        // If-None-Match takes precedence over the date
+        let mut both = at.clone();
+        both.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"old\""));
+        assert!(modified_since(&both, "\"abc\"", Some(modified)));
+    }
+}
@@ -1,98 +1,388 @@
 use std::collections::HashMap;
 use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 use entities::album::Album;
 use entities::artist::Artist;
+use entities::lyrics::SongLyrics;
 use entities::song::Song;
+use id3::frame::TimestampFormat;
 use id3::{Tag, TagLike};
 use log::error;
-use symphonia::core::codecs::CODEC_TYPE_NULL;
+use symphonia::core::codecs::{
+    CodecType, CODEC_TYPE_AAC, CODEC_TYPE_ALAC, CODEC_TYPE_FLAC, CODEC_TYPE_MONKEYS_AUDIO,
+    CODEC_TYPE_MP1, CODEC_TYPE_MP2, CODEC_TYPE_MP3, CODEC_TYPE_NULL, CODEC_TYPE_OPUS,
+    CODEC_TYPE_VORBIS, CODEC_TYPE_WAVPACK,
+};
 
 use symphonia::core::formats::{FormatOptions, Track};
 use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
+use symphonia::core::meta::{MetadataOptions, StandardTagKey};
 use symphonia::core::probe::Hint;
-use symphonia::core::units::Time;
 use uuid::Uuid;
 
+use crate::ape::{self, ApeTag};
+use crate::cover_art;
+use crate::explorer::{ScannedFile, TagType};
+use crate::lyrics;
+use crate::Config;
+
+// Directories whose folder art a parser remembers before starting over
+const FOLDER_ART_CACHE_SIZE: usize = 64;
+// Album artist of compilations that don't name one
+pub const VARIOUS_ARTISTS: &str = "Various Artists";
+const VARIOUS_ARTISTS_MUSICBRAINZ_ID: &str = "89ad4ac3-39f7-470e-963a-56509c546377";
+const ID3V2_HEADER_LENGTH: u64 = 10;
+const ID3V1_LENGTH: u64 = 128;
+
+// Multi-valued fields hold each value the tag has, before they're split on separators
 struct SongTags {
-    artist: String,
+    // Who performs the song
+    artists: Vec<String>,
+    album_artist: Vec<String>,
+    // Flagged as a compilation, a various artists album
+    compilation: bool,
     album: String,
-    duration: i32,
+    audio: AudioProperties,
     track: i32,
     year: i32,
     title: String,
     path: String,
-    genre: String,
+    genres: Vec<String>,
     suffix: String,
-    content_type: String,
     disc_number: i32,
+    embedded_art: bool,
+    musicbrainz: MusicBrainzTags,
+    replay_gain: ReplayGainTags,
+    lyrics: Vec<SongLyrics>,
+}
+
+/// What the audio stream says about itself, rather than the tags.
+#[derive(Default)]
+struct AudioProperties {
+    duration: i32,
+    codec: Option<CodecType>,
+    sample_rate: i32,
+    bit_depth: i32,
+    channels: i32,
+    // In kbps, known up front only for uncompressed audio
+    bit_rate: i32,
 }
 
-pub fn parse(
-    paths: Vec<(String, crate::explorer::TagType)>,
-) -> Result<HashMap<Artist, HashMap<Album, Vec<Song>>>, String> {
-    let mut artists_map: HashMap<String, Artist> = HashMap::new();
-    let mut artists_albums_map: HashMap<Artist, HashMap<Album, Vec<Song>>> = HashMap::new();
-    println!("{}", paths.capacity());
-    for item in paths {
-        let tag_result: Option<SongTags> = tag(&item.0, item.1);
-        if tag_result.is_none() {
-            continue;
+/// Lyrics from tag values that may or may not be LRC, without repeats.
+fn lyrics_from_texts(texts: Vec<String>) -> Vec<SongLyrics> {
+    let mut ret = Vec::new();
+    for text in texts {
+        if let Some(l) = lyrics::from_text(&text, "") {
+            lyrics::push_unique(&mut ret, l);
         }
-        let song_tags = tag_result.unwrap();
+    }
+    ret
+}
 
-        let artist_model: Artist = Artist {
-            id: Uuid::nil(),
-            name: song_tags.artist.to_owned(),
-            album_count: 0,
-        };
-        // If we come across this artist for the first time we push it to the artists hashmap
-        if !artists_map.contains_key(&song_tags.artist) {
-            artists_map.insert(song_tags.artist.to_owned(), artist_model.to_owned());
-            artists_albums_map.insert(artist_model.clone(), HashMap::new());
+/// ReplayGain values, gains in dB and peaks as a fraction of full scale.
+#[derive(Default)]
+struct ReplayGainTags {
+    track_gain: Option<f64>,
+    track_peak: Option<f64>,
+    album_gain: Option<f64>,
+    album_peak: Option<f64>,
+}
+
+impl ReplayGainTags {
+    /// Reads the `REPLAYGAIN_*` values every format but MP4 stores under the same names.
+    fn from_values(get: impl Fn(&str) -> Option<String>) -> Self {
+        ReplayGainTags {
+            track_gain: gain_value(get("REPLAYGAIN_TRACK_GAIN")),
+            track_peak: gain_value(get("REPLAYGAIN_TRACK_PEAK")),
+            album_gain: gain_value(get("REPLAYGAIN_ALBUM_GAIN")),
+            album_peak: gain_value(get("REPLAYGAIN_ALBUM_PEAK")),
         }
+    }
+}
 
-        let album_model = Album {
-            id: Uuid::nil(),
-            name: song_tags.album.to_owned(),
-            year: song_tags.year.to_owned(),
-            artist_id: uuid::Uuid::parse_str("00000000-0000-0000-0000-000000000000").unwrap(),
-            song_count: 0,
-        };
+/// MusicBrainz identifiers, as Picard and other taggers write them.
+#[derive(Default)]
+struct MusicBrainzTags {
+    // One for each of the song's artists
+    artist: Vec<String>,
+    album_artist: Option<String>,
+    album: Option<String>,
+    release_group: Option<String>,
+    track: Option<String>,
+}
 
-        // If we don't already have this album, we add it
-        if !artists_albums_map
-            .get(&artist_model)
-            .unwrap()
-            .contains_key(&album_model)
-        {
-            artists_albums_map
-                .get_mut(&artist_model)
-                .unwrap()
-                .insert(album_model.to_owned(), Vec::new());
+/// What splits artist and genre tag values holding more than one, on top of the null bytes
+/// ID3v2.4 and APEv2 separate values with.
+#[derive(Clone)]
+pub struct TagSeparators {
+    artist: Vec<String>,
+    genre: Vec<String>,
+}
+
+impl TagSeparators {
+    pub fn from_config(config: &Config) -> Self {
+        TagSeparators {
+            artist: config.artist_separators.to_owned(),
+            genre: config.genre_separators.to_owned(),
         }
-        let song_model: Song = Song {
-            id: Uuid::nil(),
-            title: song_tags.title.to_owned(),
-            duration: song_tags.duration.to_owned(),
-            track: song_tags.track.to_owned(),
-            album_id: uuid::Uuid::parse_str("00000000-0000-0000-0000-000000000000").unwrap(),
-            path: song_tags.path,
-            genre: song_tags.genre,
-            suffix: song_tags.suffix,
-            content_type: song_tags.content_type,
-            disc_number: song_tags.disc_number,
+    }
+}
+
+/// One file's tags, along with the artist and album it goes under.
+#[derive(Clone)]
+pub struct ParsedSong {
+    // The album artist
+    pub artist: Artist,
+    pub album: Album,
+    pub song: Song,
+    // Who performs the song, the first one goes in `song.artist_id` once the artists are in the
+    // database
+    pub track_artists: Vec<Artist>,
+    pub genres: Vec<String>,
+    // Embedded lyrics first, then those of `.lrc` and `.txt` files
+    pub lyrics: Vec<SongLyrics>,
+}
+
+/// Reads the tags of a single file. `folder_art` remembers the folder art of directories already
+/// seen, since the songs of an album usually share one.
+pub fn parse_file(
+    item: ScannedFile,
+    separators: &TagSeparators,
+    folder_art: &mut HashMap<PathBuf, Option<String>>,
+) -> Result<ParsedSong, String> {
+    let song_tags =
+        tag(&item.path, item.tag_type).ok_or("the file does not have a tag we can read")?;
+
+    let album_artists = split_values(&song_tags.album_artist, &[]);
+    let mut track_artists = split_values(&song_tags.artists, &separators.artist);
+    if track_artists.is_empty() {
+        track_artists = split_values(&song_tags.album_artist, &separators.artist);
+    }
+    if track_artists.is_empty() {
+        track_artists.push(String::new());
+    }
+    let genres = split_values(&song_tags.genres, &separators.genre);
+    let musicbrainz = &song_tags.musicbrainz;
+    // Taggers join IDs with `/` where the format can't hold several
+    let artist_ids = split_values(&musicbrainz.artist, &["/".to_string()]);
+    let (album_artist, album_artist_id) = if !album_artists.is_empty() {
+        (
+            album_artists.join("; "),
+            identifier(&musicbrainz.album_artist),
+        )
+    } else if song_tags.compilation {
+        (
+            VARIOUS_ARTISTS.to_string(),
+            Some(VARIOUS_ARTISTS_MUSICBRAINZ_ID.to_string()),
+        )
+    } else {
+        let id = match artist_ids.as_slice() {
+            [id] if track_artists.len() == 1 => Some(id.to_owned()),
+            _ => None,
         };
+        (track_artists.join("; "), id)
+    };
+    // IDs only go with names when there's one for each
+    let identified = artist_ids.len() == track_artists.len();
+    let track_artists: Vec<Artist> = track_artists
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| Artist {
+            id: Uuid::nil(),
+            name,
+            album_count: 0,
+            musicbrainz_id: artist_ids.get(i).filter(|_| identified).cloned(),
+        })
+        .collect();
+    let artist = Artist {
+        id: Uuid::nil(),
+        name: album_artist,
+        album_count: 0,
+        musicbrainz_id: album_artist_id,
+    };
+    let album = Album {
+        id: Uuid::nil(),
+        name: song_tags.album.to_owned(),
+        year: song_tags.year.to_owned(),
+        artist_id: Uuid::nil(),
+        song_count: 0,
+        art_source: cover_art::ART_NONE.to_string(),
+        art_path: None,
+        musicbrainz_id: identifier(&musicbrainz.album),
+        musicbrainz_release_group_id: identifier(&musicbrainz.release_group),
+    };
+    let (art_source, art_path) = if song_tags.embedded_art {
+        (cover_art::ART_EMBEDDED, Some(song_tags.path.to_owned()))
+    } else {
+        let dir = Path::new(&song_tags.path)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        if folder_art.len() >= FOLDER_ART_CACHE_SIZE && !folder_art.contains_key(&dir) {
+            // Directories come one after the other, old ones won't be asked about again
+            folder_art.clear();
+        }
+        let art = folder_art
+            .entry(dir.to_owned())
+            .or_insert_with(|| cover_art::find_folder_art(&dir));
+        match art {
+            Some(p) => (cover_art::ART_FOLDER, Some(p.to_owned())),
+            None => (cover_art::ART_NONE, None),
+        }
+    };
+    let payload = audio_payload(&item.path);
+    let audio = &song_tags.audio;
+    // The average over the audio alone, embedded art would easily double it otherwise
+    let bit_rate = match (audio.bit_rate, audio.duration) {
+        (0, 0) => 0,
+        (0, seconds) => {
+            let bytes = payload
+                .as_ref()
+                .map_or(item.state.size as u64, |p| p.end - p.start);
+            (bytes * 8 / seconds as u64 / 1000) as i32
+        }
+        (bit_rate, _) => bit_rate,
+    };
+    let song = Song {
+        id: Uuid::nil(),
+        title: song_tags.title.to_owned(),
+        duration: audio.duration,
+        track: song_tags.track.to_owned(),
+        album_id: Uuid::nil(),
+        path: song_tags.path,
+        genre: genres.first().cloned().unwrap_or_default(),
+        content_type: mime_type(audio.codec, &song_tags.suffix).to_string(),
+        suffix: song_tags.suffix,
+        disc_number: song_tags.disc_number,
+        art_source: art_source.to_string(),
+        art_path,
+        size: item.state.size,
+        mtime: item.state.mtime,
+        hash: hash_file(&item.path, payload),
+        // Filled in by the scan, which knows the folders
+        music_folder_id: None,
+        artist_id: Uuid::nil(),
+        musicbrainz_id: identifier(&musicbrainz.track),
+        track_gain: song_tags.replay_gain.track_gain,
+        track_peak: song_tags.replay_gain.track_peak,
+        album_gain: song_tags.replay_gain.album_gain,
+        album_peak: song_tags.replay_gain.album_peak,
+        bit_rate,
+        sample_rate: audio.sample_rate,
+        bit_depth: audio.bit_depth,
+        channels: audio.channels,
+    };
+    let mut lyrics = song_tags.lyrics;
+    for sidecar in lyrics::find_sidecar_lyrics(&item.path) {
+        lyrics::push_unique(&mut lyrics, sidecar);
+    }
+    Ok(ParsedSong {
+        artist,
+        album,
+        song,
+        track_artists,
+        genres,
+        lyrics,
+    })
+}
 
+/// Groups parsed songs by artist and album, the shape `database_sync` writes.
+pub fn group<'a>(
+    parsed: impl IntoIterator<Item = &'a ParsedSong>,
+) -> HashMap<&'a Artist, HashMap<&'a Album, Vec<&'a Song>>> {
+    let mut artists_albums_map: HashMap<&Artist, HashMap<&Album, Vec<&Song>>> = HashMap::new();
+    for item in parsed {
         artists_albums_map
-            .get_mut(&artist_model)
-            .unwrap()
-            .get_mut(&album_model.to_owned())
-            .unwrap()
-            .push(song_model);
+            .entry(&item.artist)
+            .or_default()
+            .entry(&item.album)
+            .or_default()
+            .push(&item.song);
     }
-    Ok(artists_albums_map)
+    artists_albums_map
+}
+
+/// blake3 of the audio, which lets us recognize a file even after it's moved or renamed, and
+/// retagged.
+fn hash_file(path: &str, payload: Option<Range<u64>>) -> Option<String> {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(err) => {
+            error!("Error opening {} to hash it: {}", path, err);
+            return None;
+        }
+    };
+    let mut hasher = blake3::Hasher::new();
+    let result = match payload {
+        Some(range) => file
+            .seek(SeekFrom::Start(range.start))
+            .and_then(|_| hasher.update_reader(file.take(range.end - range.start))),
+        // Formats that keep their tags amid the audio are hashed whole
+        None => hasher.update_reader(file),
+    };
+    if let Err(err) = result {
+        error!("Error hashing {}: {}", path, err);
+        return None;
+    }
+    Some(hasher.finalize().to_hex().to_string())
+}
+
+/// Where the audio of a file is, leaving out ID3v2 tags and FLAC metadata at the start and APE
+/// and ID3v1 tags at the end. None if there's nothing left, or the file can't be read.
+fn audio_payload(path: &str) -> Option<Range<u64>> {
+    payload_range(&mut File::open(path).ok()?)
+}
+
+fn payload_range<R: Read + Seek>(file: &mut R) -> Option<Range<u64>> {
+    let length = file.seek(SeekFrom::End(0)).ok()?;
+    file.seek(SeekFrom::Start(0)).ok()?;
+    let mut start = 0;
+    let mut header = [0u8; ID3V2_HEADER_LENGTH as usize];
+    // Some files have had more than one ID3v2 tag put in front of them
+    while file.read_exact(&mut header).is_ok() && &header[0..3] == b"ID3" {
+        let size = header[6..10]
+            .iter()
+            .fold(0u64, |size, b| size << 7 | u64::from(b & 0x7f));
+        // A footer repeats the header at the end
+        let footer = if header[5] & 0x10 != 0 {
+            ID3V2_HEADER_LENGTH
+        } else {
+            0
+        };
+        start += ID3V2_HEADER_LENGTH + size + footer;
+        file.seek(SeekFrom::Start(start)).ok()?;
+    }
+    file.seek(SeekFrom::Start(start)).ok()?;
+    let mut marker = [0u8; 4];
+    if file.read_exact(&mut marker).is_ok() && &marker == b"fLaC" {
+        start += 4;
+        // Metadata blocks up to the one flagged as the last
+        loop {
+            let mut block = [0u8; 4];
+            file.read_exact(&mut block).ok()?;
+            let size = u64::from(u32::from_be_bytes([0, block[1], block[2], block[3]]));
+            start += 4 + size;
+            if block[0] & 0x80 != 0 {
+                break;
+            }
+            file.seek(SeekFrom::Start(start)).ok()?;
+        }
+    }
+    let mut end = length;
+    if end >= ID3V1_LENGTH {
+        let mut tag = [0u8; 3];
+        file.seek(SeekFrom::Start(end - ID3V1_LENGTH)).ok()?;
+        if file.read_exact(&mut tag).is_ok() && &tag == b"TAG" {
+            end -= ID3V1_LENGTH;
+        }
+    }
+    if let Some(tag_start) = ape::tag_start(file, end) {
+        end = tag_start;
+    }
+    (start < end).then_some(start..end)
 }
 
 fn tag_id3(path: &str) -> Option<SongTags> {
@@ -109,28 +399,87 @@ fn tag_id3(path: &str) -> Option<SongTags> {
             .to_owned()
             .nth(path_split.into_iter().collect::<Vec<_>>().len() - 1)
             .unwrap_or("");
-        let mut metadata_option = get_metadata(path.to_string(), suffix.to_string());
-        if metadata_option.is_none() {
+        let mut audio = get_metadata(path.to_string(), suffix.to_string()).unwrap_or_default();
+        if audio.duration == 0 {
             // We have a tag but can't decode this.
-            metadata_option = Some((Time::from(tag.duration().unwrap_or(0)), suffix.to_string()));
+            audio.duration = tag.duration().unwrap_or(0) as i32;
+        }
+        let compilation = tag
+            .get("TCMP")
+            .and_then(|f| f.content().text())
+            .is_some_and(flag);
+        // Only ID3v2.4 separates values with null bytes, the id3 crate turns the `/` of older
+        // versions into null bytes too, which would split names like AC/DC
+        let values = |id: &str| -> Vec<String> {
+            let text = match tag.get(id).and_then(|f| f.content().text()) {
+                Some(t) => t,
+                None => return Vec::new(),
+            };
+            match tag.version() {
+                id3::Version::Id3v24 => text.split('\0').map(|v| v.to_string()).collect(),
+                _ => vec![text.replace('\0', "/")],
+            }
+        };
+        let single = |text: Option<&str>| -> String {
+            let text = text.unwrap_or("");
+            match tag.version() {
+                id3::Version::Id3v24 => first_value(text),
+                _ => text.replace('\0', "/"),
+            }
+        };
+        let txxx = |description: &str| -> Option<String> {
+            tag.extended_texts()
+                .find(|t| t.description.eq_ignore_ascii_case(description))
+                .map(|t| t.value.to_owned())
+        };
+        let mut lyrics: Vec<SongLyrics> = Vec::new();
+        for sylt in tag.synchronised_lyrics() {
+            let found = match sylt.timestamp_format {
+                TimestampFormat::Ms => lyrics::from_timed_lines(&sylt.content, &sylt.lang),
+                // Times in MPEG frames, the text is all that can be used
+                TimestampFormat::Mpeg => {
+                    let text: Vec<&str> = sylt.content.iter().map(|(_, t)| t.as_str()).collect();
+                    lyrics::from_text(&text.concat(), &sylt.lang)
+                }
+            };
+            if let Some(l) = found {
+                lyrics::push_unique(&mut lyrics, l);
+            }
         }
-        let metadata = metadata_option?;
-        let artist = tag.album_artist().unwrap_or(tag.artist().unwrap_or(""));
-        let album = tag.album().unwrap_or("");
-        let title = tag.title().unwrap_or("");
-        let genre = tag.genre().unwrap_or("");
+        for uslt in tag.lyrics() {
+            if let Some(l) = lyrics::from_text(&uslt.text, &uslt.lang) {
+                lyrics::push_unique(&mut lyrics, l);
+            }
+        }
+        let musicbrainz = MusicBrainzTags {
+            artist: txxx("MusicBrainz Artist Id").into_iter().collect(),
+            album_artist: txxx("MusicBrainz Album Artist Id"),
+            album: txxx("MusicBrainz Album Id"),
+            release_group: txxx("MusicBrainz Release Group Id"),
+            // Picard keeps the recording in a UFID frame
+            track: tag
+                .unique_file_identifiers()
+                .find(|u| u.owner_identifier == "http://musicbrainz.org")
+                .map(|u| String::from_utf8_lossy(&u.identifier).to_string())
+                .or(txxx("MusicBrainz Track Id")),
+        };
         let song = SongTags {
-            artist: str::replace(artist, char::from(0), "?"),
-            album: str::replace(album, char::from(0), "?"),
-            duration: metadata.0.seconds as i32,
+            artists: values("TPE1"),
+            album_artist: values("TPE2"),
+            compilation,
+            album: single(tag.album()),
+            audio,
             track: tag.track().unwrap_or(0) as i32,
             year: tag.year().unwrap_or(0),
-            title: str::replace(title, char::from(0), "?"),
+            title: single(tag.title()),
             path: path.to_string(),
-            genre: str::replace(genre, char::from(0), "?"),
+            genres: values("TCON"),
             suffix: suffix.to_string(),
-            content_type: format!("audio/{}", metadata.1),
             disc_number: tag.disc().unwrap_or(1) as i32,
+            embedded_art: tag.pictures().next().is_some(),
+            musicbrainz,
+            replay_gain: ReplayGainTags::from_values(txxx),
+            lyrics,
         };
         return Some(song);
     }
@@ -146,6 +495,12 @@ fn parse_vorbis_comment(tag: &metaflac::Tag, tag_name: &str) -> Option<String> {
     Some(comment.unwrap().to_string())
 }
 
+/// Every value of a comment that can be repeated, like `ARTIST`.
+fn vorbis_comment_values(tag: &metaflac::Tag, tag_name: &str) -> Vec<String> {
+    tag.get_vorbis(tag_name)
+        .map_or(Vec::new(), |v| v.map(|s| s.to_string()).collect())
+}
+
 fn parse_vorbis_comment_integer(tag: &metaflac::Tag, tag_name: &str) -> i32 {
     let track_str = parse_vorbis_comment(tag, tag_name).unwrap_or("".into());
     match track_str.as_str() {
@@ -163,50 +518,324 @@ fn tag_flac(path: &str) -> Option<SongTags> {
 
     if let Some(tag) = this_tag {
         let path_split = path.split('.');
-        let suffix = path_split.clone().last().unwrap_or("");
-        let metadata_option = get_metadata(path.to_string(), suffix.to_string());
-        let (duration, suffix): (i32, String) = match metadata_option {
-            Some(metadata) => (metadata.0.seconds as i32, metadata.1.to_string()),
-            None => (0, suffix.to_string()),
-        };
-        let artist = parse_vorbis_comment(&tag, "ALBUMARTIST").unwrap_or(
-            parse_vorbis_comment(&tag, "ARTIST")
-                .unwrap_or("".to_string())
-                .to_string(),
-        );
+        let suffix = path_split.clone().next_back().unwrap_or("");
+        // The stream info block has it all too, should symphonia not manage
+        let audio = get_metadata(path.to_string(), suffix.to_string()).unwrap_or_else(|| match tag
+            .get_streaminfo()
+        {
+            Some(info) if info.sample_rate > 0 => AudioProperties {
+                duration: (info.total_samples / info.sample_rate as u64) as i32,
+                codec: Some(CODEC_TYPE_FLAC),
+                sample_rate: info.sample_rate as i32,
+                bit_depth: info.bits_per_sample as i32,
+                channels: info.num_channels as i32,
+                bit_rate: 0,
+            },
+            _ => AudioProperties::default(),
+        });
+        let compilation = parse_vorbis_comment(&tag, "COMPILATION").is_some_and(|c| flag(&c));
         let album = parse_vorbis_comment(&tag, "ALBUM").unwrap_or("".into());
         let title = parse_vorbis_comment(&tag, "TITLE").unwrap_or("".into());
-        let genre = parse_vorbis_comment(&tag, "GENRE").unwrap_or("".into());
         let track = parse_vorbis_comment_integer(&tag, "TRACK");
         let year = parse_vorbis_comment_integer(&tag, "YEAR");
         let disc_number = parse_vorbis_comment_integer(&tag, "DISCNUMBER");
+        let musicbrainz = MusicBrainzTags {
+            artist: vorbis_comment_values(&tag, "MUSICBRAINZ_ARTISTID"),
+            album_artist: parse_vorbis_comment(&tag, "MUSICBRAINZ_ALBUMARTISTID"),
+            album: parse_vorbis_comment(&tag, "MUSICBRAINZ_ALBUMID"),
+            release_group: parse_vorbis_comment(&tag, "MUSICBRAINZ_RELEASEGROUPID"),
+            track: parse_vorbis_comment(&tag, "MUSICBRAINZ_TRACKID"),
+        };
         let song = SongTags {
-            artist: str::replace(&artist, char::from(0), "?"),
-            album: str::replace(&album, char::from(0), "?"),
-            duration,
+            artists: vorbis_comment_values(&tag, "ARTIST"),
+            album_artist: vorbis_comment_values(&tag, "ALBUMARTIST"),
+            compilation,
+            album: first_value(&album),
+            audio,
             track,
             year,
-            title: str::replace(&title, char::from(0), "?"),
+            title: first_value(&title),
             path: path.to_string(),
-            genre: str::replace(&genre, char::from(0), "?"),
+            genres: vorbis_comment_values(&tag, "GENRE"),
             suffix: suffix.to_string(),
-            content_type: format!("audio/{}", suffix),
             disc_number,
+            embedded_art: tag.pictures().next().is_some(),
+            musicbrainz,
+            replay_gain: ReplayGainTags::from_values(|name| parse_vorbis_comment(&tag, name)),
+            lyrics: lyrics_from_texts(
+                [
+                    vorbis_comment_values(&tag, "LYRICS"),
+                    vorbis_comment_values(&tag, "UNSYNCEDLYRICS"),
+                ]
+                .concat(),
+            ),
         };
         return Some(song);
     }
     None
 }
-fn tag(path: &str, t: crate::explorer::TagType) -> Option<SongTags> {
+/// Ogg and MP4 tags, which symphonia reads while probing the file.
+fn tag_symphonia(path: &str) -> Option<SongTags> {
+    let suffix = Path::new(path)
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_string();
+    let src = File::open(path).ok()?;
+    let media_source_stream = MediaSourceStream::new(Box::new(src), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(suffix.as_str());
+    let mut probed = match symphonia::default::get_probe().format(
+        &hint,
+        media_source_stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) {
+        Ok(p) => p,
+        Err(err) => {
+            error!("Error parsing {} with Symphonia: {}", path, err);
+            return None;
+        }
+    };
+    // Tags can be found both in the container and ahead of it
+    let mut tags: Vec<symphonia::core::meta::Tag> = Vec::new();
+    let mut embedded_art = false;
+    if let Some(metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.current() {
+            tags.extend(revision.tags().iter().cloned());
+            embedded_art |= !revision.visuals().is_empty();
+        }
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.extend(revision.tags().iter().cloned());
+        embedded_art |= !revision.visuals().is_empty();
+    }
+    let audio = match first_supported_track(probed.format.tracks()) {
+        Some(track) => track_metadata(track),
+        None => {
+            error!("File {} has no audio we can read", path);
+            return None;
+        }
+    };
+    let get = |key: StandardTagKey| -> Option<String> {
+        tags.iter()
+            .find(|t| t.std_key == Some(key))
+            .map(|t| t.value.to_string())
+    };
+    let all = |key: StandardTagKey| -> Vec<String> {
+        tags.iter()
+            .filter(|t| t.std_key == Some(key))
+            .map(|t| t.value.to_string())
+            .collect()
+    };
+    // Symphonia doesn't know the Vorbis comment by its usual name
+    let compilation = get(StandardTagKey::Compilation)
+        .or(tags
+            .iter()
+            .find(|t| t.key.eq_ignore_ascii_case("COMPILATION"))
+            .map(|t| t.value.to_string()))
+        .is_some_and(|c| flag(&c));
+    let mut replay_gain = ReplayGainTags {
+        track_gain: gain_value(get(StandardTagKey::ReplayGainTrackGain)),
+        track_peak: gain_value(get(StandardTagKey::ReplayGainTrackPeak)),
+        album_gain: gain_value(get(StandardTagKey::ReplayGainAlbumGain)),
+        album_peak: gain_value(get(StandardTagKey::ReplayGainAlbumPeak)),
+    };
+    // Opus files carry R128 gains instead, Q7.8 numbers relative to -23 LUFS where ReplayGain
+    // aims at -18
+    let r128 = |key: &str| -> Option<f64> {
+        tags.iter()
+            .find(|t| t.key.eq_ignore_ascii_case(key))
+            .and_then(|t| t.value.to_string().trim().parse::<i16>().ok())
+            .map(|q| q as f64 / 256.0 + 5.0)
+    };
+    replay_gain.track_gain = replay_gain.track_gain.or(r128("R128_TRACK_GAIN"));
+    replay_gain.album_gain = replay_gain.album_gain.or(r128("R128_ALBUM_GAIN"));
+    // Symphonia only knows `LYRICS` as lyrics, foobar2000 and Mp3tag write unsynced ones to an
+    // `UNSYNCEDLYRICS` comment
+    let mut lyrics = all(StandardTagKey::Lyrics);
+    lyrics.extend(
+        tags.iter()
+            .filter(|t| t.key.eq_ignore_ascii_case("UNSYNCEDLYRICS"))
+            .map(|t| t.value.to_string()),
+    );
+    let album = get(StandardTagKey::Album).unwrap_or_default();
+    let title = get(StandardTagKey::TrackTitle).unwrap_or_default();
+    Some(SongTags {
+        artists: all(StandardTagKey::Artist),
+        album_artist: all(StandardTagKey::AlbumArtist),
+        compilation,
+        album: first_value(&album),
+        audio,
+        track: leading_number(get(StandardTagKey::TrackNumber)).unwrap_or(0),
+        year: leading_number(get(StandardTagKey::Date).or(get(StandardTagKey::OriginalDate)))
+            .unwrap_or(0),
+        title: first_value(&title),
+        path: path.to_string(),
+        genres: all(StandardTagKey::Genre),
+        suffix,
+        disc_number: leading_number(get(StandardTagKey::DiscNumber)).unwrap_or(1),
+        embedded_art,
+        musicbrainz: MusicBrainzTags {
+            artist: all(StandardTagKey::MusicBrainzArtistId),
+            album_artist: get(StandardTagKey::MusicBrainzAlbumArtistId),
+            album: get(StandardTagKey::MusicBrainzAlbumId),
+            release_group: get(StandardTagKey::MusicBrainzReleaseGroupId),
+            track: get(StandardTagKey::MusicBrainzTrackId)
+                .or(get(StandardTagKey::MusicBrainzRecordingId)),
+        },
+        replay_gain,
+        lyrics: lyrics_from_texts(lyrics),
+    })
+}
+
+/// APEv2 tags, found on Monkey's Audio and WavPack files.
+fn tag_ape(path: &str) -> Option<SongTags> {
+    let tag = ApeTag::read_from_path(path)?;
+    let suffix = Path::new(path)
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_string();
+    // Symphonia can't open either format, their headers are simple enough to read ourselves
+    let audio = match ape::stream_info(path) {
+        Some(info) => AudioProperties {
+            duration: info.seconds as i32,
+            codec: Some(match suffix.to_lowercase().as_str() {
+                "wv" => CODEC_TYPE_WAVPACK,
+                _ => CODEC_TYPE_MONKEYS_AUDIO,
+            }),
+            sample_rate: info.sample_rate as i32,
+            bit_depth: info.bit_depth as i32,
+            channels: info.channels as i32,
+            bit_rate: 0,
+        },
+        None => get_metadata(path.to_string(), suffix.to_owned()).unwrap_or_default(),
+    };
+    let mut album_artist = tag.get_all("Album Artist");
+    if album_artist.is_empty() {
+        album_artist = tag.get_all("AlbumArtist");
+    }
+    let album = tag.get("Album").unwrap_or_default();
+    let title = tag.get("Title").unwrap_or_default();
+    Some(SongTags {
+        artists: tag.get_all("Artist"),
+        album_artist,
+        compilation: tag.get("Compilation").is_some_and(|c| flag(&c)),
+        album: first_value(&album),
+        audio,
+        track: leading_number(tag.get("Track")).unwrap_or(0),
+        year: leading_number(tag.get("Year")).unwrap_or(0),
+        title: first_value(&title),
+        path: path.to_string(),
+        genres: tag.get_all("Genre"),
+        suffix,
+        disc_number: leading_number(tag.get("Disc")).unwrap_or(1),
+        embedded_art: tag.cover().is_some(),
+        musicbrainz: MusicBrainzTags {
+            artist: tag.get_all("MUSICBRAINZ_ARTISTID"),
+            album_artist: tag.get("MUSICBRAINZ_ALBUMARTISTID"),
+            album: tag.get("MUSICBRAINZ_ALBUMID"),
+            release_group: tag.get("MUSICBRAINZ_RELEASEGROUPID"),
+            track: tag.get("MUSICBRAINZ_TRACKID"),
+        },
+        replay_gain: ReplayGainTags::from_values(|name| tag.get(name)),
+        lyrics: lyrics_from_texts(
+            [tag.get("Lyrics"), tag.get("UNSYNCEDLYRICS")]
+                .into_iter()
+                .flatten()
+                .collect(),
+        ),
+    })
+}
+
+/// The number a tag value starts with, for values like `3/12` or `2019-05-01`.
+fn leading_number(value: Option<String>) -> Option<i32> {
+    let value = value?;
+    let digits: String = value
+        .trim()
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+/// Splits tag values on null bytes and `separators`, trimmed, without empty or repeated ones.
+fn split_values(values: &[String], separators: &[String]) -> Vec<String> {
+    let mut ret: Vec<String> = Vec::new();
+    for value in values {
+        let mut value = value.to_owned();
+        for separator in separators.iter().filter(|s| !s.is_empty()) {
+            value = value.replace(separator.as_str(), "\0");
+        }
+        for part in value.split('\0').map(str::trim).filter(|p| !p.is_empty()) {
+            if !ret.iter().any(|r| r == part) {
+                ret.push(part.to_string());
+            }
+        }
+    }
+    ret
+}
+
+/// The first of the null-separated values of a tag that only has room for one, like a title.
+fn first_value(value: &str) -> String {
+    value
+        .split('\0')
+        .find(|v| !v.is_empty())
+        .unwrap_or("")
+        .to_string()
+}
+
+/// The number in a ReplayGain value like `-6.48 dB`, `+1.5dB` or `0.988553`.
+fn gain_value(value: Option<String>) -> Option<f64> {
+    let value = value?;
+    let number = value
+        .trim()
+        .trim_end_matches(|c: char| c.is_ascii_alphabetic())
+        .trim_end();
+    number
+        .trim_start_matches('+')
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite())
+}
+
+/// A MusicBrainz ID, if the tag has one.
+fn identifier(value: &Option<String>) -> Option<String> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+}
+
+/// Whether a yes/no tag value says yes.
+fn flag(value: &str) -> bool {
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "1" | "true" | "yes"
+    )
+}
+
+fn tag(path: &str, t: TagType) -> Option<SongTags> {
     match t {
-        crate::explorer::TagType::Id3 => tag_id3(path),
-        crate::explorer::TagType::Flac => tag_flac(path),
+        // MP3s without an ID3v2 tag sometimes have an APEv2 one
+        TagType::Id3 => tag_id3(path).or_else(|| tag_ape(path)),
+        TagType::Flac => tag_flac(path),
+        TagType::Ogg | TagType::Mp4 => tag_symphonia(path),
+        TagType::Ape => tag_ape(path),
     }
 }
 
-fn get_metadata(path: String, suffix: String) -> Option<(Time, String)> {
+fn get_metadata(path: String, suffix: String) -> Option<AudioProperties> {
     // Open the media source.
-    let src = File::open(&path).expect("failed to open media");
+    let src = match File::open(&path) {
+        Ok(f) => f,
+        Err(err) => {
+            error!("Error opening {}: {}", path, err);
+            return None;
+        }
+    };
 
     // Create the media source stream.
     let media_source_stream = MediaSourceStream::new(Box::new(src), Default::default());
@@ -234,13 +863,57 @@ fn get_metadata(path: String, suffix: String) -> Option<(Time, String)> {
         Err(_) => return None,
     };
     let track_option = first_supported_track(probed.format.tracks());
-    let track = track_option?;
+    Some(track_metadata(track_option?))
+}
+
+/// What the codec parameters of a track say, the length only if the container knows it.
+fn track_metadata(track: &Track) -> AudioProperties {
     let params = &track.codec_params;
-    let n_frames = params.n_frames?;
-    let tb = params.time_base?;
-    let time = tb.calc_time(n_frames);
-    let codec = params.codec.to_string();
-    Some((time, codec))
+    let duration = match (params.n_frames, params.time_base) {
+        (Some(n_frames), Some(tb)) => tb.calc_time(n_frames).seconds as i32,
+        _ => 0,
+    };
+    let sample_rate = params.sample_rate.unwrap_or(0);
+    let channels = params.channels.map_or(0, |c| c.count() as u32);
+    // Only PCM says how many bits it stores each sample in, which makes its bitrate exact
+    let bit_rate = params
+        .bits_per_coded_sample
+        .map_or(0, |bits| (bits * sample_rate * channels / 1000) as i32);
+    AudioProperties {
+        duration,
+        codec: Some(params.codec),
+        sample_rate: sample_rate as i32,
+        // Lossy codecs have no bit depth to speak of
+        bit_depth: params.bits_per_sample.unwrap_or(0) as i32,
+        channels: channels as i32,
+        bit_rate,
+    }
+}
+
+/// The MIME type of a file, by codec where symphonia found one and by suffix otherwise.
+fn mime_type(codec: Option<CodecType>, suffix: &str) -> &'static str {
+    match codec {
+        Some(CODEC_TYPE_MP1 | CODEC_TYPE_MP2 | CODEC_TYPE_MP3) => return "audio/mpeg",
+        Some(CODEC_TYPE_FLAC) => return "audio/flac",
+        Some(CODEC_TYPE_VORBIS | CODEC_TYPE_OPUS) => return "audio/ogg",
+        Some(CODEC_TYPE_AAC | CODEC_TYPE_ALAC) => return "audio/mp4",
+        Some(CODEC_TYPE_WAVPACK) => return "audio/x-wavpack",
+        Some(CODEC_TYPE_MONKEYS_AUDIO) => return "audio/x-ape",
+        _ => {}
+    }
+    // PCM says nothing about the container it's in
+    match suffix.to_lowercase().as_str() {
+        "mp3" | "mp2" | "mpga" => "audio/mpeg",
+        "flac" => "audio/flac",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "m4a" | "m4b" | "mp4" | "aac" => "audio/mp4",
+        "wav" => "audio/wav",
+        "aif" | "aiff" => "audio/aiff",
+        "wv" => "audio/x-wavpack",
+        "ape" => "audio/x-ape",
+        "wma" => "audio/x-ms-wma",
+        _ => "application/octet-stream",
+    }
 }
 
 fn first_supported_track(tracks: &[Track]) -> Option<&Track> {
@@ -248,3 +921,108 @@ fn first_supported_track(tracks: &[Track]) -> Option<&Track> {
         .iter()
         .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn payload(parts: &[&[u8]]) -> Option<Range<u64>> {
+        payload_range(&mut Cursor::new(parts.concat()))
+    }
+
+    fn id3v2(size: u8) -> Vec<u8> {
+        let mut tag = b"ID3\x03\x00\x00\x00\x00\x00".to_vec();
+        tag.push(size);
+        tag.extend(vec![0u8; size as usize]);
+        tag
+    }
+
+    fn ape_tag() -> Vec<u8> {
+        let part = |flags: u32| {
+            let mut part = b"APETAGEX".to_vec();
+            part.extend(2000u32.to_le_bytes());
+            part.extend(32u32.to_le_bytes());
+            part.extend(0u32.to_le_bytes());
+            part.extend(flags.to_le_bytes());
+            part.extend([0u8; 8]);
+            part
+        };
+        [part(0xa000_0000), part(0x8000_0000)].concat()
+    }
+
+    fn id3v1() -> Vec<u8> {
+        let mut tag = b"TAG".to_vec();
+        tag.extend([b' '; 125]);
+        tag
+    }
+
+    #[test]
+    fn tags_around_the_audio_are_left_out() {
+        assert_eq!(
+            payload(&[&id3v2(20), b"AUDIO", &ape_tag(), &id3v1()]),
+            Some(30..35)
+        );
+        assert_eq!(payload(&[b"AUDIO"]), Some(0..5));
+        assert_eq!(payload(&[&id3v2(40), b"AUDIO"]), Some(50..55));
+        assert_eq!(payload(&[&id3v2(20)]), None);
+    }
+
+    #[test]
+    fn flac_metadata_blocks_are_left_out() {
+        assert_eq!(
+            payload(&[
+                b"fLaC",
+                &[0x00, 0, 0, 34],
+                &[0u8; 34],
+                &[0x84, 0, 0, 6],
+                b"vorbis",
+                b"FRAMES",
+            ]),
+            Some(52..58)
+        );
+    }
+
+    #[test]
+    fn values_are_split_on_separators_and_null_bytes() {
+        let separators = vec![";".to_string(), " feat. ".to_string(), "".to_string()];
+        let values = vec![
+            "Alpha; Beta feat. Gamma".to_string(),
+            "Delta\0Alpha\0".to_string(),
+        ];
+        assert_eq!(
+            split_values(&values, &separators),
+            vec!["Alpha", "Beta", "Gamma", "Delta"]
+        );
+        assert_eq!(split_values(&["AC/DC".to_string()], &[]), vec!["AC/DC"]);
+        assert!(split_values(&[" ; ".to_string()], &separators).is_empty());
+
+        // Titles and albums only keep their first value
+        assert_eq!(first_value("Title\0Other Title"), "Title");
+        assert_eq!(first_value("\0Album"), "Album");
+        assert_eq!(first_value("Album; Part 2"), "Album; Part 2");
+        assert_eq!(first_value(""), "");
+    }
+
+    #[test]
+    fn replay_gain_values_are_read_without_their_unit() {
+        assert_eq!(gain_value(Some("-6.48 dB".to_string())), Some(-6.48));
+        assert_eq!(gain_value(Some("+1.5dB".to_string())), Some(1.5));
+        assert_eq!(gain_value(Some(" +2.25 dB".to_string())), Some(2.25));
+        assert_eq!(gain_value(Some("0.988553".to_string())), Some(0.988553));
+        assert_eq!(gain_value(Some("inf".to_string())), None);
+        assert_eq!(gain_value(Some("".to_string())), None);
+        assert_eq!(gain_value(None), None);
+
+        let tags = ReplayGainTags::from_values(|name| match name {
+            "REPLAYGAIN_TRACK_GAIN" => Some("-3.10 dB".to_string()),
+            "REPLAYGAIN_ALBUM_PEAK" => Some("1.000000".to_string()),
+            _ => None,
+        });
+        assert_eq!(tags.track_gain, Some(-3.1));
+        assert_eq!(tags.track_peak, None);
+        assert_eq!(tags.album_gain, None);
+        assert_eq!(tags.album_peak, Some(1.0));
+    }
+}
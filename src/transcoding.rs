@@ -0,0 +1,417 @@
+use std::fs::File;
+use std::path::Path;
+use std::process::Stdio;
+
+use axum::body::Body;
+use entities::song::Song;
+use log::{error, info, warn};
+use serde::Deserialize;
+use symphonia::core::audio::{SampleBuffer, SignalSpec};
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::Time;
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio::process::Command;
+use tokio::runtime::Handle;
+use tokio_util::io::ReaderStream;
+
+const WAV_CONTENT_TYPE: &str = "audio/wav";
+
+/// A named transcoding profile from the configuration, e.g.
+/// `{"name": "flac→opus 128k", "from": ["flac"], "to": "opus", "content_type": "audio/ogg",
+///   "bitrate": 128, "command": ["ffmpeg", "-ss", "%t", "-i", "%s", "-b:a", "%bk", "-f", "opus", "-"]}`.
+/// In the command `%s` is replaced by the source path, `%b` by the bitrate in kbps, `%t` by the
+/// time offset in seconds, `%g` by the ReplayGain to apply in dB and `%%` by `%`. Profiles without
+/// a command are decoded in-process into WAV.
+#[derive(Deserialize, Clone, Debug)]
+pub struct TranscodingProfile {
+    pub name: String,
+    // Source suffixes this profile applies to, `*` matches anything
+    #[serde(default = "any_suffix")]
+    pub from: Vec<String>,
+    pub to: String,
+    #[serde(default = "wav_content_type")]
+    pub content_type: String,
+    #[serde(default)]
+    pub bitrate: u32,
+    #[serde(default)]
+    pub command: Option<Vec<String>>,
+    // Used when the client doesn't ask for a format, and reported as `transcodedSuffix`
+    #[serde(default)]
+    pub default: bool,
+    // ReplayGain applied by the server, for clients that can't do it themselves
+    #[serde(default)]
+    pub replay_gain: GainMode,
+}
+
+/// Which of a song's ReplayGain values a profile applies. Either falls back to the other when the
+/// song's tags only have one.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GainMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+}
+
+fn any_suffix() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+fn wav_content_type() -> String {
+    WAV_CONTENT_TYPE.to_string()
+}
+
+impl TranscodingProfile {
+    fn matches(&self, suffix: &str) -> bool {
+        self.from
+            .iter()
+            .any(|f| f == "*" || f.eq_ignore_ascii_case(suffix))
+    }
+
+    /// Whether the output follows `bitrate_for`. Decoding to WAV gives PCM at whatever rate the
+    /// source has.
+    pub fn encodes(&self) -> bool {
+        self.command.is_some()
+    }
+
+    /// The bitrate to encode at, capped by the client's `maxBitRate` (0 means no limit).
+    pub fn bitrate_for(&self, max_bit_rate: Option<u32>) -> u32 {
+        match max_bit_rate {
+            Some(max) if max > 0 && (self.bitrate == 0 || max < self.bitrate) => max,
+            _ => self.bitrate,
+        }
+    }
+
+    /// The gain in dB to apply to a song, lowered where needed so its peak doesn't clip.
+    pub fn gain_for(&self, song: &Song) -> f64 {
+        let track = song.track_gain.map(|g| (g, song.track_peak));
+        let album = song.album_gain.map(|g| (g, song.album_peak));
+        let chosen = match self.replay_gain {
+            GainMode::Off => None,
+            GainMode::Track => track.or(album),
+            GainMode::Album => album.or(track),
+        };
+        match chosen {
+            Some((gain, Some(peak))) if peak > 0.0 => gain.min(-20.0 * peak.log10()),
+            Some((gain, _)) => gain,
+            None => 0.0,
+        }
+    }
+}
+
+fn suffix_of(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+/// Picks the profile for a stream request. `format=raw` always streams the original file, an
+/// explicit format picks the first profile producing it, otherwise the default profile (if any)
+/// for the file's suffix is used.
+pub fn select<'a>(
+    profiles: &'a [TranscodingProfile],
+    path: &str,
+    format: Option<&str>,
+) -> Option<&'a TranscodingProfile> {
+    let suffix = suffix_of(path);
+    match format {
+        Some("raw") => None,
+        Some(f) if !f.is_empty() => profiles
+            .iter()
+            .find(|p| p.to.eq_ignore_ascii_case(f) && p.matches(&suffix)),
+        _ => default_profile(profiles, path),
+    }
+}
+
+pub fn default_profile<'a>(
+    profiles: &'a [TranscodingProfile],
+    path: &str,
+) -> Option<&'a TranscodingProfile> {
+    let suffix = suffix_of(path);
+    profiles.iter().find(|p| p.default && p.matches(&suffix))
+}
+
+/// A profile that can bring a song under a user's bitrate limit: of those that encode the file's
+/// suffix, the default one if there is one and the first otherwise.
+pub fn limiting_profile<'a>(
+    profiles: &'a [TranscodingProfile],
+    path: &str,
+) -> Option<&'a TranscodingProfile> {
+    let suffix = suffix_of(path);
+    let mut encoders = profiles
+        .iter()
+        .filter(|p| p.encodes() && p.matches(&suffix));
+    encoders
+        .clone()
+        .find(|p| p.default)
+        .or_else(|| encoders.next())
+}
+
+/// Starts transcoding and returns a body that streams the encoded audio as it's produced.
+pub fn transcode(
+    profile: &TranscodingProfile,
+    path: &str,
+    bitrate: u32,
+    time_offset: u32,
+    gain: f64,
+) -> Result<Body, String> {
+    match &profile.command {
+        Some(command) => run_command(command, path, bitrate, time_offset, gain),
+        None => Ok(decode_to_wav(path.to_string(), time_offset, gain)),
+    }
+}
+
+fn run_command(
+    command: &[String],
+    path: &str,
+    bitrate: u32,
+    time_offset: u32,
+    gain: f64,
+) -> Result<Body, String> {
+    let args: Vec<String> = command
+        .iter()
+        .map(|arg| substitute(arg, path, bitrate, time_offset, gain))
+        .collect();
+    if args.is_empty() {
+        return Err("Transcoding profile has an empty command".to_string());
+    }
+    info!("Running encoder: {}", args.join(" "));
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Error starting encoder {}: {}", args[0], e))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or("Encoder has no output".to_string())?;
+    // If the client goes away the encoder gets a broken pipe and exits, we just reap it
+    tokio::spawn(async move {
+        match child.wait().await {
+            Ok(status) if !status.success() => warn!("Encoder exited with {}", status),
+            Ok(_) => {}
+            Err(err) => error!("Error waiting for encoder: {}", err),
+        }
+    });
+    Ok(Body::from_stream(ReaderStream::new(stdout)))
+}
+
+/// Fills in the placeholders of a command argument in a single pass, so a path that itself
+/// contains `%b` or `%t` comes through untouched. `%%` is a literal `%`, anything else is kept.
+fn substitute(arg: &str, path: &str, bitrate: u32, time_offset: u32, gain: f64) -> String {
+    let mut ret = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => ret.push_str(path),
+            Some('b') => ret.push_str(&bitrate.to_string()),
+            Some('t') => ret.push_str(&time_offset.to_string()),
+            Some('g') => ret.push_str(&format!("{:.2}", gain)),
+            Some('%') => ret.push('%'),
+            Some(other) => {
+                ret.push('%');
+                ret.push(other);
+            }
+            None => ret.push('%'),
+        }
+    }
+    ret
+}
+
+fn decode_to_wav(path: String, time_offset: u32, gain: f64) -> Body {
+    let (writer, reader) = tokio::io::duplex(64 * 1024);
+    let handle = Handle::current();
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = write_wav(&path, time_offset, gain, writer, handle) {
+            error!("Error decoding {}: {}", path, err);
+        }
+    });
+    Body::from_stream(ReaderStream::new(reader))
+}
+
+fn write_wav(
+    path: &str,
+    time_offset: u32,
+    gain: f64,
+    mut writer: DuplexStream,
+    handle: Handle,
+) -> Result<(), String> {
+    let src = File::open(path).map_err(|e| e.to_string())?;
+    let media_source_stream = MediaSourceStream::new(Box::new(src), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(&suffix_of(path));
+    let metadata_opts: MetadataOptions = Default::default();
+    let format_opts: FormatOptions = Default::default();
+    let probed = symphonia::default::get_probe()
+        .format(&hint, media_source_stream, &format_opts, &metadata_opts)
+        .map_err(|e| e.to_string())?;
+    let mut format = probed.format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("No decodable track")?
+        .clone();
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| e.to_string())?;
+    if time_offset > 0 {
+        format
+            .seek(
+                SeekMode::Coarse,
+                SeekTo::Time {
+                    time: Time::from(time_offset),
+                    track_id: Some(track.id),
+                },
+            )
+            .map_err(|e| e.to_string())?;
+    }
+
+    let scale = 10f64.powf(gain / 20.0);
+    let mut header_written = false;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(p) => p,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        if packet.track_id() != track.id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(d) => d,
+            Err(SymphoniaError::DecodeError(e)) => {
+                warn!("Skipping undecodable packet in {}: {}", path, e);
+                continue;
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        let spec = *decoded.spec();
+        let mut out = Vec::new();
+        if !header_written {
+            out.extend_from_slice(&wav_header(&spec));
+            header_written = true;
+        }
+        let mut samples = SampleBuffer::<i16>::new(decoded.capacity() as u64, spec);
+        samples.copy_interleaved_ref(decoded);
+        for sample in samples.samples() {
+            let sample = if gain == 0.0 {
+                *sample
+            } else {
+                (*sample as f64 * scale)
+                    .round()
+                    .clamp(i16::MIN as f64, i16::MAX as f64) as i16
+            };
+            out.extend_from_slice(&sample.to_le_bytes());
+        }
+        if handle.block_on(writer.write_all(&out)).is_err() {
+            // The client stopped listening
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// A 16 bit PCM header with unknown lengths, which players accept for streamed WAV.
+fn wav_header(spec: &SignalSpec) -> Vec<u8> {
+    let channels = spec.channels.count() as u16;
+    let sample_rate = spec.rate;
+    let block_align = channels * 2;
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_in_the_path_are_not_replaced() {
+        assert_eq!(
+            substitute("%s", "/music/100%b %t%g.flac", 128, 30, -6.5),
+            "/music/100%b %t%g.flac"
+        );
+        assert_eq!(
+            substitute("-b:a %bk -ss %t -af volume=%gdB 100%%", "", 128, 30, -6.5),
+            "-b:a 128k -ss 30 -af volume=-6.50dB 100%"
+        );
+        assert_eq!(substitute("%x %", "", 0, 0, 0.0), "%x %");
+    }
+
+    #[tokio::test]
+    async fn the_command_output_is_streamed() {
+        // Prints every argument it gets on its own line
+        let command: Vec<String> = [
+            "sh",
+            "-c",
+            r#"for arg in "$@"; do echo "$arg"; done"#,
+            "stub",
+            "-i",
+            "%s",
+            "%bk",
+            "%t",
+            "%g",
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        let body = run_command(&command, "/music/50%b off.flac", 96, 12, 1.5).unwrap();
+        let output = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output.to_vec()).unwrap(),
+            "-i\n/music/50%b off.flac\n96k\n12\n1.50\n"
+        );
+    }
+
+    #[test]
+    fn only_encoding_profiles_limit_the_bitrate() {
+        let profile = |name: &str, from: &str, command: bool, default: bool| TranscodingProfile {
+            name: name.to_string(),
+            from: vec![from.to_string()],
+            to: name.to_string(),
+            content_type: WAV_CONTENT_TYPE.to_string(),
+            bitrate: 0,
+            command: command.then(|| vec!["encoder".to_string()]),
+            default,
+            replay_gain: GainMode::Off,
+        };
+        let profiles = [
+            profile("wav", "*", false, true),
+            profile("mp3", "mp3", true, false),
+            profile("opus", "flac", true, false),
+            profile("aac", "flac", true, true),
+        ];
+        let name = |path: &str| limiting_profile(&profiles, path).map(|p| p.name.as_str());
+        assert_eq!(name("/music/a.flac"), Some("aac"));
+        assert_eq!(name("/music/a.mp3"), Some("mp3"));
+        assert_eq!(name("/music/a.ogg"), None);
+    }
+}
@@ -0,0 +1,325 @@
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use entities::user::User;
+use log::{error, info};
+use serde::Deserialize;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth_middleware::decode_password;
+use crate::responses::format::ResponseFormat;
+use crate::responses::responses::{EmptyResponse, ErrorResponse, SubsonicResponse};
+use crate::responses::user_response::{UserResponse, UsersResponse};
+use crate::DatabaseState;
+
+#[derive(Deserialize)]
+pub struct UsernameQuery {
+    username: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordQuery {
+    username: String,
+    password: String,
+}
+
+/// Parameters of `createUser` and `updateUser`, anything left out keeps its current (or default)
+/// value.
+#[derive(Deserialize)]
+pub struct UserQuery {
+    username: String,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(rename = "adminRole", default)]
+    admin_role: Option<bool>,
+    #[serde(rename = "settingsRole", default)]
+    settings_role: Option<bool>,
+    #[serde(rename = "downloadRole", default)]
+    download_role: Option<bool>,
+    #[serde(rename = "uploadRole", default)]
+    upload_role: Option<bool>,
+    #[serde(rename = "playlistRole", default)]
+    playlist_role: Option<bool>,
+    #[serde(rename = "coverArtRole", default)]
+    cover_art_role: Option<bool>,
+    #[serde(rename = "commentRole", default)]
+    comment_role: Option<bool>,
+    #[serde(rename = "podcastRole", default)]
+    podcast_role: Option<bool>,
+    #[serde(rename = "shareRole", default)]
+    share_role: Option<bool>,
+    #[serde(rename = "streamRole", default)]
+    stream_role: Option<bool>,
+    #[serde(rename = "maxBitRate", default)]
+    max_bit_rate: Option<i32>,
+}
+
+impl UserQuery {
+    fn apply(&self, user: &mut User) {
+        if self.email.is_some() {
+            user.email = self.email.to_owned();
+        }
+        user.admin_role = self.admin_role.unwrap_or(user.admin_role);
+        user.settings_role = self.settings_role.unwrap_or(user.settings_role);
+        user.download_role = self.download_role.unwrap_or(user.download_role);
+        user.upload_role = self.upload_role.unwrap_or(user.upload_role);
+        user.playlist_role = self.playlist_role.unwrap_or(user.playlist_role);
+        user.cover_art_role = self.cover_art_role.unwrap_or(user.cover_art_role);
+        user.comment_role = self.comment_role.unwrap_or(user.comment_role);
+        user.podcast_role = self.podcast_role.unwrap_or(user.podcast_role);
+        user.share_role = self.share_role.unwrap_or(user.share_role);
+        user.stream_role = self.stream_role.unwrap_or(user.stream_role);
+        user.max_bit_rate = self.max_bit_rate.unwrap_or(user.max_bit_rate).max(0);
+    }
+}
+
+/// A user with the default roles Subsonic gives new users, plus cover art which `getCoverArt` needs.
+pub fn new_user(username: String, password: String) -> User {
+    User {
+        id: Uuid::nil(),
+        username,
+        password,
+        email: None,
+        admin_role: false,
+        settings_role: true,
+        download_role: false,
+        upload_role: false,
+        playlist_role: false,
+        cover_art_role: true,
+        comment_role: false,
+        podcast_role: false,
+        share_role: false,
+        stream_role: true,
+        max_bit_rate: 0,
+    }
+}
+
+async fn store_password(
+    state: &DatabaseState,
+    conn: &mut PgConnection,
+    user: &User,
+    password: &str,
+) -> Result<(), String> {
+    let encrypted = state.cipher.encrypt(password)?;
+    queries::update_user_password(conn, user.id, &encrypted)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+fn error_response(format: &ResponseFormat, code: i32, message: &str) -> Response {
+    let ret: SubsonicResponse<ErrorResponse> =
+        SubsonicResponse::from_error_code(code, message.to_string());
+    ret.render(format)
+}
+
+fn not_admin(format: &ResponseFormat) -> Response {
+    error_response(format, 50, "user is not authorized for the given operation")
+}
+
+fn missing(format: &ResponseFormat, parameter: &str) -> Response {
+    error_response(
+        format,
+        10,
+        &format!(r#"required parameter "{}" is missing"#, parameter),
+    )
+}
+
+/// Fetches the user named in a request, answering with the Subsonic error when that's not possible.
+async fn find_user(
+    state: &DatabaseState,
+    format: &ResponseFormat,
+    username: &String,
+) -> Result<User, Response> {
+    match queries::get_user_by_username(&state.pool, username).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(error_response(format, 70, "user not found")),
+        Err(err) => {
+            error!("Error fetching user {}: {}", username, err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+pub async fn get_user(
+    State(state): State<DatabaseState>,
+    Extension(current): Extension<User>,
+    format: ResponseFormat,
+    query_option: Option<Query<UsernameQuery>>,
+) -> Response {
+    let username = match query_option {
+        Some(Query(q)) => q.username,
+        None => return missing(&format, "username"),
+    };
+    // Everyone can look themselves up, only admins can see other users
+    if username != current.username && !current.admin_role {
+        return not_admin(&format);
+    }
+    match find_user(&state, &format, &username).await {
+        Ok(user) => SubsonicResponse::<UserResponse>::from_user(&user).render(&format),
+        Err(response) => response,
+    }
+}
+
+pub async fn get_users(State(state): State<DatabaseState>, format: ResponseFormat) -> Response {
+    match queries::get_users(&state.pool).await {
+        Ok(users) => SubsonicResponse::<UsersResponse>::from_user_list(&users).render(&format),
+        Err(err) => {
+            error!("Error fetching users: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn create_user(
+    State(state): State<DatabaseState>,
+    Extension(current): Extension<User>,
+    format: ResponseFormat,
+    query_option: Option<Query<UserQuery>>,
+) -> Response {
+    let query = match query_option {
+        Some(Query(q)) => q,
+        None => return missing(&format, "username"),
+    };
+    let password = match query.password.as_deref().and_then(decode_password) {
+        Some(p) if !p.is_empty() => p,
+        _ => return missing(&format, "password"),
+    };
+    match queries::get_user_by_username(&state.pool, &query.username).await {
+        Ok(None) => {}
+        Ok(Some(_)) => return error_response(&format, 0, "user already exists"),
+        Err(err) => {
+            error!("Error fetching user {}: {}", query.username, err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+    let encrypted = match state.cipher.encrypt(&password) {
+        Ok(e) => e,
+        Err(err) => {
+            error!("{}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let mut user = new_user(query.username.to_owned(), encrypted);
+    query.apply(&mut user);
+    if let Err(err) = queries::add_user(&state.pool, &user).await {
+        error!("Error creating user {}: {}", user.username, err);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    info!("User {} created by {}", user.username, current.username);
+    SubsonicResponse::<EmptyResponse>::ok().render(&format)
+}
+
+pub async fn update_user(
+    State(state): State<DatabaseState>,
+    Extension(current): Extension<User>,
+    format: ResponseFormat,
+    query_option: Option<Query<UserQuery>>,
+) -> Response {
+    let query = match query_option {
+        Some(Query(q)) => q,
+        None => return missing(&format, "username"),
+    };
+    // Checked before anything is written, so a bad password leaves the user as it was
+    let password = match query.password.as_deref().map(decode_password) {
+        None => None,
+        Some(Some(p)) if !p.is_empty() => Some(p),
+        Some(_) => return missing(&format, "password"),
+    };
+    let mut user = match find_user(&state, &format, &query.username).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+    query.apply(&mut user);
+    if user.id == current.id && !user.admin_role {
+        // Otherwise the last admin can lock everyone out of user management
+        return error_response(&format, 0, "you can't revoke your own admin role");
+    }
+    let saved = async {
+        let mut tx = state.pool.begin().await.map_err(|e| e.to_string())?;
+        queries::update_user(&mut tx, &user)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Some(password) = &password {
+            store_password(&state, &mut tx, &user, password).await?;
+        }
+        tx.commit().await.map_err(|e| e.to_string())
+    }
+    .await;
+    if let Err(err) = saved {
+        error!("Error updating user {}: {}", user.username, err);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    info!("User {} updated by {}", user.username, current.username);
+    SubsonicResponse::<EmptyResponse>::ok().render(&format)
+}
+
+pub async fn delete_user(
+    State(state): State<DatabaseState>,
+    Extension(current): Extension<User>,
+    format: ResponseFormat,
+    query_option: Option<Query<UsernameQuery>>,
+) -> Response {
+    let username = match query_option {
+        Some(Query(q)) => q.username,
+        None => return missing(&format, "username"),
+    };
+    let user = match find_user(&state, &format, &username).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+    if user.id == current.id {
+        return error_response(&format, 0, "you can't delete yourself");
+    }
+    if let Err(err) = queries::delete_user_by_id(&state.pool, user.id).await {
+        error!("Error deleting user {}: {}", username, err);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    info!("User {} deleted by {}", username, current.username);
+    SubsonicResponse::<EmptyResponse>::ok().render(&format)
+}
+
+pub async fn change_password(
+    State(state): State<DatabaseState>,
+    Extension(current): Extension<User>,
+    format: ResponseFormat,
+    query_option: Option<Query<ChangePasswordQuery>>,
+) -> Response {
+    let query = match query_option {
+        Some(Query(q)) => q,
+        None => return missing(&format, "password"),
+    };
+    // Changing your own password is a personal setting, anyone else's needs an admin
+    let allowed = if query.username == current.username {
+        current.settings_role || current.admin_role
+    } else {
+        current.admin_role
+    };
+    if !allowed {
+        return not_admin(&format);
+    }
+    let password = match decode_password(&query.password) {
+        Some(p) if !p.is_empty() => p,
+        _ => return missing(&format, "password"),
+    };
+    let user = match find_user(&state, &format, &query.username).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+    let stored = match state.pool.acquire().await {
+        Ok(mut conn) => store_password(&state, &mut conn, &user, &password).await,
+        Err(err) => Err(err.to_string()),
+    };
+    if let Err(err) = stored {
+        error!("Error updating password of {}: {}", user.username, err);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    info!(
+        "Password of {} changed by {}",
+        user.username, current.username
+    );
+    SubsonicResponse::<EmptyResponse>::ok().render(&format)
+}
@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{error, info, warn};
+use notify::event::EventKind;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::time::{sleep, timeout};
+
+use crate::scan::{self, ScanKind};
+use crate::DatabaseState;
+
+// How long to wait before trying again when a full scan is running
+const BUSY_RETRY: Duration = Duration::from_secs(5);
+
+/// Watches the music folders and syncs whatever changes in it, once things have been quiet for
+/// `watch_debounce_ms` so copying a whole album in results in a single update.
+pub fn start(state: DatabaseState) -> Result<(), String> {
+    let (sender, receiver) = unbounded_channel();
+    let mut watcher = RecommendedWatcher::new(
+        move |event: notify::Result<Event>| {
+            // Fails only when the receiving task is gone, which means we're shutting down
+            let _ = sender.send(event);
+        },
+        notify::Config::default(),
+    )
+    .map_err(|e| format!("Error starting the file watcher: {}", e))?;
+    for folder in &state.config.music_folders {
+        watcher
+            .watch(Path::new(&folder.path), RecursiveMode::Recursive)
+            .map_err(|e| format!("Error watching {}: {}", folder.path, e))?;
+        info!("Watching {} ({}) for changes", folder.path, folder.name);
+    }
+    tokio::spawn(async move {
+        // The watcher stops when dropped, so it lives as long as the task
+        let _watcher = watcher;
+        run(state, receiver).await;
+    });
+    Ok(())
+}
+
+async fn run(state: DatabaseState, mut receiver: UnboundedReceiver<notify::Result<Event>>) {
+    let debounce = Duration::from_millis(state.config.watch_debounce_ms);
+    let mut pending: HashSet<PathBuf> = HashSet::new();
+    loop {
+        let event = if pending.is_empty() {
+            receiver.recv().await
+        } else {
+            match timeout(debounce, receiver.recv()).await {
+                Ok(event) => event,
+                Err(_) => {
+                    // Quiet for long enough, sync what piled up
+                    sync_pending(&state, &mut pending).await;
+                    continue;
+                }
+            }
+        };
+        match event {
+            Some(Ok(event)) => {
+                if relevant(&event.kind) {
+                    pending.extend(event.paths);
+                }
+            }
+            Some(Err(err)) => error!("File watcher error: {}", err),
+            None => {
+                warn!("File watcher stopped");
+                return;
+            }
+        }
+    }
+}
+
+fn relevant(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) | EventKind::Any
+    )
+}
+
+async fn sync_pending(state: &DatabaseState, pending: &mut HashSet<PathBuf>) {
+    let counters = match state.scan.try_begin(&state.pool, ScanKind::Watch).await {
+        Ok(Some(c)) => c,
+        Ok(None) => {
+            // A full scan will most likely pick these up too, but we check again once it's done
+            sleep(BUSY_RETRY).await;
+            return;
+        }
+        Err(err) => {
+            error!("{}", err);
+            sleep(BUSY_RETRY).await;
+            return;
+        }
+    };
+    let paths: Vec<PathBuf> = pending.drain().collect();
+    info!("Syncing {} changed paths", paths.len());
+    let result = scan::sync_paths(&state.pool, &state.config, &paths, &counters).await;
+    if let Err(err) = &result {
+        error!("Error syncing changed files: {}", err);
+    }
+    state.scan.finish(&result);
+}
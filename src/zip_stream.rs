@@ -0,0 +1,284 @@
+use axum::body::Body;
+use chrono::{DateTime, Datelike, Local, Timelike};
+use crc32fast::Hasher;
+use log::{error, info};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio_util::io::ReaderStream;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const ZIP64_END_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const END_SIGNATURE: u32 = 0x06054b50;
+// Sizes and CRC come after the data, names are UTF-8
+const FLAGS: u16 = 0x0008 | 0x0800;
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+/// A file that goes into the archive under `name`.
+pub struct ZipEntry {
+    pub name: String,
+    pub path: String,
+}
+
+struct CentralRecord {
+    name: String,
+    crc: u32,
+    size: u64,
+    offset: u64,
+    time: u16,
+    date: u16,
+}
+
+/// Streams a stored (uncompressed, audio doesn't shrink anyway) zip archive of the given files
+/// as it's being built, so nothing is written to disk and memory use doesn't depend on its size.
+pub fn zip_body(entries: Vec<ZipEntry>) -> Body {
+    let (writer, reader) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        if let Err(err) = write_zip(entries, writer).await {
+            // Most likely the client went away
+            info!("Zip stream stopped: {}", err);
+        }
+    });
+    Body::from_stream(ReaderStream::new(reader))
+}
+
+async fn write_zip(entries: Vec<ZipEntry>, mut writer: DuplexStream) -> std::io::Result<()> {
+    let mut offset: u64 = 0;
+    let mut records: Vec<CentralRecord> = Vec::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    for entry in entries {
+        let mut file = match tokio::fs::File::open(&entry.path).await {
+            Ok(f) => f,
+            Err(err) => {
+                error!("Skipping {} in zip: {}", entry.path, err);
+                continue;
+            }
+        };
+        let metadata = file.metadata().await.ok();
+        let modified: DateTime<Local> = match metadata.as_ref().map(|m| m.modified()) {
+            Some(Ok(m)) => DateTime::from(m),
+            _ => Local::now(),
+        };
+        let (time, date) = dos_date_time(modified);
+        // Readers only expect 8 byte sizes in the data descriptor when the local header says so
+        let zip64 = metadata.is_none_or(|m| m.len() >= u32::MAX as u64);
+
+        let header = local_header(&entry.name, time, date, zip64);
+        writer.write_all(&header).await?;
+
+        let mut hasher = Hasher::new();
+        let mut size: u64 = 0;
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            writer.write_all(&buffer[..read]).await?;
+            size += read as u64;
+            if !zip64 && size >= u32::MAX as u64 {
+                // Too late to say so in the header, the archive can't be read past this point
+                return Err(std::io::Error::other(format!(
+                    "{} grew past 4 GiB while being zipped",
+                    entry.path
+                )));
+            }
+        }
+        let crc = hasher.finalize();
+
+        let descriptor = data_descriptor(crc, size, zip64);
+        writer.write_all(&descriptor).await?;
+
+        records.push(CentralRecord {
+            name: entry.name,
+            crc,
+            size,
+            offset,
+            time,
+            date,
+        });
+        offset += header.len() as u64 + size + descriptor.len() as u64;
+    }
+
+    let central_offset = offset;
+    let mut central = Vec::new();
+    for record in &records {
+        let mut zip64_extra = Vec::new();
+        if record.size >= u32::MAX as u64 {
+            put_u64(&mut zip64_extra, record.size);
+            put_u64(&mut zip64_extra, record.size);
+        }
+        if record.offset >= u32::MAX as u64 {
+            put_u64(&mut zip64_extra, record.offset);
+        }
+        let mut extra = Vec::new();
+        if !zip64_extra.is_empty() {
+            put_u16(&mut extra, ZIP64_EXTRA_ID);
+            put_u16(&mut extra, zip64_extra.len() as u16);
+            extra.extend_from_slice(&zip64_extra);
+        }
+        let size32 = record.size.min(u32::MAX as u64) as u32;
+        put_u32(&mut central, CENTRAL_HEADER_SIGNATURE);
+        put_u16(&mut central, VERSION_ZIP64);
+        put_u16(
+            &mut central,
+            if extra.is_empty() {
+                VERSION
+            } else {
+                VERSION_ZIP64
+            },
+        );
+        put_u16(&mut central, FLAGS);
+        put_u16(&mut central, 0);
+        put_u16(&mut central, record.time);
+        put_u16(&mut central, record.date);
+        put_u32(&mut central, record.crc);
+        put_u32(&mut central, size32);
+        put_u32(&mut central, size32);
+        put_u16(&mut central, record.name.len() as u16);
+        put_u16(&mut central, extra.len() as u16);
+        put_u16(&mut central, 0);
+        put_u16(&mut central, 0);
+        put_u16(&mut central, 0);
+        put_u32(&mut central, 0);
+        put_u32(&mut central, record.offset.min(u32::MAX as u64) as u32);
+        central.extend_from_slice(record.name.as_bytes());
+        central.extend_from_slice(&extra);
+    }
+    let central_size = central.len() as u64;
+    let count = records.len() as u64;
+
+    let needs_zip64 = count >= u16::MAX as u64
+        || central_size >= u32::MAX as u64
+        || central_offset >= u32::MAX as u64;
+    if needs_zip64 {
+        let zip64_end_offset = central_offset + central_size;
+        put_u32(&mut central, ZIP64_END_SIGNATURE);
+        put_u64(&mut central, 44);
+        put_u16(&mut central, VERSION_ZIP64);
+        put_u16(&mut central, VERSION_ZIP64);
+        put_u32(&mut central, 0);
+        put_u32(&mut central, 0);
+        put_u64(&mut central, count);
+        put_u64(&mut central, count);
+        put_u64(&mut central, central_size);
+        put_u64(&mut central, central_offset);
+
+        put_u32(&mut central, ZIP64_LOCATOR_SIGNATURE);
+        put_u32(&mut central, 0);
+        put_u64(&mut central, zip64_end_offset);
+        put_u32(&mut central, 1);
+    }
+    put_u32(&mut central, END_SIGNATURE);
+    put_u16(&mut central, 0);
+    put_u16(&mut central, 0);
+    put_u16(&mut central, count.min(u16::MAX as u64) as u16);
+    put_u16(&mut central, count.min(u16::MAX as u64) as u16);
+    put_u32(&mut central, central_size.min(u32::MAX as u64) as u32);
+    put_u32(&mut central, central_offset.min(u32::MAX as u64) as u32);
+    put_u16(&mut central, 0);
+    writer.write_all(&central).await?;
+    writer.shutdown().await
+}
+
+/// The header before an entry's data. Its sizes and CRC come in the data descriptor, for an entry
+/// that may not fit in 4 GiB it has the ZIP64 extra field with 0xFFFFFFFF sizes (APPNOTE 4.3.9.2).
+fn local_header(name: &str, time: u16, date: u16, zip64: bool) -> Vec<u8> {
+    let mut extra = Vec::new();
+    if zip64 {
+        put_u16(&mut extra, ZIP64_EXTRA_ID);
+        put_u16(&mut extra, 16);
+        put_u64(&mut extra, 0);
+        put_u64(&mut extra, 0);
+    }
+    let sizes = if zip64 { u32::MAX } else { 0 };
+    let mut header = Vec::with_capacity(30 + name.len() + extra.len());
+    put_u32(&mut header, LOCAL_HEADER_SIGNATURE);
+    put_u16(&mut header, VERSION_ZIP64);
+    put_u16(&mut header, FLAGS);
+    put_u16(&mut header, 0);
+    put_u16(&mut header, time);
+    put_u16(&mut header, date);
+    put_u32(&mut header, 0);
+    put_u32(&mut header, sizes);
+    put_u32(&mut header, sizes);
+    put_u16(&mut header, name.len() as u16);
+    put_u16(&mut header, extra.len() as u16);
+    header.extend_from_slice(name.as_bytes());
+    header.extend_from_slice(&extra);
+    header
+}
+
+fn data_descriptor(crc: u32, size: u64, zip64: bool) -> Vec<u8> {
+    let mut descriptor = Vec::with_capacity(24);
+    put_u32(&mut descriptor, DATA_DESCRIPTOR_SIGNATURE);
+    put_u32(&mut descriptor, crc);
+    if zip64 {
+        put_u64(&mut descriptor, size);
+        put_u64(&mut descriptor, size);
+    } else {
+        put_u32(&mut descriptor, size as u32);
+        put_u32(&mut descriptor, size as u32);
+    }
+    descriptor
+}
+
+fn dos_date_time(date_time: DateTime<Local>) -> (u16, u16) {
+    // DOS dates start in 1980
+    let year = (date_time.year().clamp(1980, 2107) - 1980) as u16;
+    let time = ((date_time.hour() as u16) << 11)
+        | ((date_time.minute() as u16) << 5)
+        | (date_time.second() as u16 / 2);
+    let date = (year << 9) | ((date_time.month() as u16) << 5) | date_time.day() as u16;
+    (time, date)
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn large_entries_have_a_zip64_local_header() {
+        let header = local_header("a.flac", 0, 0, true);
+        assert_eq!(u32_at(&header, 18), u32::MAX);
+        assert_eq!(u32_at(&header, 22), u32::MAX);
+        assert_eq!(u16_at(&header, 28), 20);
+        assert_eq!(&header[30..36], b"a.flac");
+        assert_eq!(u16_at(&header, 36), ZIP64_EXTRA_ID);
+        assert_eq!(u16_at(&header, 38), 16);
+        assert_eq!(header.len(), 56);
+        assert_eq!(data_descriptor(0, 5, true).len(), 24);
+    }
+
+    #[test]
+    fn small_entries_have_a_plain_local_header() {
+        let header = local_header("a.flac", 0, 0, false);
+        assert_eq!(u32_at(&header, 18), 0);
+        assert_eq!(u16_at(&header, 28), 0);
+        assert_eq!(header.len(), 36);
+        assert_eq!(data_descriptor(0, 5, false).len(), 16);
+    }
+}